@@ -0,0 +1,21 @@
+//! Regenerate the C header `include/nickel_jl.h` from the exported functions,
+//! `NativeBuffer` and the binary protocol constants in `src/lib.rs`.
+
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Unable to read cbindgen.toml");
+
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Unable to generate C bindings")
+        .write_to_file(crate_dir.join("include").join("nickel_jl.h"));
+}
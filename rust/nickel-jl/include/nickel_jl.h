@@ -0,0 +1,1653 @@
+#ifndef NICKEL_JL_H
+#define NICKEL_JL_H
+
+/* Generated by build.rs with cbindgen; do not edit by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define TYPE_NULL 0
+
+#define TYPE_BOOL 1
+
+#define TYPE_INT 2
+
+#define TYPE_FLOAT 3
+
+#define TYPE_STRING 4
+
+#define TYPE_ARRAY 5
+
+#define TYPE_RECORD 6
+
+#define TYPE_ENUM 7
+
+#define TYPE_DATE 8
+
+#define TYPE_MISSING 9
+
+#define TYPE_FUNCTION 10
+
+#define TYPE_PACKED_ARRAY 11
+
+#define TYPE_KEYED_RECORD 12
+
+#define TYPE_QUANTITY 13
+
+#define TYPE_UNSUPPORTED 14
+
+#define TYPE_FLOAT32 15
+
+#define PROTOCOL_VARINT 128
+
+#define PROTOCOL_PACKED 129
+
+#define PROTOCOL_CHECKED 130
+
+#define SHAPE_ERROR 255
+
+/**
+ * Iterator over the elements of an evaluated Nickel array.
+ *
+ * Opaque to C callers; created by `nickel_eval_array_open` and released with
+ * `nickel_array_free`.
+ */
+typedef struct NickelArrayIter NickelArrayIter;
+
+/**
+ * An evaluator with its own error slot and import paths.
+ *
+ * Opaque to C callers; created by `nickel_evaluator_new` and released with
+ * `nickel_evaluator_free`. Unlike the free functions, which report errors through
+ * thread-local state, an evaluator keeps everything it needs to itself, so each
+ * task can own one regardless of the thread it runs on. Thread-local settings
+ * such as the prelude, the fuel limit, the progress callback, colored diagnostics
+ * and the JSON size cap don't apply, and its errors leave `nickel_get_error_position`
+ * and `nickel_get_contract_failure` untouched. An evaluator may move between
+ * threads but must not be used by two threads at once.
+ */
+typedef struct NickelEvaluator NickelEvaluator;
+
+/**
+ * A Nickel function applied to some of its arguments.
+ *
+ * Opaque to C callers; created by `nickel_partial_apply`, extended with
+ * `nickel_partial_apply_more`, evaluated with `nickel_partial_force` and released
+ * with `nickel_partial_free`. The handle keeps the function applied to the
+ * arguments so far, evaluated to weak head normal form, along with the virtual
+ * machine holding its environment. Each call only applies the new arguments to
+ * it, so neither the function nor earlier arguments are evaluated again.
+ */
+typedef struct NickelPartial NickelPartial;
+
+/**
+ * A parsed Nickel program that can be evaluated repeatedly.
+ *
+ * Opaque to C callers; created by `nickel_program_new` and released with
+ * `nickel_program_free`. The parsed and typechecked source is cached, while the
+ * evaluation cache is reset before every run, so evaluating the same handle
+ * again is idempotent.
+ *
+ * Overrides set with `nickel_program_set_override` are kept by field path, each
+ * with the source of its value, which is parsed when the override is set. A
+ * `Program` can only accumulate overrides, so the handle drives the virtual
+ * machine itself and merges the current set into the cached program on every
+ * evaluation, without parsing the program again.
+ */
+typedef struct NickelProgram NickelProgram;
+
+/**
+ * Nickel source assembled from chunks of bytes.
+ *
+ * Opaque to C callers; created by `nickel_source_new`, filled with
+ * `nickel_source_push` and released with `nickel_source_free`. Chunks are kept as
+ * raw bytes and only decoded as UTF-8 once the source is evaluated, so a chunk
+ * boundary may fall inside a multibyte character.
+ */
+typedef struct NickelSource NickelSource;
+
+/**
+ * Result buffer for native evaluation
+ */
+typedef struct {
+  uint8_t *data;
+  uintptr_t len;
+} NativeBuffer;
+
+/**
+ * Callback returning the source text of the file imported at `path`, or NULL if
+ * it doesn't know it. See `nickel_set_import_resolver`.
+ */
+typedef const char *(*NickelImportResolver)(const char *path);
+
+/**
+ * Callback receiving the number of steps taken so far. See
+ * `nickel_set_progress_callback`.
+ */
+typedef void (*NickelProgressCallback)(uint64_t forced);
+
+/**
+ * Evaluate a Nickel code string and return the result as a JSON string.
+ *
+ * A bare enum `'Foo` is written as the string `"Foo"`, as `nickel export` does. An
+ * enum variant carrying an argument, like `'Error "oops"`, has no plain JSON form
+ * and fails with an error naming its location; `nickel_eval_json_tagged` encodes
+ * both kinds as objects instead.
+ *
+ * Nickel numbers are exact rationals and don't remember how they were written, so
+ * `2` and `2.0` are the same number. Numbers with a denominator of 1 are written
+ * as JSON integers, without a decimal point or exponent, as long as they fit in an
+ * `i128`; other numbers are written as decimals. Integers beyond 2^53, which many
+ * JSON parsers read as doubles and round, are also reported through
+ * `nickel_get_warnings`.
+ *
+ * Empty or whitespace-only code fails with the error `Empty input: no Nickel code
+ * to evaluate` rather than a parse error; this holds for every entry point taking
+ * code.
+ *
+ * A panic inside Nickel is caught and reported as an error rather than unwinding
+ * into the caller.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_string(const char *code);
+
+/**
+ * Evaluate Nickel code and return the JSON result encoded as UTF-16LE.
+ *
+ * The buffer holds `len / 2` little-endian code units, with no byte order mark
+ * and no terminating null.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_string_utf16(const char *code);
+
+/**
+ * Evaluate Nickel code and return the result as minified JSON.
+ *
+ * Same as `nickel_eval_string`, but without indentation or newlines, for
+ * shipping results over the wire.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_json_compact(const char *code);
+
+/**
+ * Evaluate Nickel code and return the result as JSON, along with where each value
+ * comes from in the source.
+ *
+ * The result is an object `{"value": ..., "$spans": {...}}`, where `value` is the
+ * result of `nickel_eval_string` and `$spans` maps the JSON Pointer (RFC 6901) of
+ * each record field and array element of the first two levels, like `/server` or
+ * `/server/ports`, to `{"file": ..., "start": ..., "end": ...}`. The span is that
+ * of the expression that produced the value, in bytes; a computed value may point
+ * into the standard library. Values without a known position are left out.
+ * Offsets into the code string are shifted by the bindings inserted on its first
+ * line when a prelude or `env` variables are set.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_json_with_spans(const char *code);
+
+/**
+ * Evaluate Nickel code and return the result as JSON indented by `spaces` spaces.
+ *
+ * `nickel_eval_string` always indents by 2 spaces; this lets the output match
+ * another formatter's style. With `spaces` 0 the output is minified, as with
+ * `nickel_eval_json_compact`.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_json_indent(const char *code, uint32_t spaces);
+
+/**
+ * Evaluate Nickel code to JSON and also return a 64-bit hash of it.
+ *
+ * The JSON is the same as from `nickel_eval_string`, whose records are always
+ * written with their fields sorted, so identical results give identical bytes.
+ * The hash is the 64-bit FNV-1a hash of those bytes, without the terminating null,
+ * and is stable across runs, platforms and library versions that produce the same
+ * JSON. It's meant for cache keys, not for security.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out_json` must be valid for writes; on success the string written there must
+ *   be freed with `nickel_free_string`, on error it is set to NULL
+ * - `out_hash` must be valid for writes
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_eval_json_hashed(const char *code, const char **out_json, uint64_t *out_hash);
+
+/**
+ * Evaluate Nickel code and return the changes from a previous result as a JSON
+ * Patch (RFC 6902).
+ *
+ * `previous_json` is typically an earlier result of `nickel_eval_string`. The patch
+ * is an array of `add`, `remove` and `replace` operations that turns it into the
+ * new result, e.g. `[{"op":"replace","path":"/port","value":8080}]`, and is empty
+ * when nothing changed. Records are compared field by field; arrays element by
+ * element, with elements added or removed at the end.
+ *
+ * # Safety
+ * - `code` and `previous_json` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_diff(const char *code, const char *previous_json);
+
+/**
+ * Evaluate Nickel code and return the result as a JSON string, failing if any
+ * string value is longer than `max_chars` characters.
+ *
+ * The error message names the path of the offending string (e.g. `users[2].name`)
+ * and its length in characters.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_json_max_string(const char *code, uintptr_t max_chars);
+
+/**
+ * Evaluate Nickel code and return the result as a JSON string, with enums tagged
+ * so they can be told apart from strings.
+ *
+ * A bare enum `'Foo` becomes `{"$enum": "Foo"}` and a variant `'Some 42` becomes
+ * `{"$enum": "Some", "arg": 42}`, at any depth.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_json_tagged(const char *code);
+
+/**
+ * Evaluate Nickel code and return the result as a JSON string, with enum variant
+ * fields flattened into their containing record.
+ *
+ * A field `status = 'Active { since = 2020 }` becomes `status = "Active"` plus one
+ * field per argument field, named `status<separator>since`. A variant with a
+ * non-record argument, e.g. `count = 'Some 3`, becomes `count = "Some"` and
+ * `count<separator>value = 3`. Bare enums are exported as their tag string.
+ *
+ * # Safety
+ * - `code` and `separator` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_json_flatten_enums(const char *code, const char *separator);
+
+/**
+ * Evaluate Nickel code and return JSON, rejecting record fields that no contract declares.
+ *
+ * Nickel record contracts are already closed: `{ a = 1, b = 2 } | { a | Number }`
+ * fails on `b`. But fields can still slip past a schema, either through an open
+ * contract (`{ a | Number, .. }`) or by merging a record into one that was
+ * already checked (`({ a = 1 } | Schema) & { b = 2 }`).
+ *
+ * The check keys off field metadata, which keeps the annotations of every
+ * contract or type applied to a field: in strict mode, a record in which at least
+ * one field carries an annotation is treated as declared by a schema, and each of
+ * its fields must then carry an annotation too. Records without any annotated
+ * field are left alone. The error names the path of the first undeclared field.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_strict(const char *code);
+
+/**
+ * Evaluate Nickel code, apply a contract to the whole result, and return the
+ * result as a JSON string only if the contract holds.
+ *
+ * `contract_code` is any Nickel expression usable as a contract, such as
+ * `{ port | Number, host | String }`. It is applied to the final value of `code`,
+ * after all merges, as a guardrail independent of annotations inside `code`.
+ * On violation the blame error is reported through `nickel_get_error`.
+ *
+ * # Safety
+ * - `code` and `contract_code` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_json_validated(const char *code, const char *contract_code);
+
+/**
+ * Evaluate Nickel code to JSON and validate the result against a JSON Schema.
+ *
+ * `schema_json` is a JSON Schema document, validated with the `jsonschema` crate
+ * in the draft named by its `$schema` (2020-12 by default). References are
+ * resolved within the document; remote `$ref`s can't be fetched and make the
+ * schema invalid. Every violation is reported, one per line, with the path of
+ * the offending value.
+ *
+ * # Safety
+ * - `code` and `schema_json` must be valid null-terminated C strings
+ *
+ * # Returns
+ * - 0 if the result is valid
+ * - 1 if it isn't; `nickel_get_error` lists the violations
+ * - -1 on any other error, such as an evaluation error or an invalid schema (use
+ *   `nickel_get_error` for details)
+ */
+int32_t nickel_eval_validate_schema(const char *code, const char *schema_json);
+
+/**
+ * Evaluate a flat Nickel record and return it in `.env` format.
+ *
+ * Each field becomes a `KEY=value` line, in sorted key order. Booleans are
+ * written as `true` or `false`, numbers as in JSON, null as an empty value and a
+ * bare enum `'Foo` as `Foo`. Values containing anything but letters, digits and
+ * `_-.,:/@%+=` are single-quoted for the shell, with embedded quotes written as
+ * `'\''`. Fails if a field holds a record, an array or another value without a
+ * scalar form, or if a field name isn't a valid environment variable name.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_dotenv(const char *code);
+
+/**
+ * Evaluate Nickel code and write the result to the file at `out_path`, in the
+ * format given by its extension: `.json`, `.yaml` (or `.yml`) or `.toml`.
+ *
+ * Other extensions are written as JSON, and a warning saying so is added to
+ * those returned by `nickel_get_warnings`. An existing file is overwritten.
+ *
+ * # Safety
+ * - `code` and `out_path` must be valid null-terminated C strings
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details); on
+ *   error the file is not written
+ */
+int32_t nickel_eval_to_path(const char *code, const char *out_path);
+
+/**
+ * Evaluate a stream of Nickel documents separated by `---` lines and return all
+ * results as one JSON array.
+ *
+ * A line consisting only of `---` (trailing whitespace allowed) separates two
+ * documents, as in a YAML stream. Each document is evaluated independently and
+ * contributes one element to the array: `{"value": ...}` on success or
+ * `{"error": "..."}` on failure, so one invalid document doesn't stop the
+ * others. Line numbers in error messages refer to the whole input.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out` must be valid for writes; it is set to NULL on error
+ * - The string written to `out` must be freed with `nickel_free_string`
+ *
+ * # Returns
+ * - The number of documents that failed (0 if all succeeded)
+ * - -1 on error; use `nickel_get_error` to retrieve error message
+ */
+int32_t nickel_eval_multi(const char *code, const char **out);
+
+/**
+ * Evaluate several Nickel code strings and return each result as a JSON string.
+ *
+ * Item `i` of `codes` is evaluated independently, like `nickel_eval_string`, and
+ * its result is written to `out[i]`. A failed item gets a NULL entry in `out`
+ * and, if `errors` isn't NULL, its error message in `errors[i]` (NULL for items
+ * that succeeded). A failed item doesn't stop the others.
+ *
+ * # Safety
+ * - `codes` must point to `count` valid null-terminated C strings
+ * - `out` must be valid for writes of `count` pointers
+ * - `errors` must be NULL or valid for writes of `count` pointers
+ * - Every non-NULL string written to `out` or `errors` must be freed with
+ *   `nickel_free_string`
+ *
+ * # Returns
+ * - The number of items that failed (0 if all succeeded)
+ * - -1 if `codes` or `out` is NULL; use `nickel_get_error` to retrieve error message
+ */
+int32_t nickel_eval_batch(const char *const *codes,
+                          uintptr_t count,
+                          const char **out,
+                          const char **errors);
+
+/**
+ * Evaluate several Nickel files merged together and return the result as a JSON string.
+ *
+ * The files are combined with `&` in the order given, like `nickel export a.ncl
+ * b.ncl`, so a later file can override the defaults of an earlier one and must
+ * satisfy the contracts it declares. Each file's imports are resolved relative to
+ * that file; relative paths in `paths` are relative to the current directory.
+ *
+ * # Safety
+ * - `paths` must point to `count` valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error (including when `count` is 0); use `nickel_get_error`
+ *   to retrieve error message
+ */
+const char *nickel_eval_merge_files(const char *const *paths, uintptr_t count);
+
+/**
+ * Evaluate a single field of a Nickel configuration and return it as a JSON string.
+ *
+ * `field_path` is a dot-separated path such as `config.database`; segments that
+ * aren't valid identifiers can be quoted (`config."my-key"`). Only the requested
+ * field is fully evaluated and serialized, not the rest of the configuration.
+ * Fails if the path doesn't exist or goes through a value that isn't a record.
+ *
+ * # Safety
+ * - `code` and `field_path` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_field(const char *code, const char *field_path);
+
+/**
+ * Evaluate a Nickel record and return only the fields under a path prefix, as JSON.
+ *
+ * A field is kept, along with everything below it, when its dot-separated path
+ * starts with `prefix` as a string: `service.` keeps every field of `service`,
+ * `service.port` keeps that field and any sibling such as `service.ports`. Records
+ * on the way to a kept field are kept with only the matching fields, so the result
+ * has the same nesting as the input. An empty prefix keeps everything; a prefix
+ * matching nothing gives `{}`. Fields that don't match are never serialized.
+ *
+ * # Safety
+ * - `code` and `prefix` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error (including when the result isn't a record); use
+ *   `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_prefix(const char *code, const char *prefix);
+
+/**
+ * Get what `nickel query` shows about a field, as a JSON object.
+ *
+ * `field_path` uses the same syntax as `nickel_eval_field`. The object has the keys:
+ * - `value`: the fully evaluated value, or null if the field has no definition
+ * - `type`: the type annotation (`x : Number`) as a string, or null
+ * - `contracts`: the contract annotations (`x | Number`) as an array of strings
+ * - `doc`: the documentation (`x | doc "..."`), or null
+ *
+ * # Safety
+ * - `code` and `field_path` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_query(const char *code, const char *field_path);
+
+/**
+ * Get the documentation attached to a field with `| doc "..."`.
+ *
+ * `field_path` uses the same syntax as `nickel_eval_field`. Only the metadata is
+ * looked up; the field's value isn't evaluated.
+ *
+ * # Safety
+ * - `code` and `field_path` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error, including when the field has no documentation; use
+ *   `nickel_get_error` to retrieve error message
+ */
+const char *nickel_query_doc(const char *code, const char *field_path);
+
+/**
+ * Evaluate Nickel code with a JSON document bound to the variable `input`, and
+ * return the result as a JSON string.
+ *
+ * This lets snippets consume data produced elsewhere, e.g. `input.foo + 1`.
+ * The binding is inserted on the first line of the program, so line numbers in
+ * error messages are unaffected.
+ *
+ * # Safety
+ * - `code` and `input_json` must be valid null-terminated C strings
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_with_json_input(const char *code, const char *input_json);
+
+/**
+ * Apply a Nickel function to arguments given as JSON and return the result as JSON.
+ *
+ * `func_code` must evaluate to a function and `args_json` must be a JSON array;
+ * its elements are passed as arguments from left to right, e.g. `fun x y => x - y`
+ * applied to `[5, 2]` gives `3`. The call must use exactly as many arguments as
+ * the function takes: supplying more fails, and so does supplying fewer, since the
+ * result would itself be a function.
+ *
+ * # Safety
+ * - `func_code` and `args_json` must be valid null-terminated C strings
+ * - `out_json` must be valid for writes; it is set to NULL on error
+ * - The string written to `out_json` must be freed with `nickel_free_string`
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_apply(const char *func_code, const char *args_json, const char **out_json);
+
+/**
+ * Apply a Nickel function to some of its arguments and keep the result for later.
+ *
+ * `func_code` must evaluate to a function and `args_json` must be a JSON array,
+ * possibly empty, of arguments passed from left to right. Unlike `nickel_apply`,
+ * fewer arguments than the function takes are fine: the returned handle holds
+ * the partial application, which `nickel_partial_apply_more` extends and
+ * `nickel_partial_force` evaluates. Supplying more arguments than the function
+ * takes fails as with `nickel_apply`.
+ *
+ * # Safety
+ * - `func_code` and `args_json` must be valid null-terminated C strings
+ * - The returned handle must be freed with `nickel_partial_free`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+NickelPartial *nickel_partial_apply(const char *func_code, const char *args_json);
+
+/**
+ * Apply the partial application in `handle` to more arguments.
+ *
+ * `args_json` must be a JSON array of the next arguments. On error the handle is
+ * left as it was, so it can still be used.
+ *
+ * # Safety
+ * - `handle` must have been returned by `nickel_partial_apply` and not yet freed
+ * - `args_json` must be a valid null-terminated C string
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_partial_apply_more(NickelPartial *handle, const char *args_json);
+
+/**
+ * Evaluate the partial application in `handle` and return the result as JSON.
+ *
+ * Fails if the result is still a function, i.e. if the function hasn't received
+ * all its arguments yet. The handle is kept, so it may be forced again or
+ * applied to more arguments if the result is itself a function.
+ *
+ * # Safety
+ * - `handle` must have been returned by `nickel_partial_apply` and not yet freed
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_partial_force(NickelPartial *handle);
+
+/**
+ * Free a partial application created by `nickel_partial_apply`.
+ *
+ * # Safety
+ * - `handle` must have been returned by `nickel_partial_apply`
+ * - The handle must not be used after this call
+ * - Passing NULL is safe (no-op)
+ */
+void nickel_partial_free(NickelPartial *handle);
+
+/**
+ * Evaluate Nickel code and return the binary encoding with varint lengths.
+ *
+ * Same as `nickel_eval_native`, except that:
+ * - The buffer starts with a protocol byte, `0x80`, before the first type tag
+ * - Every length prefix (strings, arrays, records, keys, enum tags, dates) is an
+ *   unsigned LEB128 varint instead of a little-endian u32
+ *
+ * This shrinks payloads made of many short strings, where the 4-byte prefixes
+ * dominate.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_native_varint(const char *code);
+
+/**
+ * Evaluate Nickel code and return the binary encoding with packed scalar arrays.
+ *
+ * Same as `nickel_eval_native`, except that:
+ * - The buffer starts with a protocol byte, `0x81`, before the first type tag
+ * - A non-empty array whose elements are all booleans, or all integers fitting
+ *   in an `i64`, is encoded as `TYPE_PACKED_ARRAY` (11): the tag, the element
+ *   type tag (`TYPE_BOOL` or `TYPE_INT`), a u32 element count, then the payload
+ *   with no per-element tags. Booleans take one bit each, least significant bit
+ *   first, padded to a whole byte; integers take 8 bytes each, little-endian.
+ *
+ * Other arrays use the regular `TYPE_ARRAY` layout.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_native_packed(const char *code);
+
+/**
+ * Evaluate Nickel code and return the binary encoding with 4-byte floats.
+ *
+ * Same as `nickel_eval_native`, except that numbers which aren't integers fitting
+ * in an `i64` are encoded as `TYPE_FLOAT32` (15): the tag, the number rounded to
+ * an `f32` (little-endian) with the configured rounding mode, then a lossy flag
+ * byte set when the `f32` isn't exactly the original number. This saves 4 bytes
+ * per decimal when single precision is enough.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_native_f32(const char *code);
+
+/**
+ * Evaluate Nickel code and return the binary encoding with an integrity trailer.
+ *
+ * Same as `nickel_eval_native`, except that the buffer starts with a protocol
+ * byte, `0x82`, and ends with a 12-byte trailer: the total length of the buffer
+ * (trailer included) as a u64, then the CRC-32 (IEEE) of the encoded value, the
+ * bytes between the protocol byte and the trailer, as a u32, both little-endian.
+ * A decoder can check both before parsing, so a truncated or corrupted buffer is
+ * rejected up front rather than failing partway through decoding.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_native_checked(const char *code);
+
+/**
+ * Evaluate Nickel code and write the binary encoding into a caller-provided buffer.
+ *
+ * Uses the same binary protocol as `nickel_eval_native`, but no memory is
+ * allocated for the caller. The encoded length is always written to `out_len`;
+ * if it exceeds `cap`, nothing is copied and 1 is returned so the caller can
+ * retry with a large enough buffer. `buf` may be NULL when `cap` is 0, to query
+ * the size.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `buf` must be valid for writes of `cap` bytes (or NULL if `cap` is 0)
+ * - `out_len` must be a valid pointer to a `usize`
+ *
+ * # Returns
+ * - 0 if the encoding was written to `buf`
+ * - 1 if `cap` is too small; `out_len` holds the required size
+ * - -1 on error; use `nickel_get_error` to retrieve error message
+ */
+int32_t nickel_eval_native_into(const char *code, uint8_t *buf, uintptr_t cap, uintptr_t *out_len);
+
+/**
+ * Evaluate Nickel code and return its JSON value along with evaluation statistics.
+ *
+ * The statistics are a JSON object `{"eval_us": 1234, "steps": 5678}` holding the
+ * time spent parsing and evaluating the code, in microseconds, excluding JSON
+ * serialization, and the number of evaluation steps taken, counted as for
+ * `nickel_set_eval_fuel`.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out_json` and `out_stats` must be valid for writes; both are set to NULL on error
+ * - Strings written to `out_json` and `out_stats` must be freed with `nickel_free_string`
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_eval_with_stats(const char *code, const char **out_json, const char **out_stats);
+
+/**
+ * Evaluate Nickel code and return both its JSON value and its type.
+ *
+ * The type is the one Nickel's typechecker infers for the whole expression, as
+ * if it were annotated with `: _`, e.g. `Number` for `1 + 1`. When the code
+ * doesn't typecheck statically, its apparent type is used instead, which is
+ * `Dyn` unless the code carries a type annotation.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out_json` and `out_type` must be valid for writes; both are set to NULL on error
+ * - Strings written to `out_json` and `out_type` must be freed with `nickel_free_string`
+ *
+ * # Returns
+ * - 0 if both the value and the type were written
+ * - 1 if only the type was written, because the value can't be exported to JSON
+ *   (e.g. a function); use `nickel_get_error` for the reason
+ * - -1 on error; use `nickel_get_error` to retrieve error message
+ */
+int32_t nickel_eval_with_type(const char *code, const char **out_json, const char **out_type);
+
+/**
+ * Check that Nickel code parses, without typechecking or evaluating it.
+ *
+ * This is cheap even for programs that would be expensive to evaluate or would
+ * not terminate. The prelude, if any, is not applied.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - Returns 0 if the code parses, -1 otherwise (use `nickel_get_error` for the
+ *   diagnostic and `nickel_get_error_position` for its location)
+ */
+int32_t nickel_check_syntax(const char *code);
+
+/**
+ * Parse Nickel code and print it back in a canonical layout, without evaluating it.
+ *
+ * The output is Nickel's own pretty-printing of the parsed program: record fields
+ * are sorted, spacing and line breaks are normalized and lines are wrapped at 80
+ * columns, so snippets differing only in formatting give the same text, ending
+ * with a newline. Comments aren't part of the parsed program and are dropped;
+ * `doc` metadata is kept. The prelude, if any, is not applied.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_normalize(const char *code);
+
+/**
+ * Evaluate Nickel code and return the diagnostics it produces as JSON.
+ *
+ * The result is a JSON array with one object per diagnostic:
+ * `{"severity": "error", "message": "...", "line": 1, "column": 5, "byte_span": [4, 9]}`.
+ * `severity` is one of `bug`, `error`, `warning`, `note` or `help`. `line` and
+ * `column` are 1-based and `byte_span` is the 0-based byte range of the primary
+ * label in its file; all three are `null` when the diagnostic has no location.
+ * A successful evaluation returns `[]`; the value itself is discarded.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error (only for invalid arguments); use `nickel_get_error` to
+ *   retrieve error message
+ */
+const char *nickel_eval_diagnostics(const char *code);
+
+/**
+ * Evaluate Nickel code and return binary-encoded native types.
+ *
+ * Binary protocol:
+ * - Type tag (1 byte): 0=Null, 1=Bool, 2=Int64, 3=Float64, 4=String, 5=Array, 6=Record,
+ *   7=Enum, 8=Date, 9=Missing (a record field with no definition), 10=Function
+ *   (arity and type signature, in place of the function itself), 12=KeyedRecord
+ *   (with `nickel_set_numeric_keys`), 13=Quantity (`Duration` and `ByteSize`
+ *   fields) and 14=Unsupported (with `nickel_set_encode_mode`). Tags 11=PackedArray
+ *   and 15=Float32 only come from `nickel_eval_native_packed` and
+ *   `nickel_eval_native_f32`
+ * - Value data (varies by type)
+ * - Float64 values are followed by a lossy flag (1 byte): 1 if the Nickel number
+ *   wasn't exactly representable and was rounded, 0 otherwise
+ *
+ * A number is an Int64 when the exact rational is an integer in range, before
+ * any rounding to `f64`. Nickel doesn't keep how a literal was written, so `2`
+ * and `2.0` are the same number and both are encoded as Int64.
+ *
+ * A panic inside Nickel is caught and reported as an error rather than unwinding
+ * into the caller.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_native(const char *code);
+
+/**
+ * Evaluate Nickel code and return the result encoded as MessagePack.
+ *
+ * The value is exported like `nickel_eval_string` (integers stay integers, enum
+ * tags become strings) and fails on the same unrepresentable values, such as
+ * functions.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_msgpack(const char *code);
+
+/**
+ * Evaluate Nickel code and return the result encoded as CBOR (RFC 8949).
+ *
+ * The value is exported like `nickel_eval_msgpack`: integers are CBOR integers,
+ * other numbers floats (in the smallest width that holds them exactly), enum
+ * tags strings and records maps with string keys in sorted order. Fails on the
+ * same unrepresentable values, such as functions.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_cbor(const char *code);
+
+/**
+ * Evaluate Nickel code and return the result as a protobuf `Value` message.
+ *
+ * The message follows `google.protobuf.Value` from `struct.proto`: `null` sets
+ * `null_value`, numbers `number_value` as doubles rounded with the configured
+ * rounding mode, strings `string_value`, booleans `bool_value`, records
+ * `struct_value`, and arrays `list_value`. Enums become a `struct_value` with the
+ * tag as the string field `tag` and, for a variant, its argument as `arg`. Fails
+ * on values without a protobuf form, such as functions.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out` must be valid for writes; it receives a null buffer on error
+ * - The buffer written to `out` must be freed with `nickel_free_buffer`
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_eval_protobuf(const char *code, NativeBuffer *out);
+
+/**
+ * Evaluate Nickel code producing a table and return it as an Arrow IPC stream.
+ *
+ * The result must be a non-empty array of records that all have the same fields.
+ * Each field becomes a column, in alphabetical order, whose type is inferred from
+ * its values: `Boolean`, `Int64` (integers fitting in an `i64`), `Float64` (other
+ * numbers, and integers in a column that also has them), or `Utf8`. `null` values
+ * make a column nullable, and a column of nulls only has the `Null` type. Any
+ * other value, such as a nested array or record, is an error. The stream holds the
+ * schema and a single record batch.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_arrow(const char *code);
+
+/**
+ * List the files imported by a Nickel file, directly or transitively.
+ *
+ * Imports are resolved like during evaluation, but nothing is typechecked or
+ * evaluated. The resolved paths are written to `*out` separated by newlines, in
+ * the order they were resolved; each file appears once and the entry point itself
+ * isn't included. Useful for setting up file watches.
+ *
+ * # Safety
+ * - `path` must be a valid null-terminated C string containing a file path
+ * - `out` must be valid for writes; the string written there must be freed with
+ *   `nickel_free_string`
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_list_imports(const char *path, const char **out);
+
+/**
+ * Evaluate a Nickel file and return binary-encoded native types.
+ *
+ * This function evaluates a Nickel file from the filesystem, which allows
+ * the file to use `import` statements to include other Nickel files.
+ *
+ * # Safety
+ * - `path` must be a valid null-terminated C string containing a file path
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_eval_file_native(const char *path);
+
+/**
+ * Evaluate Nickel code that produces an integer and write it to `out`.
+ *
+ * The result must be a whole number that fits in an `i64`; no buffer is allocated.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out` must be a valid pointer to an `i64`
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_eval_i64(const char *code, int64_t *out);
+
+/**
+ * Evaluate Nickel code that produces a number and write it to `out` as an `f64`.
+ *
+ * Numbers are rounded with the mode set by `nickel_set_rounding_mode`, which
+ * defaults to Nearest; no buffer is allocated.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out` must be a valid pointer to an `f64`
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_eval_f64(const char *code, double *out);
+
+/**
+ * Evaluate Nickel code that produces a boolean and write it to `out`.
+ *
+ * No buffer is allocated.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out` must be a valid pointer to a `bool`
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_eval_bool(const char *code, bool *out);
+
+/**
+ * Evaluate Nickel code that produces a string and return its contents unquoted.
+ *
+ * Unlike `nickel_eval_string`, which would return the JSON string `"hello"`, this
+ * returns `hello` as is, like `nickel export --format raw`.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error (including when the result isn't a string); use
+ *   `nickel_get_error` to retrieve error message
+ */
+const char *nickel_eval_raw_string(const char *code);
+
+/**
+ * Check whether Nickel code passes its contracts.
+ *
+ * `code` is fully evaluated, typically a value with a contract annotation or a call
+ * like `std.contract.apply MyContract value`, or an expression evaluating to a
+ * boolean. It fails when a contract is broken (including `std.fail_with`) or when
+ * the result is `false`; any other result passes.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ *
+ * # Returns
+ * - 1 if `code` passes
+ * - 0 if it fails; `nickel_get_error` describes the failure, and
+ *   `nickel_get_contract_failure` the broken contract, if any
+ * - -1 on any other error, such as a syntax or type error (use `nickel_get_error`
+ *   for details)
+ */
+int32_t nickel_validate(const char *code);
+
+/**
+ * Check that Nickel code evaluates, without serializing the result.
+ *
+ * `code` is fully evaluated as for `nickel_eval_string`, so contracts are checked
+ * all the way down, but no JSON string or buffer is built. Useful as a cheap health
+ * check for configurations whose output would be discarded.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - Returns 0 on success, -1 on error; use `nickel_get_error` to retrieve error message
+ */
+int32_t nickel_eval_ok(const char *code);
+
+/**
+ * Look up the binary protocol tag for a type name.
+ *
+ * Names are the lowercase tag names without the `TYPE_` prefix: `null`, `bool`,
+ * `int`, `float`, `string`, `array`, `record`, `enum`, `date`, `missing`,
+ * `function`, `packed_array`, `keyed_record`, `quantity`, `unsupported` and
+ * `float32`. Decoders can fetch the tags at startup instead of hard-coding them.
+ *
+ * # Safety
+ * - `name` must be NULL or a valid null-terminated C string
+ * - Returns the tag, or -1 if `name` is NULL or not a known type name
+ */
+int32_t nickel_type_tag(const char *name);
+
+/**
+ * Evaluate Nickel code only to weak head normal form and return its type tag.
+ *
+ * Nested values are not forced: for an array or a record, `out_count` receives the
+ * number of elements or fields, but none of them is evaluated. Fields marked
+ * `not_exported` aren't counted, matching `nickel_record_keys`. Numbers are tagged
+ * `TYPE_INT` when they are integers fitting in an `i64` and `TYPE_FLOAT` otherwise,
+ * as in the binary protocol.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - `out_count` may be NULL; otherwise it must be valid for writes and is set to 0
+ *   for values other than arrays and records
+ * - Returns one of the `TYPE_*` tags, or `SHAPE_ERROR` (255) on error; use
+ *   `nickel_get_error` to retrieve error message
+ */
+uint8_t nickel_eval_shape(const char *code, uint64_t *out_count);
+
+/**
+ * Evaluate Nickel code only to weak head normal form and return its type tag.
+ *
+ * Same as `nickel_eval_shape` without the element count, for dispatching on the
+ * kind of a result before choosing how to fetch it.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - Returns one of the `TYPE_*` tags, or -1 on error; use `nickel_get_error` to
+ *   retrieve error message
+ */
+int32_t nickel_result_kind(const char *code);
+
+/**
+ * Evaluate Nickel code to a record and return its top-level field names.
+ *
+ * The names are sorted and separated by newlines. The record is only evaluated
+ * to weak head normal form, so field values are never computed. Fields marked
+ * `not_exported` are left out, as they are from exported values.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - Returns a pointer to a null-terminated string that must be freed with `nickel_free_string`
+ * - Returns NULL on error (including when the result isn't a record); use
+ *   `nickel_get_error` to retrieve error message
+ */
+const char *nickel_record_keys(const char *code);
+
+/**
+ * Evaluate Nickel code to a record and list the paths to all of its leaves.
+ *
+ * A leaf is any value that isn't a record or an array. Paths are dot-separated,
+ * with array elements named by their 0-based index, like `server.hosts.0`, and
+ * are separated by newlines. Record fields are visited in sorted order, array
+ * elements in order; fields marked `not_exported` are left out, and empty records
+ * and arrays have no leaves, so they don't appear.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - Returns a pointer to a null-terminated string that must be freed with `nickel_free_string`
+ * - Returns NULL on error (including when the result isn't a record); use
+ *   `nickel_get_error` to retrieve error message
+ */
+const char *nickel_leaf_paths(const char *code);
+
+/**
+ * Parse and typecheck Nickel code into a program handle for repeated evaluation.
+ *
+ * Syntax and type errors are reported here rather than on the first evaluation.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned handle must be freed with `nickel_program_free`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+NickelProgram *nickel_program_new(const char *code);
+
+/**
+ * Evaluate a program handle and return binary-encoded native types.
+ *
+ * Uses the same binary protocol as `nickel_eval_native`. The handle can be
+ * evaluated any number of times and yields the same buffer each time.
+ *
+ * # Safety
+ * - `handle` must have been returned by `nickel_program_new` and not yet freed
+ * - The returned buffer must be freed with `nickel_free_buffer`
+ * - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+ */
+NativeBuffer nickel_program_eval_native(NickelProgram *handle);
+
+/**
+ * Override a field of a program handle with a JSON value.
+ *
+ * `field_path` is dot-separated, as for `nickel_eval_field`. The value is merged
+ * with force priority, so it replaces whatever the program defines. Setting the
+ * same path again replaces the previous override; other overrides are kept. The
+ * change takes effect on the next evaluation of the handle.
+ *
+ * # Safety
+ * - `handle` must have been returned by `nickel_program_new` and not yet freed
+ * - `field_path` and `value_json` must be valid null-terminated C strings
+ * - Returns 0 on success, -1 on error; use `nickel_get_error` to retrieve error message
+ */
+int32_t nickel_program_set_override(NickelProgram *handle,
+                                    const char *field_path,
+                                    const char *value_json);
+
+/**
+ * Evaluate a program handle, with its current overrides, and return JSON.
+ *
+ * # Safety
+ * - `handle` must have been returned by `nickel_program_new` and not yet freed
+ * - The returned string must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_program_reeval_json(NickelProgram *handle);
+
+/**
+ * Free a program handle.
+ *
+ * # Safety
+ * - `handle` must have been returned by `nickel_program_new`
+ * - The handle must not be used after this call
+ * - Passing NULL is safe (no-op)
+ */
+void nickel_program_free(NickelProgram *handle);
+
+/**
+ * Create an empty source to push chunks of Nickel code into.
+ *
+ * # Safety
+ * - The returned source must be freed with `nickel_source_free`
+ */
+NickelSource *nickel_source_new(void);
+
+/**
+ * Append `len` bytes of Nickel code to `src`.
+ *
+ * The bytes don't need to be valid UTF-8 on their own: a multibyte character may
+ * be split across two pushes.
+ *
+ * # Safety
+ * - `src` must have been returned by `nickel_source_new` and not yet freed
+ * - `bytes` must be valid for reads of `len` bytes; it may be NULL only if `len` is 0
+ * - Returns 0 on success, -1 on error; use `nickel_get_error` for message
+ */
+int32_t nickel_source_push(NickelSource *src, const uint8_t *bytes, uintptr_t len);
+
+/**
+ * Evaluate the code pushed into `src` so far and return the result as a JSON string.
+ *
+ * Same as `nickel_eval_string` on the concatenated chunks. The chunks are kept, so
+ * more may be pushed and the source evaluated again. A multibyte character left
+ * incomplete by the last chunk is reported as invalid UTF-8.
+ *
+ * # Safety
+ * - `src` must have been returned by `nickel_source_new` and not yet freed
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+const char *nickel_source_eval_json(const NickelSource *src);
+
+/**
+ * Free a source created by `nickel_source_new`.
+ *
+ * # Safety
+ * - `src` must have been returned by `nickel_source_new`
+ * - The source must not be used after this call
+ * - Passing NULL is safe (no-op)
+ */
+void nickel_source_free(NickelSource *src);
+
+/**
+ * Create an evaluator with no import paths.
+ *
+ * # Safety
+ * - The returned evaluator must be freed with `nickel_evaluator_free`
+ */
+NickelEvaluator *nickel_evaluator_new(void);
+
+/**
+ * Add a directory searched by `import` statements evaluated by `ev`.
+ *
+ * # Safety
+ * - `ev` must have been returned by `nickel_evaluator_new` and not yet freed
+ * - `path` must be a valid null-terminated C string
+ * - Returns 0 on success, -1 on error; errors about a null `ev` are reported
+ *   through `nickel_get_error`, others through the evaluator
+ */
+int32_t nickel_evaluator_add_import_path(NickelEvaluator *ev, const char *path);
+
+/**
+ * Evaluate a Nickel code string with `ev` and return the result as a JSON string.
+ *
+ * On error, NULL is returned and, if `err_out` isn't NULL, `*err_out` is set to
+ * the error message. On success `*err_out` is set to NULL.
+ *
+ * # Safety
+ * - `ev` must have been returned by `nickel_evaluator_new` and not yet freed
+ * - `code` must be a valid null-terminated C string
+ * - `err_out` must be NULL or valid for writes
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - The error message is owned by `ev` and valid until its next call; do not free it
+ */
+const char *nickel_evaluator_eval_string(NickelEvaluator *ev,
+                                         const char *code,
+                                         const char **err_out);
+
+/**
+ * Free an evaluator, including its last error message.
+ *
+ * # Safety
+ * - `ev` must have been returned by `nickel_evaluator_new`
+ * - The evaluator and any error message it returned must not be used after this call
+ * - Passing NULL is safe (no-op)
+ */
+void nickel_evaluator_free(NickelEvaluator *ev);
+
+/**
+ * Evaluate Nickel code that produces an array and open an iterator over it.
+ *
+ * Elements are encoded one at a time by `nickel_array_next`, so the caller never
+ * holds a buffer for the whole array. Fails if the result isn't an array.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - The returned iterator must be freed with `nickel_array_free`
+ * - Returns NULL on error; use `nickel_get_error` to retrieve error message
+ */
+NickelArrayIter *nickel_eval_array_open(const char *code);
+
+/**
+ * Encode the next element of an array iterator into `out`.
+ *
+ * Each element uses the same binary protocol as `nickel_eval_native` and must be
+ * freed with `nickel_free_buffer`.
+ *
+ * # Safety
+ * - `iter` must have been returned by `nickel_eval_array_open` and not yet freed
+ * - `out` must be valid for writes
+ *
+ * # Returns
+ * - 0 if an element was written to `out`
+ * - 1 if the iterator is exhausted (`out` gets a null buffer)
+ * - -1 on error; use `nickel_get_error` to retrieve error message
+ */
+int32_t nickel_array_next(NickelArrayIter *iter, NativeBuffer *out);
+
+/**
+ * Free an array iterator.
+ *
+ * # Safety
+ * - `iter` must have been returned by `nickel_eval_array_open`
+ * - The iterator must not be used after this call
+ * - Passing NULL is safe (no-op)
+ */
+void nickel_array_free(NickelArrayIter *iter);
+
+/**
+ * Set the rounding mode used when converting Nickel numbers to `f64`.
+ *
+ * Nickel numbers are arbitrary-precision rationals, so most non-integers have no
+ * exact `f64` representation. The mode applies to the current thread:
+ * - 0: Nearest (default)
+ * - 1: Down (towards zero)
+ * - 2: Up (away from zero)
+ * - 3: Floor (towards negative infinity)
+ * - 4: Ceiling (towards positive infinity)
+ *
+ * # Safety
+ * - Returns 0 on success, -1 for an unknown mode (use `nickel_get_error` for details)
+ */
+int32_t nickel_set_rounding_mode(uint8_t mode);
+
+/**
+ * Enable or disable strict numbers mode for the current thread.
+ *
+ * By default numbers that have no exact representation in the output format are
+ * rounded (e.g. `1/3` becomes the nearest `f64`). In strict mode this is an error
+ * instead, both for the binary protocol and for JSON results, while numbers that
+ * convert exactly, like `0.5`, are unaffected.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_strict_numbers(bool enabled);
+
+/**
+ * Enable or disable ANSI colors in error messages for the current thread.
+ *
+ * Error messages are rendered like Nickel's command line reports them. By
+ * default they are plain text, for logs and programs to read; when enabled they
+ * keep the escape sequences that color them in a terminal.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_color_diagnostics(bool enabled);
+
+/**
+ * Enable or disable numeric record keys in binary results for the current thread.
+ *
+ * Nickel record keys are always strings. When enabled, a record with at least one
+ * key made only of digits, without leading zeros and fitting in an `i64`, is
+ * encoded as `TYPE_KEYED_RECORD` instead of `TYPE_RECORD`: each key is preceded
+ * by a key type byte, `TYPE_INT` followed by the key as an `i64`, or `TYPE_STRING`
+ * followed by the key as usual. Other records are unaffected. Disabled by default.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_numeric_keys(bool enabled);
+
+/**
+ * Enable or disable the tagged union convention in binary results for the current
+ * thread.
+ *
+ * When enabled, a record with exactly two fields, `tag` holding an enum tag
+ * without argument and `value` holding anything, is encoded as `TYPE_ENUM` with
+ * that tag and `value` as its argument, just like the enum variant `'Foo value`.
+ * This lets decoders treat both styles of tagged unions the same way. Other
+ * records are unaffected. Disabled by default.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_union_convention(bool enabled);
+
+/**
+ * Enable or disable encoding every number as a float in binary results for the
+ * current thread.
+ *
+ * By default, numbers that are integers fitting in an `i64` are encoded as
+ * `TYPE_INT` and others as `TYPE_FLOAT`. When enabled, every number is encoded as
+ * `TYPE_FLOAT` (or `TYPE_FLOAT32` with `nickel_eval_native_f32`), so decoders get
+ * a single numeric type; integer arrays are no longer packed either. Disabled by
+ * default.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_always_float(bool enabled);
+
+/**
+ * Choose what the binary protocol does with values it can't encode, for the
+ * current thread.
+ *
+ * Values such as contracts, types or sealed terms have no native form:
+ * - 0: Fail the whole evaluation with an error naming the value's location (default)
+ * - 1: Encode each such value as `TYPE_UNSUPPORTED` followed by a short
+ *   description of it, like `a type`, in the same layout as `TYPE_STRING`, and
+ *   keep the rest of the result
+ *
+ * # Safety
+ * - Returns 0 on success, -1 for an unknown mode (use `nickel_get_error` for details)
+ */
+int32_t nickel_set_encode_mode(uint8_t mode);
+
+/**
+ * Set the number of steps each subsequent evaluation on this thread may take.
+ *
+ * A step is the evaluator entering a variable or a thunk, so the count is the
+ * same on every run: once it exceeds `steps`, evaluation stops with an
+ * `Evaluation fuel exhausted` error, even for code that would loop forever.
+ * Typechecking isn't counted. The budget is unbounded by default, and
+ * `u64::MAX` restores that.
+ *
+ * Values that depend on themselves, like `let rec r = { a = r.a } in r`, fail
+ * with an `infinite recursion` error whatever the budget; the fuel is for code
+ * that keeps doing new work, such as a function calling itself forever.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_eval_fuel(uint64_t steps);
+
+/**
+ * Report evaluation progress to a callback.
+ *
+ * `cb` is called with the number of steps taken so far, as counted by
+ * `nickel_set_eval_fuel`, every 10,000 steps of an evaluation: each call means
+ * another 10,000 variables or thunks were entered. Evaluations shorter than that
+ * never call it, and the total isn't known in advance, so this suits an activity
+ * indicator rather than a percentage. The callback applies to the current
+ * thread; pass NULL to remove it.
+ *
+ * # Safety
+ * - `cb` must be NULL or a function that can be called from the evaluating thread
+ */
+void nickel_set_progress_callback(NickelProgressCallback cb);
+
+/**
+ * Set the largest number of elements an array, or fields a record, may have when
+ * encoded to the binary protocol.
+ *
+ * Larger collections make the encoding fail instead of being materialized. The
+ * limit applies to the current thread; it is unbounded by default, and `u64::MAX`
+ * restores that.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_max_elements(uint64_t n);
+
+/**
+ * Set the largest size, in bytes, of a result encoded to the binary protocol.
+ *
+ * Encoding stops with an error as soon as the buffer grows past `n` bytes, at the
+ * latest after the value that crossed the limit, so a result that is too large is
+ * never fully materialized. The limit applies to the current thread; it is
+ * unbounded by default, and `usize::MAX` restores that.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_max_buffer_bytes(uintptr_t n);
+
+/**
+ * Set the largest size, in bytes, of a JSON result.
+ *
+ * Applies to every function returning an evaluated value as JSON, from
+ * `nickel_eval_string` and its compact and indented variants to `nickel_eval_field`,
+ * `nickel_apply`, `nickel_eval_multi`, `nickel_eval_diff`, `nickel_query` and
+ * program handles. Evaluators ignore it, like other thread-local settings.
+ * Serialization stops with an error as soon as the output grows past `n` bytes, so
+ * a result that is too large is never fully materialized. The limit applies to the
+ * current thread; it is unbounded by default, and `usize::MAX` restores that.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_set_max_json_bytes(uintptr_t n);
+
+/**
+ * Set a prelude of helper definitions for subsequent evaluations on this thread.
+ *
+ * `code` must evaluate to a record, e.g. `{ double = fun x => x * 2 }`. Each of
+ * its fields is then in scope in every snippet evaluated from a string, as if
+ * bound by a `let`. Snippets can shadow prelude names. The prelude is checked
+ * once here; field names must be plain identifiers. Setting a new prelude
+ * replaces the previous one.
+ *
+ * # Safety
+ * - `code` must be a valid null-terminated C string
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details); on
+ *   error the previous prelude is kept
+ */
+int32_t nickel_set_prelude(const char *code);
+
+/**
+ * Set a string variable for subsequent evaluations on this thread.
+ *
+ * The variables set so far are in scope in every snippet evaluated from a string
+ * as the record `env`, e.g. `env.FOO`, like environment variables: values are
+ * always strings, and setting a key again replaces its value. As long as no
+ * variable is set, `env` is not bound at all.
+ *
+ * # Safety
+ * - `key` and `value` must be valid null-terminated C strings
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_set_env(const char *key, const char *value);
+
+/**
+ * Remove every variable set by `nickel_set_env`.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_clear_env(void);
+
+/**
+ * Register a secret value to redact from error messages on this thread.
+ *
+ * Every occurrence of `value` in an error message, and in the details returned by
+ * `nickel_get_contract_failure`, is replaced with `***`, so a secret quoted by a
+ * failing contract doesn't end up in logs. Registering an empty string has no
+ * effect. Errors of `NickelEvaluator` objects, which don't use thread-local state,
+ * are not redacted.
+ *
+ * # Safety
+ * - `value` must be a valid null-terminated C string
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_register_secret(const char *value);
+
+/**
+ * Forget every secret registered by `nickel_register_secret`.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_clear_secrets(void);
+
+/**
+ * Set the directory that imports in evaluated code strings are relative to.
+ *
+ * By default, `import "lib.ncl"` in a code string is looked up in the current
+ * working directory. Once a base directory is set, it's looked up there instead,
+ * as if the code string were a file in that directory; a relative `path` is made
+ * absolute first, so later changes of working directory don't affect it. Error
+ * messages then name the code string `<path>/<ffi>` rather than `<ffi>`. Imports
+ * served by `nickel_set_import_resolver` are unaffected. Pass NULL to go back to
+ * the working directory.
+ *
+ * # Safety
+ * - `path` must be NULL or a valid null-terminated C string naming a directory
+ * - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+ */
+int32_t nickel_set_base_dir(const char *path);
+
+/**
+ * Serve `import` statements from a callback instead of the filesystem.
+ *
+ * While a resolver is set, evaluating a code string first calls `cb` with the path
+ * of every file it imports, directly or through other served files. Paths are
+ * joined to the directory of the importing file as Nickel does: `import "b.ncl"`
+ * inside the served file `lib/a.ncl` asks for `lib/b.ncl`, while imports in the
+ * code string itself are passed as written. Each path is asked for at most once
+ * per evaluation.
+ *
+ * `cb` returns the source text of the file as a null-terminated UTF-8 string, or
+ * NULL if it doesn't know the path, in which case the file is looked up on the
+ * filesystem as usual. The returned string remains owned by the caller: it is
+ * copied before `cb` is called again and never freed here, so it only has to stay
+ * valid until then.
+ *
+ * The resolver applies to the current thread and to functions that fully evaluate
+ * a code string, like `nickel_eval_string` and `nickel_eval_native`. Pass NULL to
+ * remove it.
+ *
+ * # Safety
+ * - `cb` must be NULL or a function returning NULL or a valid null-terminated C string
+ */
+void nickel_set_import_resolver(NickelImportResolver cb);
+
+/**
+ * Remove the prelude set by `nickel_set_prelude`.
+ *
+ * # Safety
+ * - Always safe to call; a no-op if no prelude is set
+ */
+void nickel_clear_prelude(void);
+
+/**
+ * Restore the current thread's state to its defaults.
+ *
+ * Clears the last error (with its position and contract failure), the warnings,
+ * the prelude, the `env` variables, the registered secrets, the base directory,
+ * the import resolver and the progress callback, and resets the rounding mode,
+ * strict numbers mode, always float mode, numeric keys, union convention, encode
+ * mode, colored diagnostics, evaluation fuel, element limit, buffer size limit and
+ * JSON size limit. Import paths belong to `NickelEvaluator` objects and are not
+ * affected.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+void nickel_reset(void);
+
+/**
+ * Get the last error message.
+ *
+ * # Safety
+ * - The returned pointer is valid until the next call to any nickel_* function
+ * - Do not free this pointer; it is managed internally
+ */
+const char *nickel_get_error(void);
+
+/**
+ * Get the length in bytes of the last error message, excluding the null terminator.
+ *
+ * Returns 0 if no error has been set. Lets callers size a buffer before copying
+ * the message returned by `nickel_get_error`.
+ *
+ * # Safety
+ * - Always safe to call
+ */
+uintptr_t nickel_get_error_len(void);
+
+/**
+ * Get a copy of the last error message.
+ *
+ * Unlike `nickel_get_error`, the message is copied into a new string owned by
+ * the caller, so later calls can't invalidate it.
+ *
+ * # Safety
+ * - The returned pointer must be freed with `nickel_free_string`
+ * - Returns NULL if there is no error
+ */
+const char *nickel_get_error_owned(void);
+
+/**
+ * Get the source position of the last error.
+ *
+ * Positions are available when the last error's diagnostic points at source
+ * code: parse and type errors, but also evaluation errors such as a contract
+ * violation, where the position is that of the offending value, or a missing
+ * field, where it is that of the field access. Lines and columns are 1-based, the
+ * byte offset is 0-based from the start of the file containing the error. Any of
+ * the output pointers may be NULL.
+ *
+ * # Safety
+ * - Non-null output pointers must be valid for writes
+ *
+ * # Returns
+ * 0 if a position was written, non-zero if no position is available
+ */
+int32_t nickel_get_error_position(uint32_t *out_line, uint32_t *out_col, uint32_t *out_byte);
+
+/**
+ * Get the details of the last error, if it was a contract violation.
+ *
+ * `*out_field` receives the name of the record field the contract was attached
+ * to (empty if the contract wasn't on a field), and `*out_message` a summary such
+ * as ``expected `Number`, got `"abc"` ``, including the contract's own message
+ * when it provides one. Either output pointer may be NULL.
+ *
+ * # Safety
+ * - Non-null output pointers must be valid for writes
+ * - The strings are valid until the next call to any nickel_* function; do not free them
+ *
+ * # Returns
+ * 0 if details were written, non-zero if the last error wasn't a contract violation
+ */
+int32_t nickel_get_contract_failure(const char **out_field, const char **out_message);
+
+/**
+ * Get the diagnostic messages emitted by the last evaluation, one per line.
+ *
+ * Messages are collected even when the evaluation succeeds. nickel-lang-core
+ * currently reports its non-fatal diagnostics through `std.trace`, so each line
+ * has the form `std.trace: <message>`.
+ *
+ * # Safety
+ * - The returned pointer is valid until the next call to any nickel_* function
+ * - Do not free this pointer; it is managed internally
+ * - Returns NULL if the last evaluation emitted nothing
+ */
+const char *nickel_get_warnings(void);
+
+/**
+ * Free a string allocated by this library.
+ *
+ * # Safety
+ * - `ptr` must have been returned by `nickel_eval_string`
+ * - `ptr` must not be used after this call
+ * - Passing NULL is safe (no-op)
+ */
+void nickel_free_string(const char *ptr);
+
+/**
+ * Free a binary buffer allocated by this library.
+ *
+ * # Safety
+ * - `buffer` must have been returned by `nickel_eval_native`
+ * - The buffer must not be used after this call
+ * - Passing a buffer with null data is safe (no-op); a non-null buffer is freed
+ *   even when `len` is 0
+ */
+void nickel_free_buffer(NativeBuffer buffer);
+
+#endif  /* NICKEL_JL_H */
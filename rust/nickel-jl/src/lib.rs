@@ -6,38 +6,610 @@
 //! # Functions
 //!
 //! - `nickel_eval_string`: Evaluate Nickel code and return JSON string
+//! - `nickel_eval_to_path`: Evaluate and write the result to a JSON, YAML or TOML file
+//! - `nickel_eval_dotenv`: Evaluate a flat record to `KEY=value` lines in `.env` format
+//! - `nickel_eval_multi`: Evaluate `---`-separated documents to a JSON array of results
+//! - `nickel_eval_merge_files`: Evaluate several files merged together and return JSON
+//! - `nickel_eval_batch`: Evaluate many code strings to JSON in one call
+//! - `nickel_eval_diagnostics`: Evaluate Nickel code and return its diagnostics as JSON
 //! - `nickel_eval_native`: Evaluate Nickel code and return binary-encoded native types
+//! - `nickel_eval_msgpack`: Evaluate Nickel code and return the result as MessagePack
+//! - `nickel_eval_cbor`: Evaluate Nickel code and return the result as CBOR
+//! - `nickel_eval_protobuf`: Evaluate Nickel code to a `google.protobuf.Value` message
+//! - `nickel_eval_arrow`: Evaluate an array of records to an Arrow IPC stream
+//! - `nickel_eval_native_varint`: Evaluate to the binary encoding with varint length prefixes
+//! - `nickel_eval_native_packed`: Evaluate to the binary encoding with packed boolean and
+//!   integer arrays
+//! - `nickel_eval_native_checked`: Evaluate to the binary encoding followed by a length and
+//!   checksum trailer
+//! - `nickel_eval_native_f32`: Evaluate to the binary encoding with decimals as 4-byte floats
+//! - `nickel_eval_native_into`: Evaluate to the binary encoding in a caller-provided buffer
+//! - `nickel_eval_with_stats`: Evaluate to JSON and also return timing statistics
+//! - `nickel_eval_with_type`: Evaluate to JSON and also return the inferred type
+//! - `nickel_check_syntax`: Parse Nickel code without evaluating it
+//! - `nickel_normalize`: Parse Nickel code and print it back in a canonical layout
+//! - `nickel_eval_string_utf16`: Evaluate Nickel code and return JSON encoded as UTF-16LE
+//! - `nickel_eval_json_compact`: Evaluate Nickel code and return single-line JSON
+//! - `nickel_eval_json_with_spans`: Evaluate to JSON along with the source span of each value
+//! - `nickel_eval_json_indent`: Evaluate Nickel code to JSON with a chosen indentation width
+//! - `nickel_eval_json_hashed`: Evaluate to JSON along with a stable 64-bit hash of it
+//! - `nickel_eval_diff`: Evaluate and return a JSON Patch from a previous JSON result
+//! - `nickel_eval_json_max_string`: Evaluate to JSON, failing if any string exceeds a length limit
+//! - `nickel_eval_json_tagged`: Evaluate to JSON with enums tagged as `{"$enum": ...}` objects
+//! - `nickel_eval_json_flatten_enums`: Evaluate to JSON with enum variant fields flattened
+//! - `nickel_eval_strict`: Evaluate to JSON, rejecting fields not declared by a record's contract
+//! - `nickel_eval_json_validated`: Evaluate to JSON after checking the result against a contract
+//! - `nickel_eval_validate_schema`: Evaluate to JSON and validate the result against a JSON Schema
+//! - `nickel_eval_field`: Evaluate only the field at a dot-separated path and return JSON
+//! - `nickel_eval_prefix`: Evaluate a record to JSON keeping only the fields under a path prefix
+//! - `nickel_query`: Get the value, type, contracts and documentation of a field as JSON
+//! - `nickel_query_doc`: Get the `doc` metadata of the field at a dot-separated path
+//! - `nickel_eval_with_json_input`: Evaluate with a JSON document bound to the variable `input`
+//! - `nickel_apply`: Apply a Nickel function to JSON arguments and return JSON
+//! - `nickel_partial_apply`: Apply a Nickel function to some JSON arguments, keeping the rest
+//!   for later calls
+//! - `nickel_eval_i64`, `nickel_eval_f64`, `nickel_eval_bool`: Evaluate to a single scalar
+//!   without allocating a result
+//! - `nickel_eval_raw_string`: Evaluate to a string and return its contents without JSON quoting
+//! - `nickel_validate`: Check that code evaluates without a contract failure, as a tri-state
+//! - `nickel_eval_ok`: Check that code evaluates, without serializing the result
+//! - `nickel_record_keys`: Get the top-level field names of a record without evaluating them
+//! - `nickel_leaf_paths`: List the dot-separated paths to every leaf of a record
+//! - `nickel_eval_shape`: Get the type tag and length of a value without forcing its contents
+//! - `nickel_result_kind`: Get the type tag of a value without forcing its contents
+//! - `nickel_type_tag`: Get the binary protocol tag for a type name
+//! - `nickel_program_new`, `nickel_program_eval_native`, `nickel_program_free`: Parse and
+//!   typecheck once, then evaluate repeatedly through a program handle
+//! - `nickel_program_set_override`, `nickel_program_reeval_json`: Override fields of a
+//!   program handle and evaluate it again to JSON
+//! - `nickel_source_new`, `nickel_source_push`, `nickel_source_eval_json`,
+//!   `nickel_source_free`: Evaluate code that arrives in chunks
+//! - `nickel_evaluator_new`, `nickel_evaluator_add_import_path`, `nickel_evaluator_eval_string`,
+//!   `nickel_evaluator_free`: Evaluate through an object with its own error slot and settings
+//! - `nickel_eval_array_open`, `nickel_array_next`, `nickel_array_free`: Encode a top-level
+//!   array one element at a time
+//! - `nickel_set_rounding_mode`: Choose how numbers are rounded when converted to `f64`
+//! - `nickel_set_strict_numbers`: Fail instead of rounding numbers that can't be converted exactly
+//! - `nickel_set_numeric_keys`: Encode all-digit record keys as integers in binary results
+//! - `nickel_set_always_float`: Encode every number as a float in binary results
+//! - `nickel_set_union_convention`: Encode `{ tag, value }` records as enum variants in binary
+//!   results
+//! - `nickel_set_encode_mode`: Fail on values without a native form, or encode placeholders
+//! - `nickel_set_color_diagnostics`: Keep ANSI color codes in error messages
+//! - `nickel_set_eval_fuel`: Bound the number of steps an evaluation may take
+//! - `nickel_set_progress_callback`: Get called back periodically during long evaluations
+//! - `nickel_set_max_elements`: Limit the size of arrays and records in binary results
+//! - `nickel_set_max_buffer_bytes`: Limit the total size of binary results
+//! - `nickel_set_max_json_bytes`: Limit the size of JSON results
+//! - `nickel_set_prelude`, `nickel_clear_prelude`: Make helper definitions available to
+//!   every evaluation
+//! - `nickel_set_env`, `nickel_clear_env`: Pass string variables to every evaluation as `env`
+//! - `nickel_register_secret`, `nickel_clear_secrets`: Redact values from error messages
+//! - `nickel_set_base_dir`: Set the directory imports in code strings are relative to
+//! - `nickel_set_import_resolver`: Serve imported files from a callback instead of the filesystem
+//! - `nickel_reset`: Clear the last error and restore every setting to its default
+//! - `nickel_list_imports`: List the files a Nickel file imports, transitively
 //! - `nickel_get_error`: Get the last error message
+//! - `nickel_get_error_owned`: Get a copy of the last error message that the caller frees
+//! - `nickel_get_error_len`: Get the length of the last error message in bytes
+//! - `nickel_get_error_position`: Get the source position of the last error
+//! - `nickel_get_contract_failure`: Get the field and message of the last contract violation
+//! - `nickel_get_warnings`: Get the diagnostic messages emitted by the last evaluation
 //! - `nickel_free_string`: Free allocated string memory
 //! - `nickel_free_buffer`: Free allocated binary buffer
+//!
+//! # Determinism
+//!
+//! Evaluation is deterministic: the same code with the same settings always gives
+//! the same result. The Nickel standard library has no source of randomness, so
+//! there is no seed to configure.
 
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
 use std::ffi::{CStr, CString};
 use std::io::Cursor;
 use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
 use std::ptr;
 
-use nickel_lang_core::eval::cache::lazy::CBNCache;
-use nickel_lang_core::program::Program;
+use codespan::{FileId, Files};
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle, Severity};
+use codespan_reporting::term::termcolor::Ansi;
+
+use nickel_lang_core::error::{Error, EvalError, IntoDiagnostics};
+use nickel_lang_core::position::{RawSpan, TermPos};
+use nickel_lang_core::label::Label;
+use nickel_lang_core::eval::cache::lazy::{CBNCache, ThunkUpdateFrame};
+use nickel_lang_core::eval::cache::{BlackholedError, Cache as EvalCache, CacheIndex};
+use nickel_lang_core::eval::{Closure, VirtualMachine};
+use nickel_lang_core::cache::{Cache, CacheError, CacheOp, ErrorTolerance, InputFormat, SourcePath};
+use nickel_lang_core::program::{FieldPath, Program};
+use nickel_lang_core::repl::{EvalResult, Repl, ReplImpl};
 use nickel_lang_core::serialize::{self, ExportFormat};
-use nickel_lang_core::term::{RichTerm, Term};
+use nickel_lang_core::identifier::{Ident, LocIdent};
+use nickel_lang_core::term::array::Array;
+use nickel_lang_core::term::record::{Field, FieldDeps, RecordData};
+use nickel_lang_core::term::make::{self as mk_term, builder};
+use nickel_lang_core::term::{
+    BinaryOp, BindingType, MergePriority, RichTerm, Term, Traverse, TraverseControl,
+};
+use nickel_lang_core::typ::{Type, TypeF};
+
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
 
 use malachite::rounding_modes::RoundingMode;
-use malachite::num::conversion::traits::RoundingFrom;
+use malachite::num::conversion::traits::{IsInteger, RoundingFrom};
 
 // Thread-local storage for the last error message
 thread_local! {
     static LAST_ERROR: std::cell::RefCell<Option<CString>> = const { std::cell::RefCell::new(None) };
 }
 
-// Type tags for binary protocol
-const TYPE_NULL: u8 = 0;
-const TYPE_BOOL: u8 = 1;
-const TYPE_INT: u8 = 2;
-const TYPE_FLOAT: u8 = 3;
-const TYPE_STRING: u8 = 4;
-const TYPE_ARRAY: u8 = 5;
-const TYPE_RECORD: u8 = 6;
-const TYPE_ENUM: u8 = 7;
+// Thread-local storage for the source position of the last error, if known.
+// `PENDING_ERROR_POSITION` is filled while a diagnostic is rendered and moved to
+// `LAST_ERROR_POSITION` by the next `set_error`.
+thread_local! {
+    static LAST_ERROR_POSITION: RefCell<Option<ErrorPosition>> = const { RefCell::new(None) };
+    static PENDING_ERROR_POSITION: RefCell<Option<ErrorPosition>> = const { RefCell::new(None) };
+}
+
+// Thread-local storage for the details of the last error, if it was a contract
+// violation. Filled and moved like the error position.
+thread_local! {
+    static LAST_CONTRACT_FAILURE: RefCell<Option<ContractFailure>> = const { RefCell::new(None) };
+    static PENDING_CONTRACT_FAILURE: RefCell<Option<ContractFailure>> = const { RefCell::new(None) };
+}
+
+// Thread-local storage for diagnostic output written by the evaluator (e.g. `std.trace`).
+// `WARNINGS` is cleared whenever a new program is created; `LAST_WARNINGS` keeps the
+// string handed out by `nickel_get_warnings` alive.
+thread_local! {
+    static WARNINGS: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static LAST_WARNINGS: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// Thread-local prelude, stored as the text wrapped around every evaluated snippet.
+thread_local! {
+    static PRELUDE: RefCell<Option<Prelude>> = const { RefCell::new(None) };
+}
+
+// Thread-local string variables bound as the `env` record in evaluated snippets.
+thread_local! {
+    static ENV: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// Thread-local values replaced with `***` in error messages, longest first.
+thread_local! {
+    static SECRETS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+// Thread-local directory that imports in evaluated snippets are relative to.
+thread_local! {
+    static BASE_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+// Thread-local rounding mode used when converting Nickel numbers to `f64`.
+thread_local! {
+    static ROUNDING_MODE: std::cell::Cell<RoundingMode> = const { std::cell::Cell::new(RoundingMode::Nearest) };
+}
+
+// Thread-local limit on the size of collections encoded to the binary protocol.
+thread_local! {
+    static MAX_ELEMENTS: std::cell::Cell<u64> = const { std::cell::Cell::new(u64::MAX) };
+}
+
+// Thread-local limit on the total size of binary results.
+thread_local! {
+    static MAX_BUFFER_BYTES: std::cell::Cell<usize> = const { std::cell::Cell::new(usize::MAX) };
+}
+
+// Thread-local limit on the size of JSON results.
+thread_local! {
+    static MAX_JSON_BYTES: std::cell::Cell<usize> = const { std::cell::Cell::new(usize::MAX) };
+}
+
+// Thread-local evaluation budget: the steps each evaluation may take, and the
+// steps taken by the current one.
+thread_local! {
+    static EVAL_FUEL: std::cell::Cell<u64> = const { std::cell::Cell::new(u64::MAX) };
+    static EVAL_STEPS: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+// Thread-local callback reporting the steps taken by a long evaluation.
+thread_local! {
+    static PROGRESS_CALLBACK: std::cell::Cell<Option<NickelProgressCallback>> = const { std::cell::Cell::new(None) };
+}
+
+/// Callback receiving the number of steps taken so far. See
+/// `nickel_set_progress_callback`.
+pub type NickelProgressCallback = extern "C" fn(forced: u64);
+
+/// Number of steps between two calls to the progress callback.
+const PROGRESS_INTERVAL: u64 = 10_000;
+
+// Thread-local flag making number conversions that lose precision an error.
+thread_local! {
+    static STRICT_NUMBERS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Thread-local flag encoding every number as a float, integers included.
+thread_local! {
+    static ALWAYS_FLOAT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Thread-local flag encoding all-digit record keys as integers.
+thread_local! {
+    static NUMERIC_KEYS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Thread-local flag encoding `{ tag = 'Foo, value = .. }` records as enum variants.
+thread_local! {
+    static UNION_CONVENTION: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Thread-local flag replacing values the binary protocol can't encode with
+// `TYPE_UNSUPPORTED` placeholders instead of failing.
+thread_local! {
+    static UNSUPPORTED_PLACEHOLDERS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Thread-local flag keeping ANSI color codes in rendered error messages.
+thread_local! {
+    static COLOR_DIAGNOSTICS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Thread-local callback serving the source of imported files.
+thread_local! {
+    static IMPORT_RESOLVER: std::cell::Cell<Option<NickelImportResolver>> = const { std::cell::Cell::new(None) };
+}
+
+/// Callback returning the source text of the file imported at `path`, or NULL if
+/// it doesn't know it. See `nickel_set_import_resolver`.
+pub type NickelImportResolver = extern "C" fn(path: *const c_char) -> *const c_char;
+
+// Type tags for binary protocol, exported to `include/nickel_jl.h`
+pub const TYPE_NULL: u8 = 0;
+pub const TYPE_BOOL: u8 = 1;
+pub const TYPE_INT: u8 = 2;
+pub const TYPE_FLOAT: u8 = 3;
+pub const TYPE_STRING: u8 = 4;
+pub const TYPE_ARRAY: u8 = 5;
+pub const TYPE_RECORD: u8 = 6;
+pub const TYPE_ENUM: u8 = 7;
+pub const TYPE_DATE: u8 = 8;
+pub const TYPE_MISSING: u8 = 9;
+pub const TYPE_FUNCTION: u8 = 10;
+pub const TYPE_PACKED_ARRAY: u8 = 11;
+pub const TYPE_KEYED_RECORD: u8 = 12;
+pub const TYPE_QUANTITY: u8 = 13;
+pub const TYPE_UNSUPPORTED: u8 = 14;
+pub const TYPE_FLOAT32: u8 = 15;
+
+// Leading byte of buffers whose lengths are LEB128 varints. It can't be confused
+// with a type tag, so decoders can tell the two layouts apart.
+pub const PROTOCOL_VARINT: u8 = 0x80;
+
+// Leading byte of buffers that may contain `TYPE_PACKED_ARRAY` values.
+pub const PROTOCOL_PACKED: u8 = 0x81;
+
+// Leading byte of buffers ending with a length and checksum trailer.
+pub const PROTOCOL_CHECKED: u8 = 0x82;
+
+// Size of that trailer: a u64 total length and a u32 CRC-32.
+const CHECKED_TRAILER_LEN: usize = 12;
+
+// Returned by `nickel_eval_shape` on error, in place of a type tag.
+pub const SHAPE_ERROR: u8 = 0xFF;
+
+/// Error message for empty or whitespace-only code.
+const EMPTY_INPUT_ERROR: &str = "Empty input: no Nickel code to evaluate";
+
+/// Name of the contract marking a string field as an ISO-8601 date/time.
+///
+/// A record field whose contract annotation is spelled `Date` (or ends in `.Date`,
+/// e.g. `schema.Date`) and whose value is a string accepted by `is_plain_datetime`
+/// is encoded as `TYPE_DATE`. Other strings stay `TYPE_STRING`.
+const DATE_CONTRACT: &str = "Date";
+
+/// Whether `text` is a date/time that Julia's `DateTime(::String)` reads: `YYYY-MM-DD`,
+/// optionally followed by `THH:MM`, `:SS` and 1 to 3 fractional digits, with valid
+/// field values. Time zones (`Z`, `+02:00`) and finer fractions are rejected, since
+/// `DateTime` can't represent them.
+fn is_plain_datetime(text: &str) -> bool {
+    // A number made of exactly `len` ASCII digits
+    fn number(part: &str, len: usize) -> Option<u32> {
+        if part.len() == len && part.bytes().all(|b| b.is_ascii_digit()) {
+            part.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let mut parts = date.split('-');
+    let (Some(year), Some(month), Some(day), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    let (Some(year), Some(month), Some(day)) = (number(year, 4), number(month, 2), number(day, 2)) else {
+        return false;
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    if day == 0 || day > days {
+        return false;
+    }
+
+    let Some(time) = time else {
+        return true;
+    };
+    let (clock, fraction) = match time.split_once('.') {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (time, None),
+    };
+    let fraction_ok = fraction.is_none_or(|f| (1..=3).contains(&f.len()) && f.bytes().all(|b| b.is_ascii_digit()));
+    let mut parts = clock.split(':');
+    let (Some(hour), Some(minute), second, None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    // Fractional seconds need seconds
+    if fraction.is_some() && second.is_none() {
+        return false;
+    }
+    let second_ok = second.is_none_or(|s| number(s, 2).is_some_and(|s| s < 60));
+    fraction_ok
+        && second_ok
+        && number(hour, 2).is_some_and(|h| h < 24)
+        && number(minute, 2).is_some_and(|m| m < 60)
+}
+
+/// Name of the contract marking a string field as a duration, like `"30s"`.
+///
+/// A string field annotated with `Duration` (or a path ending in `.Duration`) is
+/// encoded as `TYPE_QUANTITY` in seconds when the string is a number, optionally
+/// followed by spaces, and one of the units in `DURATION_UNITS`. Other strings,
+/// like `"PT30S"` or `"1h30m"`, stay `TYPE_STRING`.
+const DURATION_CONTRACT: &str = "Duration";
+
+/// Name of the contract marking a string field as a size in bytes, like `"4GiB"`.
+///
+/// Works like `DURATION_CONTRACT`, with the units in `BYTE_SIZE_UNITS`, and is
+/// encoded as `TYPE_QUANTITY` in bytes.
+const BYTE_SIZE_CONTRACT: &str = "ByteSize";
+
+/// Duration units and their length in seconds.
+const DURATION_UNITS: &[(&str, f64)] = &[
+    ("ns", 1e-9),
+    ("us", 1e-6),
+    ("ms", 1e-3),
+    ("s", 1.0),
+    ("m", 60.0),
+    ("min", 60.0),
+    ("h", 3600.0),
+    ("d", 86400.0),
+];
+
+/// Read a string like `"30s"`, a non-negative decimal number, optionally followed
+/// by spaces, and one of `units`, converted to the base unit with their factors.
+/// Returns `None` for anything else.
+fn parse_quantity(text: &str, units: &[(&str, f64)]) -> Option<f64> {
+    let number_len = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(number_len);
+    let factor = units.iter().find(|(name, _)| *name == unit.trim_start()).map(|(_, f)| *f)?;
+    number.parse::<f64>().ok().map(|magnitude| magnitude * factor)
+}
+
+/// Size units and their size in bytes, decimal and binary.
+const BYTE_SIZE_UNITS: &[(&str, f64)] = &[
+    ("B", 1.0),
+    ("kB", 1e3),
+    ("KB", 1e3),
+    ("MB", 1e6),
+    ("GB", 1e9),
+    ("TB", 1e12),
+    ("KiB", 1024.0),
+    ("MiB", 1048576.0),
+    ("GiB", 1073741824.0),
+    ("TiB", 1099511627776.0),
+];
+
+/// Source position of an error: 1-based line and column, and 0-based byte offset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ErrorPosition {
+    line: u32,
+    col: u32,
+    byte: u32,
+}
+
+/// Details of a contract violation, taken from the blame label before the error
+/// is rendered.
+struct ContractFailure {
+    /// Name of the record field the contract was attached to, empty if none.
+    field: CString,
+    /// Expected contract, the contract's own message if any, and the actual value.
+    message: CString,
+}
+
+impl ContractFailure {
+    fn from_blame(evaluated_arg: Option<&RichTerm>, label: &Label) -> Self {
+        let field = label
+            .field_name
+            .map(|name| name.label().to_string())
+            .unwrap_or_default();
+
+        let mut message = format!("expected `{}`", label.typ);
+        if let Some(custom) = label.diagnostics.iter().rev().find_map(|d| d.message.as_ref()) {
+            message.push_str(": ");
+            message.push_str(custom);
+        }
+        if let Some(value) = evaluated_arg {
+            let _ = write!(message, ", got `{}`", value);
+        }
+
+        ContractFailure {
+            field: CString::new(field.replace('\0', "")).unwrap_or_default(),
+            message: CString::new(message.replace('\0', "")).unwrap_or_default(),
+        }
+    }
+}
+
+/// A Nickel error wrapper that records the primary position of the diagnostic
+/// while it is rendered by `Program::report_as_str`, where the file database is
+/// available to turn byte offsets into lines and columns.
+struct PositionedError {
+    error: Error,
+}
+
+impl IntoDiagnostics<FileId> for PositionedError {
+    fn into_diagnostics(
+        self,
+        files: &mut Files<String>,
+        stdlib_ids: Option<&Vec<FileId>>,
+    ) -> Vec<Diagnostic<FileId>> {
+        if let Error::EvalError(EvalError::BlameError { evaluated_arg, label, .. }) = &self.error {
+            let failure = ContractFailure::from_blame(evaluated_arg.as_ref(), label);
+            PENDING_CONTRACT_FAILURE.with(|f| *f.borrow_mut() = Some(failure));
+        }
+        let diagnostics = self.error.into_diagnostics(files, stdlib_ids);
+
+        let position = primary_position(&diagnostics, files);
+        PENDING_ERROR_POSITION.with(|p| *p.borrow_mut() = position);
+
+        diagnostics
+    }
+}
+
+/// A Nickel error wrapper that converts its diagnostics to JSON objects while they
+/// are rendered by `Program::report_as_str`, for `nickel_eval_diagnostics`.
+struct CollectedError<'a> {
+    error: Error,
+    out: &'a mut Vec<serde_json::Value>,
+}
+
+impl IntoDiagnostics<FileId> for CollectedError<'_> {
+    fn into_diagnostics(
+        self,
+        files: &mut Files<String>,
+        stdlib_ids: Option<&Vec<FileId>>,
+    ) -> Vec<Diagnostic<FileId>> {
+        let diagnostics = self.error.into_diagnostics(files, stdlib_ids);
+
+        for diagnostic in &diagnostics {
+            let severity = match diagnostic.severity {
+                Severity::Bug => "bug",
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Note => "note",
+                Severity::Help => "help",
+            };
+            let label = diagnostic.labels.iter().find(|l| l.style == LabelStyle::Primary);
+            let location = label.and_then(|l| files.location(l.file_id, l.range.start as u32).ok());
+            self.out.push(serde_json::json!({
+                "severity": severity,
+                "message": diagnostic.message,
+                "line": location.as_ref().map(|loc| loc.line.0 + 1),
+                "column": location.as_ref().map(|loc| loc.column.0 + 1),
+                "byte_span": label.map(|l| [l.range.start, l.range.end]),
+            }));
+        }
+
+        diagnostics
+    }
+}
+
+/// A carrier for source spans, resolved to JSON objects with file names while it
+/// is rendered by `Program::report_as_str`, for `nickel_eval_json_with_spans`.
+/// It produces no diagnostics.
+struct SpanFiles<'a> {
+    spans: Vec<(String, RawSpan)>,
+    out: &'a mut serde_json::Map<String, serde_json::Value>,
+}
+
+impl IntoDiagnostics<FileId> for SpanFiles<'_> {
+    fn into_diagnostics(
+        self,
+        files: &mut Files<String>,
+        _stdlib_ids: Option<&Vec<FileId>>,
+    ) -> Vec<Diagnostic<FileId>> {
+        for (pointer, span) in self.spans {
+            let span = serde_json::json!({
+                "file": files.name(span.src_id).to_string_lossy(),
+                "start": span.start.0,
+                "end": span.end.0,
+            });
+            self.out.insert(pointer, span);
+        }
+        Vec::new()
+    }
+}
+
+/// Find the position of the first primary label in a list of diagnostics.
+fn primary_position(diagnostics: &[Diagnostic<FileId>], files: &Files<String>) -> Option<ErrorPosition> {
+    let label = diagnostics
+        .iter()
+        .flat_map(|d| d.labels.iter())
+        .find(|l| l.style == LabelStyle::Primary)?;
+    let location = files.location(label.file_id, label.range.start as u32).ok()?;
+
+    Some(ErrorPosition {
+        line: location.line.0 + 1,
+        col: location.column.0 + 1,
+        byte: label.range.start as u32,
+    })
+}
+
+/// Render a Nickel error as a string, recording its source position for
+/// `nickel_get_error_position`.
+fn report_error(program: &mut Program<FuelCache>, error: Error) -> String {
+    PENDING_ERROR_POSITION.with(|p| *p.borrow_mut() = None);
+    PENDING_CONTRACT_FAILURE.with(|f| *f.borrow_mut() = None);
+    uncolored(program.report_as_str(PositionedError { error }))
+}
+
+/// Remove the ANSI color codes from a rendered diagnostic, unless
+/// `nickel_set_color_diagnostics` asked to keep them.
+fn uncolored(report: String) -> String {
+    if COLOR_DIAGNOSTICS.with(|c| c.get()) {
+        return report;
+    }
+    strip_colors(&report)
+}
+
+/// Remove the ANSI color codes from a rendered diagnostic.
+///
+/// Diagnostics are always rendered with colors; the renderer only emits Select
+/// Graphic Rendition sequences (`ESC [ ... m`), so those are all there is to remove.
+fn strip_colors(report: &str) -> String {
+    let mut plain = String::with_capacity(report.len());
+    let mut chars = report.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the sequence up to and including its final byte
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) && c != '[' {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
 
 /// Result buffer for native evaluation
 #[repr(C)]
@@ -46,8 +618,106 @@ pub struct NativeBuffer {
     pub len: usize,
 }
 
+/// Helper definitions made available to every evaluation by `nickel_set_prelude`.
+///
+/// A snippet `code` is evaluated as `(fun { a, b, .. } => code\n) (prelude)`, with
+/// the pattern opened on the snippet's first line so line numbers in error
+/// messages are unaffected.
+struct Prelude {
+    prefix: String,
+    suffix: String,
+    /// The prelude record and its field names, for binding them one by one.
+    source: String,
+    names: Vec<String>,
+}
+
+/// Iterator over the elements of an evaluated Nickel array.
+///
+/// Opaque to C callers; created by `nickel_eval_array_open` and released with
+/// `nickel_array_free`.
+pub struct NickelArrayIter {
+    elements: Array,
+    next: usize,
+}
+
+/// A parsed Nickel program that can be evaluated repeatedly.
+///
+/// Opaque to C callers; created by `nickel_program_new` and released with
+/// `nickel_program_free`. The parsed and typechecked source is cached, while the
+/// evaluation cache is reset before every run, so evaluating the same handle
+/// again is idempotent.
+///
+/// Overrides set with `nickel_program_set_override` are kept by field path, each
+/// with the source of its value, which is parsed when the override is set. A
+/// `Program` can only accumulate overrides, so the handle drives the virtual
+/// machine itself and merges the current set into the cached program on every
+/// evaluation, without parsing the program again.
+pub struct NickelProgram {
+    vm: VirtualMachine<Cache, FuelCache>,
+    main_id: FileId,
+    overrides: BTreeMap<String, (FieldPath, FileId)>,
+}
+
+/// An evaluator with its own error slot and import paths.
+///
+/// Opaque to C callers; created by `nickel_evaluator_new` and released with
+/// `nickel_evaluator_free`. Unlike the free functions, which report errors through
+/// thread-local state, an evaluator keeps everything it needs to itself, so each
+/// task can own one regardless of the thread it runs on. Thread-local settings
+/// such as the prelude, the fuel limit, the progress callback, colored diagnostics
+/// and the JSON size cap don't apply, and its errors leave `nickel_get_error_position`
+/// and `nickel_get_contract_failure` untouched. An evaluator may move between
+/// threads but must not be used by two threads at once.
+pub struct NickelEvaluator {
+    import_paths: Vec<PathBuf>,
+    last_error: Option<CString>,
+}
+
+/// Nickel source assembled from chunks of bytes.
+///
+/// Opaque to C callers; created by `nickel_source_new`, filled with
+/// `nickel_source_push` and released with `nickel_source_free`. Chunks are kept as
+/// raw bytes and only decoded as UTF-8 once the source is evaluated, so a chunk
+/// boundary may fall inside a multibyte character.
+pub struct NickelSource {
+    bytes: Vec<u8>,
+}
+
+/// A Nickel function applied to some of its arguments.
+///
+/// Opaque to C callers; created by `nickel_partial_apply`, extended with
+/// `nickel_partial_apply_more`, evaluated with `nickel_partial_force` and released
+/// with `nickel_partial_free`. The handle keeps the function applied to the
+/// arguments so far, evaluated to weak head normal form, along with the virtual
+/// machine holding its environment. Each call only applies the new arguments to
+/// it, so neither the function nor earlier arguments are evaluated again.
+pub struct NickelPartial {
+    vm: VirtualMachine<Cache, FuelCache>,
+    head: Closure,
+    applied: usize,
+}
+
 /// Evaluate a Nickel code string and return the result as a JSON string.
 ///
+/// A bare enum `'Foo` is written as the string `"Foo"`, as `nickel export` does. An
+/// enum variant carrying an argument, like `'Error "oops"`, has no plain JSON form
+/// and fails with an error naming its location; `nickel_eval_json_tagged` encodes
+/// both kinds as objects instead.
+///
+/// Nickel numbers are exact rationals and don't remember how they were written, so
+/// `2` and `2.0` are the same number. Numbers with a denominator of 1 are written
+/// as JSON integers, without a decimal point or exponent, as long as they fit in an
+/// `i128`; other numbers are written as decimals. Integers beyond 2^53, which many
+/// JSON parsers read as doubles and round, are also reported through
+/// `nickel_get_warnings`.
+///
+/// Empty or whitespace-only code fails with the error `Empty input: no Nickel code
+/// to evaluate` rather than a parse error; this holds for every entry point taking
+/// code.
+///
+/// A panic inside Nickel is caught and reported as an error rather than unwinding
+/// into the caller.
+///
 /// # Safety
 /// - `code` must be a valid null-terminated C string
 /// - The returned pointer must be freed with `nickel_free_string`
@@ -67,7 +737,7 @@ pub unsafe extern "C" fn nickel_eval_string(code: *const c_char) -> *const c_cha
         }
     };
 
-    match eval_nickel_json(code_str) {
+    match catch_panic(|| eval_nickel_json(code_str)) {
         Ok(json) => {
             match CString::new(json) {
                 Ok(cstr) => cstr.into_raw(),
@@ -84,22 +754,21 @@ pub unsafe extern "C" fn nickel_eval_string(code: *const c_char) -> *const c_cha
     }
 }
 
-/// Evaluate Nickel code and return binary-encoded native types.
+/// Evaluate Nickel code and return the JSON result encoded as UTF-16LE.
 ///
-/// Binary protocol:
-/// - Type tag (1 byte): 0=Null, 1=Bool, 2=Int64, 3=Float64, 4=String, 5=Array, 6=Record
-/// - Value data (varies by type)
+/// The buffer holds `len / 2` little-endian code units, with no byte order mark
+/// and no terminating null.
 ///
 /// # Safety
 /// - `code` must be a valid null-terminated C string
 /// - The returned buffer must be freed with `nickel_free_buffer`
 /// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
 #[no_mangle]
-pub unsafe extern "C" fn nickel_eval_native(code: *const c_char) -> NativeBuffer {
+pub unsafe extern "C" fn nickel_eval_string_utf16(code: *const c_char) -> NativeBuffer {
     let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
 
     if code.is_null() {
-        set_error("Null pointer passed to nickel_eval_native");
+        set_error("Null pointer passed to nickel_eval_string_utf16");
         return null_buffer;
     }
 
@@ -111,12 +780,10 @@ pub unsafe extern "C" fn nickel_eval_native(code: *const c_char) -> NativeBuffer
         }
     };
 
-    match eval_nickel_native(code_str) {
-        Ok(buffer) => {
-            let len = buffer.len();
-            let boxed = buffer.into_boxed_slice();
-            let data = Box::into_raw(boxed) as *mut u8;
-            NativeBuffer { data, len }
+    match eval_nickel_json(code_str) {
+        Ok(json) => {
+            let bytes = json.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            native_buffer_from_vec(bytes)
         }
         Err(e) => {
             set_error(&e);
@@ -125,805 +792,9783 @@ pub unsafe extern "C" fn nickel_eval_native(code: *const c_char) -> NativeBuffer
     }
 }
 
-/// Evaluate a Nickel file and return binary-encoded native types.
+/// Evaluate Nickel code and return the result as minified JSON.
 ///
-/// This function evaluates a Nickel file from the filesystem, which allows
-/// the file to use `import` statements to include other Nickel files.
+/// Same as `nickel_eval_string`, but without indentation or newlines, for
+/// shipping results over the wire.
 ///
 /// # Safety
-/// - `path` must be a valid null-terminated C string containing a file path
-/// - The returned buffer must be freed with `nickel_free_buffer`
-/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
 #[no_mangle]
-pub unsafe extern "C" fn nickel_eval_file_native(path: *const c_char) -> NativeBuffer {
-    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
-
-    if path.is_null() {
-        set_error("Null pointer passed to nickel_eval_file_native");
-        return null_buffer;
+pub unsafe extern "C" fn nickel_eval_json_compact(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_compact");
+        return ptr::null();
     }
 
-    let path_str = match CStr::from_ptr(path).to_str() {
+    let code_str = match CStr::from_ptr(code).to_str() {
         Ok(s) => s,
         Err(e) => {
-            set_error(&format!("Invalid UTF-8 in path: {}", e));
-            return null_buffer;
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
         }
     };
 
-    match eval_nickel_file_native(path_str) {
-        Ok(buffer) => {
-            let len = buffer.len();
-            let boxed = buffer.into_boxed_slice();
-            let data = Box::into_raw(boxed) as *mut u8;
-            NativeBuffer { data, len }
+    match eval_nickel_json_compact(code_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
         }
         Err(e) => {
             set_error(&e);
-            null_buffer
+            ptr::null()
         }
     }
 }
 
-/// Internal function to evaluate Nickel code and return JSON.
-fn eval_nickel_json(code: &str) -> Result<String, String> {
-    let source = Cursor::new(code.as_bytes());
-    let mut program: Program<CBNCache> = Program::new_from_source(source, "<ffi>", std::io::sink())
-        .map_err(|e| format!("Parse error: {}", e))?;
+/// Evaluate Nickel code and return the result as JSON, along with where each value
+/// comes from in the source.
+///
+/// The result is an object `{"value": ..., "$spans": {...}}`, where `value` is the
+/// result of `nickel_eval_string` and `$spans` maps the JSON Pointer (RFC 6901) of
+/// each record field and array element of the first two levels, like `/server` or
+/// `/server/ports`, to `{"file": ..., "start": ..., "end": ...}`. The span is that
+/// of the expression that produced the value, in bytes; a computed value may point
+/// into the standard library. Values without a known position are left out.
+/// Offsets into the code string are shifted by the bindings inserted on its first
+/// line when a prelude or `env` variables are set.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_json_with_spans(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_with_spans");
+        return ptr::null();
+    }
 
-    let result = program
-        .eval_full_for_export()
-        .map_err(|e| program.report_as_str(e))?;
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
 
-    serialize::to_string(ExportFormat::Json, &result)
-        .map_err(|e| format!("Serialization error: {:?}", e))
+    match eval_nickel_json_with_spans(code_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
 }
 
-/// Internal function to evaluate Nickel code and return binary-encoded native types.
-fn eval_nickel_native(code: &str) -> Result<Vec<u8>, String> {
-    let source = Cursor::new(code.as_bytes());
-    let mut program: Program<CBNCache> = Program::new_from_source(source, "<ffi>", std::io::sink())
-        .map_err(|e| format!("Parse error: {}", e))?;
+/// Evaluate Nickel code and return the result as JSON indented by `spaces` spaces.
+///
+/// `nickel_eval_string` always indents by 2 spaces; this lets the output match
+/// another formatter's style. With `spaces` 0 the output is minified, as with
+/// `nickel_eval_json_compact`.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_json_indent(code: *const c_char, spaces: u32) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_indent");
+        return ptr::null();
+    }
 
-    let result = program
-        .eval_full_for_export()
-        .map_err(|e| program.report_as_str(e))?;
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
 
-    let mut buffer = Vec::new();
-    encode_term(&result, &mut buffer)?;
-    Ok(buffer)
+    match eval_nickel_json_indent(code_str, spaces) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
 }
 
-/// Internal function to evaluate a Nickel file and return binary-encoded native types.
-fn eval_nickel_file_native(path: &str) -> Result<Vec<u8>, String> {
-    use std::path::PathBuf;
+/// Evaluate Nickel code to JSON and also return a 64-bit hash of it.
+///
+/// The JSON is the same as from `nickel_eval_string`, whose records are always
+/// written with their fields sorted, so identical results give identical bytes.
+/// The hash is the 64-bit FNV-1a hash of those bytes, without the terminating null,
+/// and is stable across runs, platforms and library versions that produce the same
+/// JSON. It's meant for cache keys, not for security.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out_json` must be valid for writes; on success the string written there must
+///   be freed with `nickel_free_string`, on error it is set to NULL
+/// - `out_hash` must be valid for writes
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_json_hashed(
+    code: *const c_char,
+    out_json: *mut *const c_char,
+    out_hash: *mut u64,
+) -> i32 {
+    if code.is_null() || out_json.is_null() || out_hash.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_hashed");
+        return -1;
+    }
 
-    let file_path = PathBuf::from(path);
-    let mut program: Program<CBNCache> = Program::new_from_file(&file_path, std::io::sink())
-        .map_err(|e| format!("Error loading file: {}", e))?;
+    *out_json = ptr::null();
 
-    let result = program
-        .eval_full_for_export()
-        .map_err(|e| program.report_as_str(e))?;
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
 
-    let mut buffer = Vec::new();
-    encode_term(&result, &mut buffer)?;
-    Ok(buffer)
+    match eval_nickel_json(code_str)
+        .and_then(|json| CString::new(json).map_err(|e| format!("Result contains null byte: {}", e)))
+    {
+        Ok(cstr) => {
+            *out_hash = fnv1a64(cstr.as_bytes());
+            *out_json = cstr.into_raw();
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
 }
 
-/// Encode a Nickel term to binary format
-fn encode_term(term: &RichTerm, buffer: &mut Vec<u8>) -> Result<(), String> {
-    match term.as_ref() {
-        Term::Null => {
-            buffer.push(TYPE_NULL);
+/// Evaluate Nickel code and return the changes from a previous result as a JSON
+/// Patch (RFC 6902).
+///
+/// `previous_json` is typically an earlier result of `nickel_eval_string`. The patch
+/// is an array of `add`, `remove` and `replace` operations that turns it into the
+/// new result, e.g. `[{"op":"replace","path":"/port","value":8080}]`, and is empty
+/// when nothing changed. Records are compared field by field; arrays element by
+/// element, with elements added or removed at the end.
+///
+/// # Safety
+/// - `code` and `previous_json` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_diff(code: *const c_char, previous_json: *const c_char) -> *const c_char {
+    if code.is_null() || previous_json.is_null() {
+        set_error("Null pointer passed to nickel_eval_diff");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
         }
-        Term::Bool(b) => {
-            buffer.push(TYPE_BOOL);
-            buffer.push(if *b { 1 } else { 0 });
+    };
+
+    let previous_str = match CStr::from_ptr(previous_json).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in previous JSON: {}", e));
+            return ptr::null();
         }
-        Term::Num(n) => {
-            // Convert to f64 using nearest rounding mode
-            let (f, _) = f64::rounding_from(n, RoundingMode::Nearest);
-            // Try to represent as integer if possible
-            if f.fract() == 0.0 && f >= i64::MIN as f64 && f <= i64::MAX as f64 {
-                buffer.push(TYPE_INT);
-                buffer.extend_from_slice(&(f as i64).to_le_bytes());
-            } else {
-                buffer.push(TYPE_FLOAT);
-                buffer.extend_from_slice(&f.to_le_bytes());
+    };
+
+    match eval_nickel_diff(code_str, previous_str) {
+        Ok(patch) => match CString::new(patch) {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
             }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
         }
-        Term::Str(s) => {
-            buffer.push(TYPE_STRING);
-            let bytes = s.as_str().as_bytes();
-            buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
-            buffer.extend_from_slice(bytes);
-        }
-        Term::Array(arr, _) => {
-            buffer.push(TYPE_ARRAY);
-            buffer.extend_from_slice(&(arr.len() as u32).to_le_bytes());
-            for elem in arr.iter() {
-                encode_term(elem, buffer)?;
-            }
+    }
+}
+
+/// Evaluate Nickel code and return the result as a JSON string, failing if any
+/// string value is longer than `max_chars` characters.
+///
+/// The error message names the path of the offending string (e.g. `users[2].name`)
+/// and its length in characters.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_json_max_string(
+    code: *const c_char,
+    max_chars: usize,
+) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_max_string");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
         }
-        Term::Record(record) => {
-            buffer.push(TYPE_RECORD);
-            let fields: Vec<_> = record.fields.iter().collect();
-            buffer.extend_from_slice(&(fields.len() as u32).to_le_bytes());
-            for (key, field) in fields {
-                // Encode field name
-                let key_bytes = key.label().as_bytes();
-                buffer.extend_from_slice(&(key_bytes.len() as u32).to_le_bytes());
-                buffer.extend_from_slice(key_bytes);
-                // Encode field value
-                if let Some(ref value) = field.value {
-                    encode_term(value, buffer)?;
-                } else {
-                    buffer.push(TYPE_NULL);
+    };
+
+    match eval_nickel_json_max_string(code_str, max_chars) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
                 }
             }
         }
-        Term::Enum(tag) => {
-            // Simple enum without argument
-            // Format: TYPE_ENUM | tag_len (u32) | tag_bytes | has_arg (u8 = 0)
-            buffer.push(TYPE_ENUM);
-            let tag_bytes = tag.label().as_bytes();
-            buffer.extend_from_slice(&(tag_bytes.len() as u32).to_le_bytes());
-            buffer.extend_from_slice(tag_bytes);
-            buffer.push(0); // no argument
-        }
-        Term::EnumVariant { tag, arg, .. } => {
-            // Enum with argument
-            // Format: TYPE_ENUM | tag_len (u32) | tag_bytes | has_arg (u8 = 1) | arg_value
-            buffer.push(TYPE_ENUM);
-            let tag_bytes = tag.label().as_bytes();
-            buffer.extend_from_slice(&(tag_bytes.len() as u32).to_le_bytes());
-            buffer.extend_from_slice(tag_bytes);
-            buffer.push(1); // has argument
-            encode_term(arg, buffer)?;
-        }
-        other => {
-            return Err(format!("Unsupported term type for native encoding: {:?}", other));
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
         }
     }
-    Ok(())
 }
 
-/// Get the last error message.
+/// Evaluate Nickel code and return the result as a JSON string, with enums tagged
+/// so they can be told apart from strings.
+///
+/// A bare enum `'Foo` becomes `{"$enum": "Foo"}` and a variant `'Some 42` becomes
+/// `{"$enum": "Some", "arg": 42}`, at any depth.
 ///
 /// # Safety
-/// - The returned pointer is valid until the next call to any nickel_* function
-/// - Do not free this pointer; it is managed internally
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
 #[no_mangle]
-pub unsafe extern "C" fn nickel_get_error() -> *const c_char {
-    LAST_ERROR.with(|e| {
-        e.borrow()
-            .as_ref()
-            .map(|s| s.as_ptr())
-            .unwrap_or(ptr::null())
-    })
+pub unsafe extern "C" fn nickel_eval_json_tagged(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_tagged");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_json_tagged(code_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
 }
 
-/// Free a string allocated by this library.
+/// Evaluate Nickel code and return the result as a JSON string, with enum variant
+/// fields flattened into their containing record.
+///
+/// A field `status = 'Active { since = 2020 }` becomes `status = "Active"` plus one
+/// field per argument field, named `status<separator>since`. A variant with a
+/// non-record argument, e.g. `count = 'Some 3`, becomes `count = "Some"` and
+/// `count<separator>value = 3`. Bare enums are exported as their tag string.
 ///
 /// # Safety
-/// - `ptr` must have been returned by `nickel_eval_string`
-/// - `ptr` must not be used after this call
-/// - Passing NULL is safe (no-op)
+/// - `code` and `separator` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
 #[no_mangle]
-pub unsafe extern "C" fn nickel_free_string(ptr: *const c_char) {
-    if !ptr.is_null() {
-        drop(CString::from_raw(ptr as *mut c_char));
+pub unsafe extern "C" fn nickel_eval_json_flatten_enums(
+    code: *const c_char,
+    separator: *const c_char,
+) -> *const c_char {
+    if code.is_null() || separator.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_flatten_enums");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let separator_str = match CStr::from_ptr(separator).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in separator: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_json_flatten_enums(code_str, separator_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
     }
 }
 
-/// Free a binary buffer allocated by this library.
+/// Evaluate Nickel code and return JSON, rejecting record fields that no contract declares.
+///
+/// Nickel record contracts are already closed: `{ a = 1, b = 2 } | { a | Number }`
+/// fails on `b`. But fields can still slip past a schema, either through an open
+/// contract (`{ a | Number, .. }`) or by merging a record into one that was
+/// already checked (`({ a = 1 } | Schema) & { b = 2 }`).
+///
+/// The check keys off field metadata, which keeps the annotations of every
+/// contract or type applied to a field: in strict mode, a record in which at least
+/// one field carries an annotation is treated as declared by a schema, and each of
+/// its fields must then carry an annotation too. Records without any annotated
+/// field are left alone. The error names the path of the first undeclared field.
 ///
 /// # Safety
-/// - `buffer` must have been returned by `nickel_eval_native`
-/// - The buffer must not be used after this call
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
 #[no_mangle]
-pub unsafe extern "C" fn nickel_free_buffer(buffer: NativeBuffer) {
-    if !buffer.data.is_null() && buffer.len > 0 {
-        let _ = Box::from_raw(std::slice::from_raw_parts_mut(buffer.data, buffer.len));
+pub unsafe extern "C" fn nickel_eval_strict(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_strict");
+        return ptr::null();
     }
-}
 
-fn set_error(msg: &str) {
-    LAST_ERROR.with(|e| {
-        *e.borrow_mut() = CString::new(msg).ok();
-    });
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_json_strict(code_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Evaluate Nickel code, apply a contract to the whole result, and return the
+/// result as a JSON string only if the contract holds.
+///
+/// `contract_code` is any Nickel expression usable as a contract, such as
+/// `{ port | Number, host | String }`. It is applied to the final value of `code`,
+/// after all merges, as a guardrail independent of annotations inside `code`.
+/// On violation the blame error is reported through `nickel_get_error`.
+///
+/// # Safety
+/// - `code` and `contract_code` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_json_validated(
+    code: *const c_char,
+    contract_code: *const c_char,
+) -> *const c_char {
+    if code.is_null() || contract_code.is_null() {
+        set_error("Null pointer passed to nickel_eval_json_validated");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let contract_str = match CStr::from_ptr(contract_code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in contract: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_json_validated(code_str, contract_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Evaluate Nickel code to JSON and validate the result against a JSON Schema.
+///
+/// `schema_json` is a JSON Schema document, validated with the `jsonschema` crate
+/// in the draft named by its `$schema` (2020-12 by default). References are
+/// resolved within the document; remote `$ref`s can't be fetched and make the
+/// schema invalid. Every violation is reported, one per line, with the path of
+/// the offending value.
+///
+/// # Safety
+/// - `code` and `schema_json` must be valid null-terminated C strings
+///
+/// # Returns
+/// - 0 if the result is valid
+/// - 1 if it isn't; `nickel_get_error` lists the violations
+/// - -1 on any other error, such as an evaluation error or an invalid schema (use
+///   `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_validate_schema(
+    code: *const c_char,
+    schema_json: *const c_char,
+) -> i32 {
+    if code.is_null() || schema_json.is_null() {
+        set_error("Null pointer passed to nickel_eval_validate_schema");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    let schema_str = match CStr::from_ptr(schema_json).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in schema: {}", e));
+            return -1;
+        }
+    };
+
+    match eval_nickel_validate_schema(code_str, schema_str) {
+        Ok(violations) if violations.is_empty() => 0,
+        Ok(violations) => {
+            set_error(&format!("JSON Schema validation failed:\n{}", violations.join("\n")));
+            1
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate a flat Nickel record and return it in `.env` format.
+///
+/// Each field becomes a `KEY=value` line, in sorted key order. Booleans are
+/// written as `true` or `false`, numbers as in JSON, null as an empty value and a
+/// bare enum `'Foo` as `Foo`. Values containing anything but letters, digits and
+/// `_-.,:/@%+=` are single-quoted for the shell, with embedded quotes written as
+/// `'\''`. Fails if a field holds a record, an array or another value without a
+/// scalar form, or if a field name isn't a valid environment variable name.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_dotenv(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_dotenv");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_dotenv(code_str) {
+        Ok(dotenv) => match CString::new(dotenv) {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Evaluate Nickel code and write the result to the file at `out_path`, in the
+/// format given by its extension: `.json`, `.yaml` (or `.yml`) or `.toml`.
+///
+/// Other extensions are written as JSON, and a warning saying so is added to
+/// those returned by `nickel_get_warnings`. An existing file is overwritten.
+///
+/// # Safety
+/// - `code` and `out_path` must be valid null-terminated C strings
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details); on
+///   error the file is not written
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_to_path(code: *const c_char, out_path: *const c_char) -> i32 {
+    if code.is_null() || out_path.is_null() {
+        set_error("Null pointer passed to nickel_eval_to_path");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    let path_str = match CStr::from_ptr(out_path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in path: {}", e));
+            return -1;
+        }
+    };
+
+    match eval_nickel_to_path(code_str, Path::new(path_str)) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate a stream of Nickel documents separated by `---` lines and return all
+/// results as one JSON array.
+///
+/// A line consisting only of `---` (trailing whitespace allowed) separates two
+/// documents, as in a YAML stream. Each document is evaluated independently and
+/// contributes one element to the array: `{"value": ...}` on success or
+/// `{"error": "..."}` on failure, so one invalid document doesn't stop the
+/// others. Line numbers in error messages refer to the whole input.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out` must be valid for writes; it is set to NULL on error
+/// - The string written to `out` must be freed with `nickel_free_string`
+///
+/// # Returns
+/// - The number of documents that failed (0 if all succeeded)
+/// - -1 on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_multi(code: *const c_char, out: *mut *const c_char) -> i32 {
+    if code.is_null() || out.is_null() {
+        set_error("Null pointer passed to nickel_eval_multi");
+        return -1;
+    }
+
+    *out = ptr::null();
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match eval_nickel_multi(code_str).and_then(|(json, failures)| {
+        CString::new(json)
+            .map(|json| (json, failures))
+            .map_err(|e| format!("Result contains null byte: {}", e))
+    }) {
+        Ok((json, failures)) => {
+            *out = json.into_raw();
+            failures as i32
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate several Nickel code strings and return each result as a JSON string.
+///
+/// Item `i` of `codes` is evaluated independently, like `nickel_eval_string`, and
+/// its result is written to `out[i]`. A failed item gets a NULL entry in `out`
+/// and, if `errors` isn't NULL, its error message in `errors[i]` (NULL for items
+/// that succeeded). A failed item doesn't stop the others.
+///
+/// # Safety
+/// - `codes` must point to `count` valid null-terminated C strings
+/// - `out` must be valid for writes of `count` pointers
+/// - `errors` must be NULL or valid for writes of `count` pointers
+/// - Every non-NULL string written to `out` or `errors` must be freed with
+///   `nickel_free_string`
+///
+/// # Returns
+/// - The number of items that failed (0 if all succeeded)
+/// - -1 if `codes` or `out` is NULL; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_batch(
+    codes: *const *const c_char,
+    count: usize,
+    out: *mut *const c_char,
+    errors: *mut *const c_char,
+) -> i32 {
+    if count > 0 && (codes.is_null() || out.is_null()) {
+        set_error("Null pointer passed to nickel_eval_batch");
+        return -1;
+    }
+
+    let mut failures = 0;
+    for i in 0..count {
+        let code = *codes.add(i);
+        let result = if code.is_null() {
+            Err("Null pointer passed to nickel_eval_batch".to_string())
+        } else {
+            CStr::from_ptr(code)
+                .to_str()
+                .map_err(|e| format!("Invalid UTF-8 in input: {}", e))
+                .and_then(eval_nickel_json)
+                .and_then(|json| {
+                    CString::new(json).map_err(|e| format!("Result contains null byte: {}", e))
+                })
+        };
+
+        let (value, error) = match result {
+            Ok(cstr) => (cstr.into_raw() as *const c_char, ptr::null()),
+            Err(e) => {
+                failures += 1;
+                (ptr::null(), error_cstring(&e).into_raw() as *const c_char)
+            }
+        };
+
+        *out.add(i) = value;
+        if errors.is_null() {
+            nickel_free_string(error);
+        } else {
+            *errors.add(i) = error;
+        }
+    }
+
+    failures
+}
+
+/// Evaluate several Nickel files merged together and return the result as a JSON string.
+///
+/// The files are combined with `&` in the order given, like `nickel export a.ncl
+/// b.ncl`, so a later file can override the defaults of an earlier one and must
+/// satisfy the contracts it declares. Each file's imports are resolved relative to
+/// that file; relative paths in `paths` are relative to the current directory.
+///
+/// # Safety
+/// - `paths` must point to `count` valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error (including when `count` is 0); use `nickel_get_error`
+///   to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_merge_files(
+    paths: *const *const c_char,
+    count: usize,
+) -> *const c_char {
+    if count > 0 && paths.is_null() {
+        set_error("Null pointer passed to nickel_eval_merge_files");
+        return ptr::null();
+    }
+
+    let mut path_strs = Vec::with_capacity(count);
+    for i in 0..count {
+        let path = *paths.add(i);
+        if path.is_null() {
+            set_error("Null pointer passed to nickel_eval_merge_files");
+            return ptr::null();
+        }
+        match CStr::from_ptr(path).to_str() {
+            Ok(s) => path_strs.push(s),
+            Err(e) => {
+                set_error(&format!("Invalid UTF-8 in path: {}", e));
+                return ptr::null();
+            }
+        }
+    }
+
+    match catch_panic(|| eval_nickel_merge_files(&path_strs)) {
+        Ok(json) => match CString::new(json) {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Evaluate a single field of a Nickel configuration and return it as a JSON string.
+///
+/// `field_path` is a dot-separated path such as `config.database`; segments that
+/// aren't valid identifiers can be quoted (`config."my-key"`). Only the requested
+/// field is fully evaluated and serialized, not the rest of the configuration.
+/// Fails if the path doesn't exist or goes through a value that isn't a record.
+///
+/// # Safety
+/// - `code` and `field_path` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_field(
+    code: *const c_char,
+    field_path: *const c_char,
+) -> *const c_char {
+    if code.is_null() || field_path.is_null() {
+        set_error("Null pointer passed to nickel_eval_field");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let path_str = match CStr::from_ptr(field_path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in field path: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_field(code_str, path_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Evaluate a Nickel record and return only the fields under a path prefix, as JSON.
+///
+/// A field is kept, along with everything below it, when its dot-separated path
+/// starts with `prefix` as a string: `service.` keeps every field of `service`,
+/// `service.port` keeps that field and any sibling such as `service.ports`. Records
+/// on the way to a kept field are kept with only the matching fields, so the result
+/// has the same nesting as the input. An empty prefix keeps everything; a prefix
+/// matching nothing gives `{}`. Fields that don't match are never serialized.
+///
+/// # Safety
+/// - `code` and `prefix` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error (including when the result isn't a record); use
+///   `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_prefix(
+    code: *const c_char,
+    prefix: *const c_char,
+) -> *const c_char {
+    if code.is_null() || prefix.is_null() {
+        set_error("Null pointer passed to nickel_eval_prefix");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let prefix_str = match CStr::from_ptr(prefix).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in prefix: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_prefix(code_str, prefix_str) {
+        Ok(json) => match CString::new(json) {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Get what `nickel query` shows about a field, as a JSON object.
+///
+/// `field_path` uses the same syntax as `nickel_eval_field`. The object has the keys:
+/// - `value`: the fully evaluated value, or null if the field has no definition
+/// - `type`: the type annotation (`x : Number`) as a string, or null
+/// - `contracts`: the contract annotations (`x | Number`) as an array of strings
+/// - `doc`: the documentation (`x | doc "..."`), or null
+///
+/// # Safety
+/// - `code` and `field_path` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_query(code: *const c_char, field_path: *const c_char) -> *const c_char {
+    if code.is_null() || field_path.is_null() {
+        set_error("Null pointer passed to nickel_query");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let path_str = match CStr::from_ptr(field_path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in field path: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match query_nickel_field(code_str, path_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Get the documentation attached to a field with `| doc "..."`.
+///
+/// `field_path` uses the same syntax as `nickel_eval_field`. Only the metadata is
+/// looked up; the field's value isn't evaluated.
+///
+/// # Safety
+/// - `code` and `field_path` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error, including when the field has no documentation; use
+///   `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_query_doc(
+    code: *const c_char,
+    field_path: *const c_char,
+) -> *const c_char {
+    if code.is_null() || field_path.is_null() {
+        set_error("Null pointer passed to nickel_query_doc");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let path_str = match CStr::from_ptr(field_path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in field path: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match query_nickel_doc(code_str, path_str) {
+        Ok(doc) => {
+            match CString::new(doc) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Documentation contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Evaluate Nickel code with a JSON document bound to the variable `input`, and
+/// return the result as a JSON string.
+///
+/// This lets snippets consume data produced elsewhere, e.g. `input.foo + 1`.
+/// The binding is inserted on the first line of the program, so line numbers in
+/// error messages are unaffected.
+///
+/// # Safety
+/// - `code` and `input_json` must be valid null-terminated C strings
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_with_json_input(
+    code: *const c_char,
+    input_json: *const c_char,
+) -> *const c_char {
+    if code.is_null() || input_json.is_null() {
+        set_error("Null pointer passed to nickel_eval_with_json_input");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let json_str = match CStr::from_ptr(input_json).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input JSON: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_json_with_input(code_str, json_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Apply a Nickel function to arguments given as JSON and return the result as JSON.
+///
+/// `func_code` must evaluate to a function and `args_json` must be a JSON array;
+/// its elements are passed as arguments from left to right, e.g. `fun x y => x - y`
+/// applied to `[5, 2]` gives `3`. The call must use exactly as many arguments as
+/// the function takes: supplying more fails, and so does supplying fewer, since the
+/// result would itself be a function.
+///
+/// # Safety
+/// - `func_code` and `args_json` must be valid null-terminated C strings
+/// - `out_json` must be valid for writes; it is set to NULL on error
+/// - The string written to `out_json` must be freed with `nickel_free_string`
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_apply(
+    func_code: *const c_char,
+    args_json: *const c_char,
+    out_json: *mut *const c_char,
+) -> i32 {
+    if func_code.is_null() || args_json.is_null() || out_json.is_null() {
+        set_error("Null pointer passed to nickel_apply");
+        return -1;
+    }
+
+    *out_json = ptr::null();
+
+    let code_str = match CStr::from_ptr(func_code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    let json_str = match CStr::from_ptr(args_json).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in arguments JSON: {}", e));
+            return -1;
+        }
+    };
+
+    match apply_nickel_function(code_str, json_str)
+        .and_then(|json| CString::new(json).map_err(|e| format!("Result contains null byte: {}", e)))
+    {
+        Ok(json) => {
+            *out_json = json.into_raw();
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Apply a Nickel function to some of its arguments and keep the result for later.
+///
+/// `func_code` must evaluate to a function and `args_json` must be a JSON array,
+/// possibly empty, of arguments passed from left to right. Unlike `nickel_apply`,
+/// fewer arguments than the function takes are fine: the returned handle holds
+/// the partial application, which `nickel_partial_apply_more` extends and
+/// `nickel_partial_force` evaluates. Supplying more arguments than the function
+/// takes fails as with `nickel_apply`.
+///
+/// # Safety
+/// - `func_code` and `args_json` must be valid null-terminated C strings
+/// - The returned handle must be freed with `nickel_partial_free`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_partial_apply(
+    func_code: *const c_char,
+    args_json: *const c_char,
+) -> *mut NickelPartial {
+    if func_code.is_null() || args_json.is_null() {
+        set_error("Null pointer passed to nickel_partial_apply");
+        return ptr::null_mut();
+    }
+
+    let code_str = match CStr::from_ptr(func_code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null_mut();
+        }
+    };
+
+    let json_str = match CStr::from_ptr(args_json).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in arguments JSON: {}", e));
+            return ptr::null_mut();
+        }
+    };
+
+    match new_nickel_partial(code_str, json_str) {
+        Ok(partial) => Box::into_raw(Box::new(partial)),
+        Err(e) => {
+            set_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Apply the partial application in `handle` to more arguments.
+///
+/// `args_json` must be a JSON array of the next arguments. On error the handle is
+/// left as it was, so it can still be used.
+///
+/// # Safety
+/// - `handle` must have been returned by `nickel_partial_apply` and not yet freed
+/// - `args_json` must be a valid null-terminated C string
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_partial_apply_more(
+    handle: *mut NickelPartial,
+    args_json: *const c_char,
+) -> i32 {
+    if handle.is_null() || args_json.is_null() {
+        set_error("Null pointer passed to nickel_partial_apply_more");
+        return -1;
+    }
+
+    let json_str = match CStr::from_ptr(args_json).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in arguments JSON: {}", e));
+            return -1;
+        }
+    };
+
+    clear_warnings();
+    reset_eval_steps();
+    match apply_nickel_arguments(&mut *handle, json_str) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate the partial application in `handle` and return the result as JSON.
+///
+/// Fails if the result is still a function, i.e. if the function hasn't received
+/// all its arguments yet. The handle is kept, so it may be forced again or
+/// applied to more arguments if the result is itself a function.
+///
+/// # Safety
+/// - `handle` must have been returned by `nickel_partial_apply` and not yet freed
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_partial_force(handle: *mut NickelPartial) -> *const c_char {
+    if handle.is_null() {
+        set_error("Null pointer passed to nickel_partial_force");
+        return ptr::null();
+    }
+
+    clear_warnings();
+    reset_eval_steps();
+    match force_nickel_application(&mut *handle)
+        .and_then(|json| CString::new(json).map_err(|e| format!("Result contains null byte: {}", e)))
+    {
+        Ok(json) => json.into_raw(),
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Free a partial application created by `nickel_partial_apply`.
+///
+/// # Safety
+/// - `handle` must have been returned by `nickel_partial_apply`
+/// - The handle must not be used after this call
+/// - Passing NULL is safe (no-op)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_partial_free(handle: *mut NickelPartial) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Evaluate Nickel code and return the binary encoding with varint lengths.
+///
+/// Same as `nickel_eval_native`, except that:
+/// - The buffer starts with a protocol byte, `0x80`, before the first type tag
+/// - Every length prefix (strings, arrays, records, keys, enum tags, dates) is an
+///   unsigned LEB128 varint instead of a little-endian u32
+///
+/// This shrinks payloads made of many short strings, where the 4-byte prefixes
+/// dominate.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_native_varint(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_native_varint");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_native_varint(code_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Evaluate Nickel code and return the binary encoding with packed scalar arrays.
+///
+/// Same as `nickel_eval_native`, except that:
+/// - The buffer starts with a protocol byte, `0x81`, before the first type tag
+/// - A non-empty array whose elements are all booleans, or all integers fitting
+///   in an `i64`, is encoded as `TYPE_PACKED_ARRAY` (11): the tag, the element
+///   type tag (`TYPE_BOOL` or `TYPE_INT`), a u32 element count, then the payload
+///   with no per-element tags. Booleans take one bit each, least significant bit
+///   first, padded to a whole byte; integers take 8 bytes each, little-endian.
+///
+/// Other arrays use the regular `TYPE_ARRAY` layout.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_native_packed(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_native_packed");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_native_packed(code_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Evaluate Nickel code and return the binary encoding with 4-byte floats.
+///
+/// Same as `nickel_eval_native`, except that numbers which aren't integers fitting
+/// in an `i64` are encoded as `TYPE_FLOAT32` (15): the tag, the number rounded to
+/// an `f32` (little-endian) with the configured rounding mode, then a lossy flag
+/// byte set when the `f32` isn't exactly the original number. This saves 4 bytes
+/// per decimal when single precision is enough.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_native_f32(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_native_f32");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_native_f32(code_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Evaluate Nickel code and return the binary encoding with an integrity trailer.
+///
+/// Same as `nickel_eval_native`, except that the buffer starts with a protocol
+/// byte, `0x82`, and ends with a 12-byte trailer: the total length of the buffer
+/// (trailer included) as a u64, then the CRC-32 (IEEE) of the encoded value, the
+/// bytes between the protocol byte and the trailer, as a u32, both little-endian.
+/// A decoder can check both before parsing, so a truncated or corrupted buffer is
+/// rejected up front rather than failing partway through decoding.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_native_checked(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_native_checked");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_native_checked(code_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Evaluate Nickel code and write the binary encoding into a caller-provided buffer.
+///
+/// Uses the same binary protocol as `nickel_eval_native`, but no memory is
+/// allocated for the caller. The encoded length is always written to `out_len`;
+/// if it exceeds `cap`, nothing is copied and 1 is returned so the caller can
+/// retry with a large enough buffer. `buf` may be NULL when `cap` is 0, to query
+/// the size.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `buf` must be valid for writes of `cap` bytes (or NULL if `cap` is 0)
+/// - `out_len` must be a valid pointer to a `usize`
+///
+/// # Returns
+/// - 0 if the encoding was written to `buf`
+/// - 1 if `cap` is too small; `out_len` holds the required size
+/// - -1 on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_native_into(
+    code: *const c_char,
+    buf: *mut u8,
+    cap: usize,
+    out_len: *mut usize,
+) -> i32 {
+    if code.is_null() || out_len.is_null() || (buf.is_null() && cap > 0) {
+        set_error("Null pointer passed to nickel_eval_native_into");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match eval_nickel_native(code_str) {
+        Ok(buffer) => {
+            *out_len = buffer.len();
+            if buffer.len() > cap {
+                return 1;
+            }
+            ptr::copy_nonoverlapping(buffer.as_ptr(), buf, buffer.len());
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate Nickel code and return its JSON value along with evaluation statistics.
+///
+/// The statistics are a JSON object `{"eval_us": 1234, "steps": 5678}` holding the
+/// time spent parsing and evaluating the code, in microseconds, excluding JSON
+/// serialization, and the number of evaluation steps taken, counted as for
+/// `nickel_set_eval_fuel`.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out_json` and `out_stats` must be valid for writes; both are set to NULL on error
+/// - Strings written to `out_json` and `out_stats` must be freed with `nickel_free_string`
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_with_stats(
+    code: *const c_char,
+    out_json: *mut *const c_char,
+    out_stats: *mut *const c_char,
+) -> i32 {
+    if code.is_null() || out_json.is_null() || out_stats.is_null() {
+        set_error("Null pointer passed to nickel_eval_with_stats");
+        return -1;
+    }
+
+    *out_json = ptr::null();
+    *out_stats = ptr::null();
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    let result = eval_nickel_json_with_stats(code_str).and_then(|(json, stats)| {
+        let json = CString::new(json).map_err(|e| format!("Result contains null byte: {}", e))?;
+        let stats = CString::new(stats).map_err(|e| format!("Stats contain null byte: {}", e))?;
+        Ok((json, stats))
+    });
+    match result {
+        Ok((json, stats)) => {
+            *out_json = json.into_raw();
+            *out_stats = stats.into_raw();
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate Nickel code and return both its JSON value and its type.
+///
+/// The type is the one Nickel's typechecker infers for the whole expression, as
+/// if it were annotated with `: _`, e.g. `Number` for `1 + 1`. When the code
+/// doesn't typecheck statically, its apparent type is used instead, which is
+/// `Dyn` unless the code carries a type annotation.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out_json` and `out_type` must be valid for writes; both are set to NULL on error
+/// - Strings written to `out_json` and `out_type` must be freed with `nickel_free_string`
+///
+/// # Returns
+/// - 0 if both the value and the type were written
+/// - 1 if only the type was written, because the value can't be exported to JSON
+///   (e.g. a function); use `nickel_get_error` for the reason
+/// - -1 on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_with_type(
+    code: *const c_char,
+    out_json: *mut *const c_char,
+    out_type: *mut *const c_char,
+) -> i32 {
+    if code.is_null() || out_json.is_null() || out_type.is_null() {
+        set_error("Null pointer passed to nickel_eval_with_type");
+        return -1;
+    }
+
+    *out_json = ptr::null();
+    *out_type = ptr::null();
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    let typ = match infer_nickel_type(code_str).and_then(|typ| {
+        CString::new(typ).map_err(|e| format!("Type contains null byte: {}", e))
+    }) {
+        Ok(typ) => typ,
+        Err(e) => {
+            set_error(&e);
+            return -1;
+        }
+    };
+
+    let result = match eval_nickel_term(code_str) {
+        Ok(result) => result,
+        Err(e) => {
+            set_error(&e);
+            return -1;
+        }
+    };
+
+    *out_type = typ.into_raw();
+    match export_json(&result)
+        .and_then(|json| CString::new(json).map_err(|e| format!("Result contains null byte: {}", e)))
+    {
+        Ok(json) => {
+            *out_json = json.into_raw();
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            1
+        }
+    }
+}
+
+/// Check that Nickel code parses, without typechecking or evaluating it.
+///
+/// This is cheap even for programs that would be expensive to evaluate or would
+/// not terminate. The prelude, if any, is not applied.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - Returns 0 if the code parses, -1 otherwise (use `nickel_get_error` for the
+///   diagnostic and `nickel_get_error_position` for its location)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_check_syntax(code: *const c_char) -> i32 {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_check_syntax");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match check_nickel_syntax(code_str) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Parse Nickel code and print it back in a canonical layout, without evaluating it.
+///
+/// The output is Nickel's own pretty-printing of the parsed program: record fields
+/// are sorted, spacing and line breaks are normalized and lines are wrapped at 80
+/// columns, so snippets differing only in formatting give the same text, ending
+/// with a newline. Comments aren't part of the parsed program and are dropped;
+/// `doc` metadata is kept. The prelude, if any, is not applied.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_normalize(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_normalize");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match normalize_nickel(code_str) {
+        Ok(text) => match CString::new(text) {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Evaluate Nickel code and return the diagnostics it produces as JSON.
+///
+/// The result is a JSON array with one object per diagnostic:
+/// `{"severity": "error", "message": "...", "line": 1, "column": 5, "byte_span": [4, 9]}`.
+/// `severity` is one of `bug`, `error`, `warning`, `note` or `help`. `line` and
+/// `column` are 1-based and `byte_span` is the 0-based byte range of the primary
+/// label in its file; all three are `null` when the diagnostic has no location.
+/// A successful evaluation returns `[]`; the value itself is discarded.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error (only for invalid arguments); use `nickel_get_error` to
+///   retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_diagnostics(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_diagnostics");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_diagnostics(code_str) {
+        Ok(json) => {
+            match CString::new(json) {
+                Ok(cstr) => cstr.into_raw(),
+                Err(e) => {
+                    set_error(&format!("Result contains null byte: {}", e));
+                    ptr::null()
+                }
+            }
+        }
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Evaluate Nickel code and return binary-encoded native types.
+///
+/// Binary protocol:
+/// - Type tag (1 byte): 0=Null, 1=Bool, 2=Int64, 3=Float64, 4=String, 5=Array, 6=Record,
+///   7=Enum, 8=Date, 9=Missing (a record field with no definition), 10=Function
+///   (arity and type signature, in place of the function itself), 12=KeyedRecord
+///   (with `nickel_set_numeric_keys`), 13=Quantity (`Duration` and `ByteSize`
+///   fields) and 14=Unsupported (with `nickel_set_encode_mode`). Tags 11=PackedArray
+///   and 15=Float32 only come from `nickel_eval_native_packed` and
+///   `nickel_eval_native_f32`
+/// - Value data (varies by type)
+/// - Float64 values are followed by a lossy flag (1 byte): 1 if the Nickel number
+///   wasn't exactly representable and was rounded, 0 otherwise
+///
+/// A number is an Int64 when the exact rational is an integer in range, before
+/// any rounding to `f64`. Nickel doesn't keep how a literal was written, so `2`
+/// and `2.0` are the same number and both are encoded as Int64.
+///
+/// A panic inside Nickel is caught and reported as an error rather than unwinding
+/// into the caller.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_native(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_native");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match catch_panic(|| eval_nickel_native(code_str)) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Evaluate Nickel code and return the result encoded as MessagePack.
+///
+/// The value is exported like `nickel_eval_string` (integers stay integers, enum
+/// tags become strings) and fails on the same unrepresentable values, such as
+/// functions.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_msgpack(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_msgpack");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_msgpack(code_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Evaluate Nickel code and return the result encoded as CBOR (RFC 8949).
+///
+/// The value is exported like `nickel_eval_msgpack`: integers are CBOR integers,
+/// other numbers floats (in the smallest width that holds them exactly), enum
+/// tags strings and records maps with string keys in sorted order. Fails on the
+/// same unrepresentable values, such as functions.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_cbor(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_cbor");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_cbor(code_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Evaluate Nickel code and return the result as a protobuf `Value` message.
+///
+/// The message follows `google.protobuf.Value` from `struct.proto`: `null` sets
+/// `null_value`, numbers `number_value` as doubles rounded with the configured
+/// rounding mode, strings `string_value`, booleans `bool_value`, records
+/// `struct_value`, and arrays `list_value`. Enums become a `struct_value` with the
+/// tag as the string field `tag` and, for a variant, its argument as `arg`. Fails
+/// on values without a protobuf form, such as functions.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out` must be valid for writes; it receives a null buffer on error
+/// - The buffer written to `out` must be freed with `nickel_free_buffer`
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_protobuf(code: *const c_char, out: *mut NativeBuffer) -> i32 {
+    if code.is_null() || out.is_null() {
+        set_error("Null pointer passed to nickel_eval_protobuf");
+        return -1;
+    }
+
+    *out = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match eval_nickel_protobuf(code_str) {
+        Ok(buffer) => {
+            *out = native_buffer_from_vec(buffer);
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate Nickel code producing a table and return it as an Arrow IPC stream.
+///
+/// The result must be a non-empty array of records that all have the same fields.
+/// Each field becomes a column, in alphabetical order, whose type is inferred from
+/// its values: `Boolean`, `Int64` (integers fitting in an `i64`), `Float64` (other
+/// numbers, and integers in a column that also has them), or `Utf8`. `null` values
+/// make a column nullable, and a column of nulls only has the `Null` type. Any
+/// other value, such as a nested array or record, is an error. The stream holds the
+/// schema and a single record batch.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_arrow(code: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_arrow");
+        return null_buffer;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_arrow(code_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// List the files imported by a Nickel file, directly or transitively.
+///
+/// Imports are resolved like during evaluation, but nothing is typechecked or
+/// evaluated. The resolved paths are written to `*out` separated by newlines, in
+/// the order they were resolved; each file appears once and the entry point itself
+/// isn't included. Useful for setting up file watches.
+///
+/// # Safety
+/// - `path` must be a valid null-terminated C string containing a file path
+/// - `out` must be valid for writes; the string written there must be freed with
+///   `nickel_free_string`
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_list_imports(path: *const c_char, out: *mut *const c_char) -> i32 {
+    if path.is_null() || out.is_null() {
+        set_error("Null pointer passed to nickel_list_imports");
+        return -1;
+    }
+
+    *out = ptr::null();
+
+    let path_str = match CStr::from_ptr(path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in path: {}", e));
+            return -1;
+        }
+    };
+
+    match list_nickel_imports(path_str)
+        .and_then(|imports| {
+            CString::new(imports.join("\n")).map_err(|e| format!("Path contains null byte: {}", e))
+        }) {
+        Ok(cstr) => {
+            *out = cstr.into_raw();
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Evaluate a Nickel file and return binary-encoded native types.
+///
+/// This function evaluates a Nickel file from the filesystem, which allows
+/// the file to use `import` statements to include other Nickel files.
+///
+/// # Safety
+/// - `path` must be a valid null-terminated C string containing a file path
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_file_native(path: *const c_char) -> NativeBuffer {
+    let null_buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    if path.is_null() {
+        set_error("Null pointer passed to nickel_eval_file_native");
+        return null_buffer;
+    }
+
+    let path_str = match CStr::from_ptr(path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in path: {}", e));
+            return null_buffer;
+        }
+    };
+
+    match eval_nickel_file_native(path_str) {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            null_buffer
+        }
+    }
+}
+
+/// Reject code that is empty or only whitespace with a dedicated message, instead
+/// of the parse error Nickel would report.
+fn check_not_empty(code: &str) -> Result<(), String> {
+    if code.trim().is_empty() {
+        return Err(EMPTY_INPUT_ERROR.to_string());
+    }
+    Ok(())
+}
+
+/// Internal function to create a program from source, with the prelude (if any) in scope.
+fn new_program(code: &str) -> Result<Program<FuelCache>, String> {
+    check_not_empty(code)?;
+    let code = apply_prelude(code);
+    let source = Cursor::new(code.as_bytes());
+    Program::new_from_source(source, snippet_name(), warning_sink())
+        .map_err(|e| format!("Parse error: {}", e))
+}
+
+/// Name of the source of an evaluated snippet. Nickel resolves relative imports
+/// from the directory of the importing source, so this places snippets in the
+/// directory set by `nickel_set_base_dir`.
+fn snippet_name() -> PathBuf {
+    BASE_DIR.with(|dir| match &*dir.borrow() {
+        Some(dir) => dir.join("<ffi>"),
+        None => PathBuf::from("<ffi>"),
+    })
+}
+
+/// Internal function to parse Nickel code, reporting the first syntax error.
+fn check_nickel_syntax(code: &str) -> Result<(), String> {
+    check_not_empty(code)?;
+    let source = Cursor::new(code.as_bytes());
+    let mut program: Program<FuelCache> = Program::new_from_source(source, "<ffi>", warning_sink())
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    program
+        .parse()
+        .map(|_| ())
+        .map_err(|e| report_error(&mut program, e))
+}
+
+/// Internal function to parse Nickel code and pretty-print the parsed program.
+fn normalize_nickel(code: &str) -> Result<String, String> {
+    check_not_empty(code)?;
+    let source = Cursor::new(code.as_bytes());
+    let mut program: Program<FuelCache> = Program::new_from_source(source, "<ffi>", warning_sink())
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    // `pprint_ast` parses leniently, so report syntax errors first
+    program.parse().map_err(|e| report_error(&mut program, e))?;
+    let mut text = Vec::new();
+    program
+        .pprint_ast(&mut text, false)
+        .map_err(|e| report_error(&mut program, e))?;
+    String::from_utf8(text).map_err(|e| format!("Invalid UTF-8 in output: {}", e))
+}
+
+/// Internal function to infer the type of Nickel code, with the prelude in scope.
+fn infer_nickel_type(code: &str) -> Result<String, String> {
+    let mut repl = ReplImpl::<FuelCache>::new(std::io::sink());
+    repl.load_stdlib()
+        .map_err(|e| report_repl_error(&mut repl, e))?;
+
+    let bindings = PRELUDE.with(|p| {
+        p.borrow().as_ref().map(|prelude| {
+            let mut bindings = vec![format!("let __nickel_prelude = (\n{}\n)", prelude.source)];
+            bindings.extend(
+                prelude
+                    .names
+                    .iter()
+                    .map(|name| format!("let {} = __nickel_prelude.{}", name, name)),
+            );
+            bindings
+        })
+    });
+    for binding in bindings.into_iter().flatten() {
+        repl.eval(&binding)
+            .map_err(|e| report_repl_error(&mut repl, e))?;
+    }
+
+    // Ask for the statically inferred type first; code that only typechecks in
+    // walk mode falls back to its apparent type.
+    match repl.typecheck(&format!("({}\n : _)", code)) {
+        Ok(typ) => Ok(typ.to_string()),
+        Err(_) => repl
+            .typecheck(code)
+            .map(|typ| typ.to_string())
+            .map_err(|e| report_repl_error(&mut repl, e)),
+    }
+}
+
+/// Build an error report for an error raised by a REPL backend.
+fn report_repl_error(repl: &mut ReplImpl<FuelCache>, error: Error) -> String {
+    report_cache_error(repl.cache_mut(), error)
+}
+
+/// Build an error report for an error raised while using a `Cache` directly.
+fn report_cache_error(cache: &mut Cache, error: Error) -> String {
+    PENDING_ERROR_POSITION.with(|p| *p.borrow_mut() = None);
+    PENDING_CONTRACT_FAILURE.with(|f| *f.borrow_mut() = None);
+    let stdlib_ids = cache.get_all_stdlib_modules_file_id();
+    let diagnostics = PositionedError { error }.into_diagnostics(cache.files_mut(), stdlib_ids.as_ref());
+    let mut buffer = Ansi::new(Cursor::new(Vec::new()));
+    let config = codespan_reporting::term::Config::default();
+    for diagnostic in &diagnostics {
+        // Writing to an in-memory buffer can't fail
+        let _ = codespan_reporting::term::emit(&mut buffer, &config, cache.files_mut(), diagnostic);
+    }
+    uncolored(String::from_utf8_lossy(&buffer.into_inner().into_inner()).into_owned())
+}
+
+/// Internal function to list the files imported by the Nickel file at `path`,
+/// directly or transitively.
+///
+/// This runs the same import resolution as `Program`, on a bare `Cache`, so
+/// nothing is typechecked or evaluated.
+fn list_nickel_imports(path: &str) -> Result<Vec<String>, String> {
+    let mut cache = Cache::new(ErrorTolerance::Strict);
+    let file_id = cache
+        .add_file(path)
+        .map_err(|e| format!("Error loading file: {}", e))?;
+
+    cache
+        .parse(file_id, InputFormat::Nickel)
+        .map_err(|e| report_cache_error(&mut cache, Error::ParseErrors(e)))?;
+
+    let resolved = match cache.resolve_imports(file_id) {
+        Ok(CacheOp::Done((ids, _))) => ids,
+        Ok(CacheOp::Cached(_)) => Vec::new(),
+        Err(CacheError::Error(e)) => return Err(report_cache_error(&mut cache, Error::ImportError(e))),
+        Err(CacheError::NotParsed) => return Err(format!("File was not parsed: {}", path)),
+    };
+
+    Ok(resolved
+        .into_iter()
+        .map(|id| cache.name(id).to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Internal function to fully evaluate Nickel code to an exportable term.
+fn eval_nickel_term(code: &str) -> Result<RichTerm, String> {
+    let result = match import_resolver() {
+        Some(resolver) => eval_nickel_term_resolved(code, resolver)?,
+        None => {
+            let mut program = new_program(code)?;
+            program
+                .eval_full_for_export()
+                .map_err(|e| report_error(&mut program, e))?
+        }
+    };
+    if strict_numbers() {
+        check_exact_numbers(&result, &mut String::new())?;
+    }
+    Ok(result)
+}
+
+/// Internal function to fully evaluate Nickel code whose imports are served by `resolver`.
+///
+/// `Program` keeps its cache private, so this goes through the REPL backend: the
+/// imported files are added to its cache as in-memory sources before evaluation,
+/// where import resolution finds them without touching the filesystem.
+fn eval_nickel_term_resolved(code: &str, resolver: NickelImportResolver) -> Result<RichTerm, String> {
+    check_not_empty(code)?;
+    let code = apply_prelude(code);
+    let mut repl = ReplImpl::<FuelCache>::new(warning_sink());
+    repl.load_stdlib()
+        .map_err(|e| report_repl_error(&mut repl, e))?;
+
+    let cache = repl.cache_mut();
+    let main_id = cache.add_string(SourcePath::Generated("<ffi>".into()), code.clone());
+    serve_imports(cache, main_id, Path::new(""), resolver, &mut HashSet::new())?;
+
+    match repl.eval_full(&code) {
+        Ok(EvalResult::Evaluated(term)) => Ok(drop_not_exported(&term)),
+        Ok(EvalResult::Bound(id)) => Err(format!("Parse error: `let {}` has no `in` body", id)),
+        Err(e) => Err(report_repl_error(&mut repl, e)),
+    }
+}
+
+/// Add the files imported by the source `file_id`, and by those files in turn, to
+/// `cache` with the text `resolver` returns for them.
+///
+/// Paths are joined to `dir`, the directory of the importing file, like Nickel's
+/// own import resolution does, so that they match the names it looks up. Paths
+/// the resolver doesn't know are left to the filesystem, and sources that don't
+/// parse are left for the evaluation to report.
+fn serve_imports(
+    cache: &mut Cache,
+    file_id: FileId,
+    dir: &Path,
+    resolver: NickelImportResolver,
+    served: &mut HashSet<PathBuf>,
+) -> Result<(), String> {
+    let Ok((term, _)) = cache.parse_nocache(file_id) else {
+        return Ok(());
+    };
+
+    let mut imports = Vec::new();
+    term.traverse_ref(
+        &mut |rt: &RichTerm, _: &()| {
+            if let Term::Import(path) = rt.as_ref() {
+                imports.push(dir.join(path));
+            }
+            TraverseControl::<(), ()>::Continue
+        },
+        &(),
+    );
+
+    for path in imports {
+        if !served.insert(path.clone()) {
+            continue;
+        }
+        if let Some(source) = resolve_import(resolver, &path)? {
+            let id = cache.add_string(SourcePath::Path(path.clone()), source);
+            let parent = path.parent().unwrap_or(Path::new(""));
+            serve_imports(cache, id, parent, resolver, served)?;
+        }
+    }
+    Ok(())
+}
+
+/// Ask `resolver` for the source of `path`, copying the text it returns.
+fn resolve_import(resolver: NickelImportResolver, path: &Path) -> Result<Option<String>, String> {
+    let name = path.to_string_lossy();
+    let c_path = CString::new(name.as_bytes())
+        .map_err(|_| format!("Import path contains a null byte: {}", name))?;
+
+    let source = resolver(c_path.as_ptr());
+    if source.is_null() {
+        return Ok(None);
+    }
+    // The resolver's contract, documented on `nickel_set_import_resolver`, makes a
+    // non-null result a valid C string until the resolver is called again.
+    let source = unsafe { CStr::from_ptr(source) };
+    source
+        .to_str()
+        .map(|s| Some(s.to_string()))
+        .map_err(|e| format!("Invalid UTF-8 in the source of import `{}`: {}", name, e))
+}
+
+/// Evaluate Nickel code that produces an integer and write it to `out`.
+///
+/// The result must be a whole number that fits in an `i64`; no buffer is allocated.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out` must be a valid pointer to an `i64`
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_i64(code: *const c_char, out: *mut i64) -> i32 {
+    eval_scalar_into(code, out, "nickel_eval_i64", |term| match term {
+        Term::Num(n) => i64::try_from(n)
+            .map_err(|_| format!("Expected an integer fitting in Int64, got {}", n)),
+        other => Err(format!("Expected an integer, got {}", term_kind(other))),
+    })
+}
+
+/// Evaluate Nickel code that produces a number and write it to `out` as an `f64`.
+///
+/// Numbers are rounded with the mode set by `nickel_set_rounding_mode`, which
+/// defaults to Nearest; no buffer is allocated.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out` must be a valid pointer to an `f64`
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_f64(code: *const c_char, out: *mut f64) -> i32 {
+    eval_scalar_into(code, out, "nickel_eval_f64", |term| match term {
+        Term::Num(n) => Ok(f64::rounding_from(n, rounding_mode()).0),
+        other => Err(format!("Expected a number, got {}", term_kind(other))),
+    })
+}
+
+/// Evaluate Nickel code that produces a boolean and write it to `out`.
+///
+/// No buffer is allocated.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out` must be a valid pointer to a `bool`
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_bool(code: *const c_char, out: *mut bool) -> i32 {
+    eval_scalar_into(code, out, "nickel_eval_bool", |term| match term {
+        Term::Bool(b) => Ok(*b),
+        other => Err(format!("Expected a boolean, got {}", term_kind(other))),
+    })
+}
+
+/// Evaluate Nickel code that produces a string and return its contents unquoted.
+///
+/// Unlike `nickel_eval_string`, which would return the JSON string `"hello"`, this
+/// returns `hello` as is, like `nickel export --format raw`.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error (including when the result isn't a string); use
+///   `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_raw_string(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_raw_string");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    let result = eval_nickel_term(code_str).and_then(|term| match term.as_ref() {
+        Term::Str(s) => Ok(s.to_string()),
+        other => Err(format!("Expected a string, got {}", term_kind(other))),
+    });
+    match result {
+        Ok(text) => match CString::new(text) {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Check whether Nickel code passes its contracts.
+///
+/// `code` is fully evaluated, typically a value with a contract annotation or a call
+/// like `std.contract.apply MyContract value`, or an expression evaluating to a
+/// boolean. It fails when a contract is broken (including `std.fail_with`) or when
+/// the result is `false`; any other result passes.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+///
+/// # Returns
+/// - 1 if `code` passes
+/// - 0 if it fails; `nickel_get_error` describes the failure, and
+///   `nickel_get_contract_failure` the broken contract, if any
+/// - -1 on any other error, such as a syntax or type error (use `nickel_get_error`
+///   for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_validate(code: *const c_char) -> i32 {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_validate");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match validate_nickel(code_str) {
+        Ok(None) => 1,
+        Ok(Some(failure)) => {
+            set_error(&failure);
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Internal function to validate Nickel code. Returns the failure message if a
+/// contract is broken or the result is `false`.
+fn validate_nickel(code: &str) -> Result<Option<String>, String> {
+    let mut program = new_program(code)?;
+
+    match program.eval_full_for_export() {
+        Ok(result) => match result.as_ref() {
+            Term::Bool(false) => Ok(Some("Validation failed: the expression evaluated to `false`".to_string())),
+            _ => Ok(None),
+        },
+        Err(e @ Error::EvalError(EvalError::BlameError { .. })) => Ok(Some(report_error(&mut program, e))),
+        Err(e) => Err(report_error(&mut program, e)),
+    }
+}
+
+/// Check that Nickel code evaluates, without serializing the result.
+///
+/// `code` is fully evaluated as for `nickel_eval_string`, so contracts are checked
+/// all the way down, but no JSON string or buffer is built. Useful as a cheap health
+/// check for configurations whose output would be discarded.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - Returns 0 on success, -1 on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_ok(code: *const c_char) -> i32 {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_ok");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match catch_panic(|| eval_nickel_term(code_str)) {
+        Ok(_) => 0,
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Shared implementation of the scalar entry points: evaluate `code`, convert the
+/// result with `extract` and write it to `out`.
+unsafe fn eval_scalar_into<T>(
+    code: *const c_char,
+    out: *mut T,
+    name: &str,
+    extract: impl FnOnce(&Term) -> Result<T, String>,
+) -> i32 {
+    if code.is_null() || out.is_null() {
+        set_error(&format!("Null pointer passed to {}", name));
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match eval_nickel_term(code_str).and_then(|term| extract(term.as_ref())) {
+        Ok(value) => {
+            *out = value;
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Human-readable name of the kind of value `term` holds, for error messages.
+fn term_kind(term: &Term) -> &'static str {
+    match term {
+        Term::Null => "null",
+        Term::Bool(_) => "a boolean",
+        Term::Num(_) => "a number",
+        Term::Str(_) => "a string",
+        Term::Array(..) => "an array",
+        Term::Record(_) => "a record",
+        Term::Enum(_) | Term::EnumVariant { .. } => "an enum",
+        Term::Fun(..) | Term::FunPattern(..) | Term::Match(_) => "a function",
+        Term::CustomContract(_) => "a contract",
+        Term::Type { .. } => "a type",
+        Term::Lbl(_) => "a contract label",
+        Term::Sealed(..) => "a sealed value",
+        Term::ForeignId(_) => "a foreign value",
+        _ => "an unsupported value",
+    }
+}
+
+/// Look up the binary protocol tag for a type name.
+///
+/// Names are the lowercase tag names without the `TYPE_` prefix: `null`, `bool`,
+/// `int`, `float`, `string`, `array`, `record`, `enum`, `date`, `missing`,
+/// `function`, `packed_array`, `keyed_record`, `quantity`, `unsupported` and
+/// `float32`. Decoders can fetch the tags at startup instead of hard-coding them.
+///
+/// # Safety
+/// - `name` must be NULL or a valid null-terminated C string
+/// - Returns the tag, or -1 if `name` is NULL or not a known type name
+#[no_mangle]
+pub unsafe extern "C" fn nickel_type_tag(name: *const c_char) -> i32 {
+    if name.is_null() {
+        return -1;
+    }
+
+    let tag = match CStr::from_ptr(name).to_bytes() {
+        b"null" => TYPE_NULL,
+        b"bool" => TYPE_BOOL,
+        b"int" => TYPE_INT,
+        b"float" => TYPE_FLOAT,
+        b"string" => TYPE_STRING,
+        b"array" => TYPE_ARRAY,
+        b"record" => TYPE_RECORD,
+        b"enum" => TYPE_ENUM,
+        b"date" => TYPE_DATE,
+        b"missing" => TYPE_MISSING,
+        b"function" => TYPE_FUNCTION,
+        b"packed_array" => TYPE_PACKED_ARRAY,
+        b"keyed_record" => TYPE_KEYED_RECORD,
+        b"quantity" => TYPE_QUANTITY,
+        b"unsupported" => TYPE_UNSUPPORTED,
+        b"float32" => TYPE_FLOAT32,
+        _ => return -1,
+    };
+    i32::from(tag)
+}
+
+/// Evaluate Nickel code only to weak head normal form and return its type tag.
+///
+/// Nested values are not forced: for an array or a record, `out_count` receives the
+/// number of elements or fields, but none of them is evaluated. Fields marked
+/// `not_exported` aren't counted, matching `nickel_record_keys`. Numbers are tagged
+/// `TYPE_INT` when they are integers fitting in an `i64` and `TYPE_FLOAT` otherwise,
+/// as in the binary protocol.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - `out_count` may be NULL; otherwise it must be valid for writes and is set to 0
+///   for values other than arrays and records
+/// - Returns one of the `TYPE_*` tags, or `SHAPE_ERROR` (255) on error; use
+///   `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_shape(code: *const c_char, out_count: *mut u64) -> u8 {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_shape");
+        return SHAPE_ERROR;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return SHAPE_ERROR;
+        }
+    };
+
+    match eval_nickel_shape(code_str) {
+        Ok((tag, count)) => {
+            if !out_count.is_null() {
+                *out_count = count as u64;
+            }
+            tag
+        }
+        Err(e) => {
+            set_error(&e);
+            SHAPE_ERROR
+        }
+    }
+}
+
+/// Evaluate Nickel code only to weak head normal form and return its type tag.
+///
+/// Same as `nickel_eval_shape` without the element count, for dispatching on the
+/// kind of a result before choosing how to fetch it.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - Returns one of the `TYPE_*` tags, or -1 on error; use `nickel_get_error` to
+///   retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_result_kind(code: *const c_char) -> i32 {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_result_kind");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match eval_nickel_shape(code_str) {
+        Ok((tag, _)) => i32::from(tag),
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Internal function to evaluate Nickel code to weak head normal form and return
+/// its type tag and element or field count.
+fn eval_nickel_shape(code: &str) -> Result<(u8, usize), String> {
+    let mut program = new_program(code)?;
+    let result = program.eval().map_err(|e| report_error(&mut program, e))?;
+
+    match result.as_ref() {
+        Term::Null => Ok((TYPE_NULL, 0)),
+        Term::Bool(_) => Ok((TYPE_BOOL, 0)),
+        Term::Num(n) if i64::try_from(n).is_ok() => Ok((TYPE_INT, 0)),
+        Term::Num(_) => Ok((TYPE_FLOAT, 0)),
+        Term::Str(_) => Ok((TYPE_STRING, 0)),
+        Term::Array(arr, _) => Ok((TYPE_ARRAY, arr.len())),
+        Term::Record(record) => {
+            let len = record.fields.values().filter(|field| !field.metadata.not_exported).count();
+            Ok((TYPE_RECORD, len))
+        }
+        Term::Enum(_) | Term::EnumVariant { .. } => Ok((TYPE_ENUM, 0)),
+        other => Err(format!("Cannot determine the shape of {}", term_kind(other))),
+    }
+}
+
+/// Evaluate Nickel code to a record and return its top-level field names.
+///
+/// The names are sorted and separated by newlines. The record is only evaluated
+/// to weak head normal form, so field values are never computed. Fields marked
+/// `not_exported` are left out, as they are from exported values.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - Returns a pointer to a null-terminated string that must be freed with `nickel_free_string`
+/// - Returns NULL on error (including when the result isn't a record); use
+///   `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_record_keys(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_record_keys");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_record_keys(code_str) {
+        Ok(keys) => match CString::new(keys.join("\n")) {
+            Ok(c_str) => c_str.into_raw(),
+            Err(e) => {
+                set_error(&format!("Field name contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Internal function to evaluate Nickel code to a record in weak head normal form
+/// and return its sorted exported field names.
+fn eval_nickel_record_keys(code: &str) -> Result<Vec<String>, String> {
+    let mut program = new_program(code)?;
+    let result = program.eval().map_err(|e| report_error(&mut program, e))?;
+
+    match result.as_ref() {
+        Term::Record(record) => {
+            let mut keys: Vec<String> = record
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.metadata.not_exported)
+                .map(|(key, _)| key.label().to_string())
+                .collect();
+            keys.sort();
+            Ok(keys)
+        }
+        other => Err(format!("Expected a record, got {}", term_kind(other))),
+    }
+}
+
+/// Evaluate Nickel code to a record and list the paths to all of its leaves.
+///
+/// A leaf is any value that isn't a record or an array. Paths are dot-separated,
+/// with array elements named by their 0-based index, like `server.hosts.0`, and
+/// are separated by newlines. Record fields are visited in sorted order, array
+/// elements in order; fields marked `not_exported` are left out, and empty records
+/// and arrays have no leaves, so they don't appear.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - Returns a pointer to a null-terminated string that must be freed with `nickel_free_string`
+/// - Returns NULL on error (including when the result isn't a record); use
+///   `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_leaf_paths(code: *const c_char) -> *const c_char {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_leaf_paths");
+        return ptr::null();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match eval_nickel_leaf_paths(code_str) {
+        Ok(paths) => match CString::new(paths.join("\n")) {
+            Ok(c_str) => c_str.into_raw(),
+            Err(e) => {
+                set_error(&format!("Field name contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Internal function to evaluate Nickel code to a record and list the paths to its leaves.
+fn eval_nickel_leaf_paths(code: &str) -> Result<Vec<String>, String> {
+    let result = eval_nickel_term(code)?;
+    if !matches!(result.as_ref(), Term::Record(_)) {
+        return Err(format!("Expected a record, got {}", term_kind(result.as_ref())));
+    }
+
+    let mut paths = Vec::new();
+    collect_leaf_paths(&result, &mut String::new(), &mut paths);
+    Ok(paths)
+}
+
+/// Add the path of every leaf of `term`, found at `path`, to `paths`.
+fn collect_leaf_paths(term: &RichTerm, path: &mut String, paths: &mut Vec<String>) {
+    let mut visit = |segment: &str, child: &RichTerm, path: &mut String| {
+        let prev_len = path.len();
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(segment);
+        collect_leaf_paths(child, path, paths);
+        path.truncate(prev_len);
+    };
+
+    match term.as_ref() {
+        Term::Record(record) => {
+            let mut fields: Vec<_> = record
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.metadata.not_exported)
+                .filter_map(|(key, field)| Some((key.label(), field.value.as_ref()?)))
+                .collect();
+            fields.sort_by_key(|(key, _)| *key);
+            for (key, value) in fields {
+                visit(key, value, path);
+            }
+        }
+        Term::Array(arr, _) => {
+            for (i, elem) in arr.iter().enumerate() {
+                visit(&i.to_string(), elem, path);
+            }
+        }
+        _ => paths.push(path.clone()),
+    }
+}
+
+/// Parse and typecheck Nickel code into a program handle for repeated evaluation.
+///
+/// Syntax and type errors are reported here rather than on the first evaluation.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned handle must be freed with `nickel_program_free`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_program_new(code: *const c_char) -> *mut NickelProgram {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_program_new");
+        return ptr::null_mut();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null_mut();
+        }
+    };
+
+    match new_nickel_program(code_str) {
+        Ok(handle) => Box::into_raw(Box::new(handle)),
+        Err(e) => {
+            set_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Evaluate a program handle and return binary-encoded native types.
+///
+/// Uses the same binary protocol as `nickel_eval_native`. The handle can be
+/// evaluated any number of times and yields the same buffer each time.
+///
+/// # Safety
+/// - `handle` must have been returned by `nickel_program_new` and not yet freed
+/// - The returned buffer must be freed with `nickel_free_buffer`
+/// - Returns NativeBuffer with null data on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_program_eval_native(handle: *mut NickelProgram) -> NativeBuffer {
+    if handle.is_null() {
+        set_error("Null pointer passed to nickel_program_eval_native");
+        return NativeBuffer { data: ptr::null_mut(), len: 0 };
+    }
+
+    clear_warnings();
+    reset_eval_steps();
+    let result = eval_program(&mut *handle).and_then(|term| {
+        let mut buffer = Vec::new();
+        encode_term(&term, &mut buffer)?;
+        Ok(buffer)
+    });
+
+    match result {
+        Ok(buffer) => native_buffer_from_vec(buffer),
+        Err(e) => {
+            set_error(&e);
+            NativeBuffer { data: ptr::null_mut(), len: 0 }
+        }
+    }
+}
+
+/// Override a field of a program handle with a JSON value.
+///
+/// `field_path` is dot-separated, as for `nickel_eval_field`. The value is merged
+/// with force priority, so it replaces whatever the program defines. Setting the
+/// same path again replaces the previous override; other overrides are kept. The
+/// change takes effect on the next evaluation of the handle.
+///
+/// # Safety
+/// - `handle` must have been returned by `nickel_program_new` and not yet freed
+/// - `field_path` and `value_json` must be valid null-terminated C strings
+/// - Returns 0 on success, -1 on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_program_set_override(
+    handle: *mut NickelProgram,
+    field_path: *const c_char,
+    value_json: *const c_char,
+) -> i32 {
+    if handle.is_null() || field_path.is_null() || value_json.is_null() {
+        set_error("Null pointer passed to nickel_program_set_override");
+        return -1;
+    }
+
+    let path_str = match CStr::from_ptr(field_path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    let value_str = match CStr::from_ptr(value_json).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match set_program_override(&mut *handle, path_str, value_str) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Internal function to record an override on a program handle.
+///
+/// The value becomes a source of its own, parsed here rather than on every
+/// evaluation, so overrides can't capture fields of the program.
+fn set_program_override(handle: &mut NickelProgram, path: &str, value_json: &str) -> Result<(), String> {
+    let value: serde_json::Value =
+        serde_json::from_str(value_json).map_err(|e| format!("Invalid JSON value: {}", e))?;
+    let cache = handle.vm.import_resolver_mut();
+    let path = FieldPath::parse(cache, path.to_string()).map_err(|e| {
+        format!("Invalid field path: {}", report_cache_error(cache, Error::ParseErrors(e.into())))
+    })?;
+
+    let mut text = String::new();
+    json_to_nickel(&value, &mut text);
+    let value_id = cache.add_string(SourcePath::Override(path.clone()), text);
+    handle
+        .vm
+        .prepare_eval(value_id)
+        .map_err(|e| report_cache_error(handle.vm.import_resolver_mut(), e))?;
+    handle.overrides.insert(path.to_string(), (path, value_id));
+    Ok(())
+}
+
+/// Internal function to create a program handle, parsing and typechecking `code`.
+fn new_nickel_program(code: &str) -> Result<NickelProgram, String> {
+    check_not_empty(code)?;
+    let mut cache = Cache::new(ErrorTolerance::Strict);
+    let main_id = cache.add_string(SourcePath::Path(snippet_name()), apply_prelude(code));
+
+    let mut vm = VirtualMachine::new(cache, warning_sink());
+    vm.prepare_eval(main_id)
+        .map_err(|e| report_cache_error(vm.import_resolver_mut(), e))?;
+    Ok(NickelProgram {
+        vm,
+        main_id,
+        overrides: BTreeMap::new(),
+    })
+}
+
+/// Internal function to fully evaluate a program handle with its current overrides.
+///
+/// This is what `Program::eval_full_for_export` does, except that the overrides
+/// are merged afresh each time: a record holding them with force priority is
+/// merged with the cached program.
+fn eval_program(handle: &mut NickelProgram) -> Result<RichTerm, String> {
+    let vm = &mut handle.vm;
+    let mut term = vm
+        .prepare_eval(handle.main_id)
+        .map_err(|e| report_cache_error(vm.import_resolver_mut(), e))?;
+
+    if !handle.overrides.is_empty() {
+        let mut record = builder::Record::new();
+        for (path, value_id) in handle.overrides.values() {
+            record = record
+                .path(path.0.iter())
+                .priority(MergePriority::Top)
+                .value(Term::ResolvedImport(*value_id));
+        }
+        term = mk_term::op2(BinaryOp::Merge(Label::default().into()), term, record.build());
+    }
+
+    vm.reset();
+    vm.eval_full_for_export_closure(Closure::atomic_closure(term))
+        .map_err(|e| report_cache_error(vm.import_resolver_mut(), e.into()))
+}
+
+/// Evaluate a program handle, with its current overrides, and return JSON.
+///
+/// # Safety
+/// - `handle` must have been returned by `nickel_program_new` and not yet freed
+/// - The returned string must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_program_reeval_json(handle: *mut NickelProgram) -> *const c_char {
+    if handle.is_null() {
+        set_error("Null pointer passed to nickel_program_reeval_json");
+        return ptr::null();
+    }
+
+    clear_warnings();
+    reset_eval_steps();
+    let result = eval_program(&mut *handle).and_then(|term| export_json(&term));
+
+    match result {
+        Ok(json) => match CString::new(json) {
+            Ok(s) => s.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Free a program handle.
+///
+/// # Safety
+/// - `handle` must have been returned by `nickel_program_new`
+/// - The handle must not be used after this call
+/// - Passing NULL is safe (no-op)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_program_free(handle: *mut NickelProgram) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Create an empty source to push chunks of Nickel code into.
+///
+/// # Safety
+/// - The returned source must be freed with `nickel_source_free`
+#[no_mangle]
+pub unsafe extern "C" fn nickel_source_new() -> *mut NickelSource {
+    Box::into_raw(Box::new(NickelSource { bytes: Vec::new() }))
+}
+
+/// Append `len` bytes of Nickel code to `src`.
+///
+/// The bytes don't need to be valid UTF-8 on their own: a multibyte character may
+/// be split across two pushes.
+///
+/// # Safety
+/// - `src` must have been returned by `nickel_source_new` and not yet freed
+/// - `bytes` must be valid for reads of `len` bytes; it may be NULL only if `len` is 0
+/// - Returns 0 on success, -1 on error; use `nickel_get_error` for message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_source_push(
+    src: *mut NickelSource,
+    bytes: *const u8,
+    len: usize,
+) -> i32 {
+    if src.is_null() || (bytes.is_null() && len > 0) {
+        set_error("Null pointer passed to nickel_source_push");
+        return -1;
+    }
+
+    if len > 0 {
+        (*src).bytes.extend_from_slice(std::slice::from_raw_parts(bytes, len));
+    }
+    0
+}
+
+/// Evaluate the code pushed into `src` so far and return the result as a JSON string.
+///
+/// Same as `nickel_eval_string` on the concatenated chunks. The chunks are kept, so
+/// more may be pushed and the source evaluated again. A multibyte character left
+/// incomplete by the last chunk is reported as invalid UTF-8.
+///
+/// # Safety
+/// - `src` must have been returned by `nickel_source_new` and not yet freed
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_source_eval_json(src: *const NickelSource) -> *const c_char {
+    if src.is_null() {
+        set_error("Null pointer passed to nickel_source_eval_json");
+        return ptr::null();
+    }
+
+    let code_str = match std::str::from_utf8(&(*src).bytes) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null();
+        }
+    };
+
+    match catch_panic(|| eval_nickel_json(code_str)) {
+        Ok(json) => match CString::new(json) {
+            Ok(cstr) => cstr.into_raw(),
+            Err(e) => {
+                set_error(&format!("Result contains null byte: {}", e));
+                ptr::null()
+            }
+        },
+        Err(e) => {
+            set_error(&e);
+            ptr::null()
+        }
+    }
+}
+
+/// Free a source created by `nickel_source_new`.
+///
+/// # Safety
+/// - `src` must have been returned by `nickel_source_new`
+/// - The source must not be used after this call
+/// - Passing NULL is safe (no-op)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_source_free(src: *mut NickelSource) {
+    if !src.is_null() {
+        drop(Box::from_raw(src));
+    }
+}
+
+/// Create an evaluator with no import paths.
+///
+/// # Safety
+/// - The returned evaluator must be freed with `nickel_evaluator_free`
+#[no_mangle]
+pub unsafe extern "C" fn nickel_evaluator_new() -> *mut NickelEvaluator {
+    Box::into_raw(Box::new(NickelEvaluator {
+        import_paths: Vec::new(),
+        last_error: None,
+    }))
+}
+
+/// Add a directory searched by `import` statements evaluated by `ev`.
+///
+/// # Safety
+/// - `ev` must have been returned by `nickel_evaluator_new` and not yet freed
+/// - `path` must be a valid null-terminated C string
+/// - Returns 0 on success, -1 on error; errors about a null `ev` are reported
+///   through `nickel_get_error`, others through the evaluator
+#[no_mangle]
+pub unsafe extern "C" fn nickel_evaluator_add_import_path(
+    ev: *mut NickelEvaluator,
+    path: *const c_char,
+) -> i32 {
+    if ev.is_null() {
+        set_error("Null pointer passed to nickel_evaluator_add_import_path");
+        return -1;
+    }
+
+    let ev = &mut *ev;
+    if path.is_null() {
+        ev.set_error("Null pointer passed to nickel_evaluator_add_import_path");
+        return -1;
+    }
+
+    match CStr::from_ptr(path).to_str() {
+        Ok(s) => {
+            ev.import_paths.push(PathBuf::from(s));
+            0
+        }
+        Err(e) => {
+            ev.set_error(&format!("Invalid UTF-8 in path: {}", e));
+            -1
+        }
+    }
+}
+
+/// Evaluate a Nickel code string with `ev` and return the result as a JSON string.
+///
+/// On error, NULL is returned and, if `err_out` isn't NULL, `*err_out` is set to
+/// the error message. On success `*err_out` is set to NULL.
+///
+/// # Safety
+/// - `ev` must have been returned by `nickel_evaluator_new` and not yet freed
+/// - `code` must be a valid null-terminated C string
+/// - `err_out` must be NULL or valid for writes
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - The error message is owned by `ev` and valid until its next call; do not free it
+#[no_mangle]
+pub unsafe extern "C" fn nickel_evaluator_eval_string(
+    ev: *mut NickelEvaluator,
+    code: *const c_char,
+    err_out: *mut *const c_char,
+) -> *const c_char {
+    if !err_out.is_null() {
+        *err_out = ptr::null();
+    }
+
+    if ev.is_null() {
+        set_error("Null pointer passed to nickel_evaluator_eval_string");
+        return ptr::null();
+    }
+
+    let ev = &mut *ev;
+    ev.last_error = None;
+
+    let result = if code.is_null() {
+        Err("Null pointer passed to nickel_evaluator_eval_string".to_string())
+    } else {
+        CStr::from_ptr(code)
+            .to_str()
+            .map_err(|e| format!("Invalid UTF-8 in input: {}", e))
+            .and_then(|code_str| ev.eval_json(code_str))
+            .and_then(|json| {
+                CString::new(json).map_err(|e| format!("Result contains null byte: {}", e))
+            })
+    };
+
+    match result {
+        Ok(cstr) => cstr.into_raw(),
+        Err(e) => {
+            ev.set_error(&e);
+            if !err_out.is_null() {
+                *err_out = ev.error_ptr();
+            }
+            ptr::null()
+        }
+    }
+}
+
+/// Free an evaluator, including its last error message.
+///
+/// # Safety
+/// - `ev` must have been returned by `nickel_evaluator_new`
+/// - The evaluator and any error message it returned must not be used after this call
+/// - Passing NULL is safe (no-op)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_evaluator_free(ev: *mut NickelEvaluator) {
+    if !ev.is_null() {
+        drop(Box::from_raw(ev));
+    }
+}
+
+impl NickelEvaluator {
+    /// Evaluate `code` with this evaluator's import paths and return JSON.
+    fn eval_json(&self, code: &str) -> Result<String, String> {
+        check_not_empty(code)?;
+        let source = Cursor::new(code.as_bytes());
+        // A plain cache rather than `FuelCache`, which counts against the
+        // thread-local fuel limit
+        let mut program: Program<CBNCache> =
+            Program::new_from_source(source, "<ffi>", std::io::sink())
+                .map_err(|e| format!("Parse error: {}", e))?;
+        program.add_import_paths(self.import_paths.iter());
+
+        // Rendered without `PositionedError`, which records the position and
+        // contract failure in thread-local slots
+        let result = program
+            .eval_full_for_export()
+            .map_err(|e| strip_colors(&program.report_as_str(e)))?;
+
+        // Not `export_json`, whose warnings go to the thread-local slot
+        check_no_enum_variants(&result, &mut String::new())?;
+        write_json_within(&ExactIntegers(&result), Some(b"  "), usize::MAX)
+    }
+
+    fn set_error(&mut self, msg: &str) {
+        self.last_error = Some(error_cstring(msg));
+    }
+
+    fn error_ptr(&self) -> *const c_char {
+        self.last_error
+            .as_ref()
+            .map(|s| s.as_ptr())
+            .unwrap_or(ptr::null())
+    }
+}
+
+/// Evaluate Nickel code that produces an array and open an iterator over it.
+///
+/// Elements are encoded one at a time by `nickel_array_next`, so the caller never
+/// holds a buffer for the whole array. Fails if the result isn't an array.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - The returned iterator must be freed with `nickel_array_free`
+/// - Returns NULL on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_eval_array_open(code: *const c_char) -> *mut NickelArrayIter {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_eval_array_open");
+        return ptr::null_mut();
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return ptr::null_mut();
+        }
+    };
+
+    match eval_nickel_array(code_str) {
+        Ok(elements) => Box::into_raw(Box::new(NickelArrayIter { elements, next: 0 })),
+        Err(e) => {
+            set_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Encode the next element of an array iterator into `out`.
+///
+/// Each element uses the same binary protocol as `nickel_eval_native` and must be
+/// freed with `nickel_free_buffer`.
+///
+/// # Safety
+/// - `iter` must have been returned by `nickel_eval_array_open` and not yet freed
+/// - `out` must be valid for writes
+///
+/// # Returns
+/// - 0 if an element was written to `out`
+/// - 1 if the iterator is exhausted (`out` gets a null buffer)
+/// - -1 on error; use `nickel_get_error` to retrieve error message
+#[no_mangle]
+pub unsafe extern "C" fn nickel_array_next(iter: *mut NickelArrayIter, out: *mut NativeBuffer) -> i32 {
+    if iter.is_null() || out.is_null() {
+        set_error("Null pointer passed to nickel_array_next");
+        return -1;
+    }
+
+    let iter = &mut *iter;
+    *out = NativeBuffer { data: ptr::null_mut(), len: 0 };
+
+    let Some(elem) = iter.elements.get(iter.next) else {
+        return 1;
+    };
+
+    let mut buffer = Vec::new();
+    let mut encoder = Encoder::new(&mut buffer);
+    encoder.path = format!("[{}]", iter.next);
+    match encoder.encode(elem) {
+        Ok(()) => {
+            iter.next += 1;
+            *out = native_buffer_from_vec(buffer);
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Free an array iterator.
+///
+/// # Safety
+/// - `iter` must have been returned by `nickel_eval_array_open`
+/// - The iterator must not be used after this call
+/// - Passing NULL is safe (no-op)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_array_free(iter: *mut NickelArrayIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+/// Internal function to evaluate Nickel code and return its diagnostics as a JSON array.
+fn eval_nickel_diagnostics(code: &str) -> Result<String, String> {
+    let mut program = new_program(code)?;
+
+    let mut diagnostics = Vec::new();
+    if let Err(error) = program.eval_full_for_export() {
+        // Only the structured form is kept; the rendered text is discarded
+        program.report_as_str(CollectedError { error, out: &mut diagnostics });
+    }
+
+    serde_json::to_string(&diagnostics).map_err(|e| format!("Serialization error: {}", e))
+}
+
+/// Internal function to evaluate Nickel code and return JSON.
+///
+/// nickel-lang-core's JSON export writes integers outside the `i64` and `u64`
+/// ranges as floats, so the term is serialized through `ExactIntegers` with the
+/// same pretty-printing.
+fn eval_nickel_json(code: &str) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+    export_json(&result)
+}
+
+/// Serialize a fully evaluated term to pretty-printed JSON, as `nickel_eval_string` returns it.
+fn export_json(result: &RichTerm) -> Result<String, String> {
+    check_no_enum_variants(result, &mut String::new())?;
+    warn_unsafe_integers(result, &mut String::new());
+
+    write_json(result, Some(b"  "))
+}
+
+/// Serialize a fully evaluated term to JSON through `ExactIntegers`, indented by
+/// `indent` or on a single line, within the limit set by `nickel_set_max_json_bytes`.
+fn write_json(result: &RichTerm, indent: Option<&[u8]>) -> Result<String, String> {
+    write_capped_json(&ExactIntegers(result), indent)
+}
+
+/// Serialize `value` to JSON like `write_json`, for results built from several
+/// terms, such as the documents of `nickel_eval_multi`.
+fn write_capped_json<T: Serialize>(value: &T, indent: Option<&[u8]>) -> Result<String, String> {
+    write_json_within(value, indent, MAX_JSON_BYTES.with(|m| m.get()))
+}
+
+/// Serialize `value` to JSON, failing once the output grows past `max_bytes`.
+fn write_json_within<T: Serialize>(value: &T, indent: Option<&[u8]>, max_bytes: usize) -> Result<String, String> {
+    let mut out = CappedJson { json: Vec::new(), max_bytes };
+
+    let written = match indent {
+        Some(indent) => {
+            let formatter = PrettyFormatter::with_indent(indent);
+            value.serialize(&mut serde_json::Serializer::with_formatter(&mut out, formatter))
+        }
+        None => value.serialize(&mut serde_json::Serializer::new(&mut out)),
+    };
+    written.map_err(|e| {
+        if e.is_io() {
+            format!("JSON result exceeds the maximum of {} bytes", out.max_bytes)
+        } else {
+            format!("Serialization error: {}", e)
+        }
+    })?;
+    String::from_utf8(out.json).map_err(|e| format!("Serialization error: {}", e))
+}
+
+/// Output of `write_capped_json`, failing as soon as the JSON grows past `max_bytes` so
+/// a result that is too large is never fully materialized.
+struct CappedJson {
+    json: Vec<u8>,
+    max_bytes: usize,
+}
+
+impl std::io::Write for CappedJson {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.json.len().saturating_add(buf.len()) > self.max_bytes {
+            return Err(std::io::Error::other("JSON size limit exceeded"));
+        }
+        self.json.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Internal function to evaluate the merge of the Nickel files at `paths` and return JSON.
+fn eval_nickel_merge_files(paths: &[&str]) -> Result<String, String> {
+    if paths.is_empty() {
+        return Err("No files to merge".to_string());
+    }
+
+    // The merge is a generated source importing each file, so pin relative paths
+    // to the current directory rather than leave them to import resolution
+    let paths = paths
+        .iter()
+        .map(|path| std::path::absolute(path).map_err(|e| format!("Error loading file {}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut program: Program<FuelCache> = Program::new_from_files(paths, warning_sink())
+        .map_err(|e| format!("Error loading file: {}", e))?;
+    let result = program
+        .eval_full_for_export()
+        .map_err(|e| report_error(&mut program, e))?;
+    if strict_numbers() {
+        check_exact_numbers(&result, &mut String::new())?;
+    }
+
+    export_json(&result)
+}
+
+/// Largest magnitude up to which every integer has an exact `f64` form, and so
+/// survives JSON consumers that parse all numbers as doubles.
+const MAX_SAFE_INTEGER: u128 = 1 << 53;
+
+/// Serializes a fully evaluated term like nickel-lang-core does, except that
+/// integers which fit in an `i128` are always written as integers.
+struct ExactIntegers<'a>(&'a RichTerm);
+
+impl Serialize for ExactIntegers<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error as _, SerializeMap, SerializeSeq};
+
+        match self.0.as_ref() {
+            Term::Num(n) if n.is_integer() => match i128::try_from(n) {
+                Ok(i) => serializer.serialize_i128(i),
+                Err(_) => self.0.serialize(serializer),
+            },
+            Term::Record(record) => {
+                let mut entries = record
+                    .iter_serializable()
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| S::Error::custom(format!("missing field definition for `{}`", e.id)))?;
+                entries.sort_by_key(|(key, _)| *key);
+
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key.label(), &ExactIntegers(value))?;
+                }
+                map.end()
+            }
+            Term::Array(arr, _) => {
+                let mut seq = serializer.serialize_seq(Some(arr.len()))?;
+                for elem in arr.iter() {
+                    seq.serialize_element(&ExactIntegers(elem))?;
+                }
+                seq.end()
+            }
+            _ => self.0.serialize(serializer),
+        }
+    }
+}
+
+/// JSON assembled around fully evaluated terms, such as the documents of
+/// `nickel_eval_multi`. Terms are written through `ExactIntegers` rather than
+/// converted to a `serde_json::Value`, which can't hold integers beyond 64 bits.
+enum TermJson<'a> {
+    Term(&'a RichTerm),
+    Value(serde_json::Value),
+    Object(BTreeMap<String, TermJson<'a>>),
+    Array(Vec<TermJson<'a>>),
+}
+
+impl Serialize for TermJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TermJson::Term(term) => ExactIntegers(term).serialize(serializer),
+            TermJson::Value(value) => value.serialize(serializer),
+            TermJson::Object(fields) => fields.serialize(serializer),
+            TermJson::Array(items) => items.serialize(serializer),
+        }
+    }
+}
+
+impl<'a> TermJson<'a> {
+    /// An object with the given fields.
+    fn object<const N: usize>(fields: [(&str, TermJson<'a>); N]) -> Self {
+        TermJson::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+}
+
+/// Add a warning for every integer in `term` whose magnitude exceeds 2^53, which
+/// JSON consumers that parse numbers as doubles can't represent exactly.
+fn warn_unsafe_integers(term: &RichTerm, path: &mut String) {
+    match term.as_ref() {
+        Term::Num(n) if n.is_integer() => {
+            let safe = i128::try_from(n).is_ok_and(|i| i.unsigned_abs() <= MAX_SAFE_INTEGER);
+            if !safe {
+                let location = if path.is_empty() { "<root>" } else { path.as_str() };
+                let warning = format!(
+                    "integer {} at `{}` exceeds 2^53 and may lose precision in JSON consumers\n",
+                    n, location
+                );
+                WARNINGS.with(|w| w.borrow_mut().extend_from_slice(warning.as_bytes()));
+            }
+        }
+        Term::Array(arr, _) => {
+            for (i, elem) in arr.iter().enumerate() {
+                let prev_len = path.len();
+                path.push_str(&format!("[{}]", i));
+                warn_unsafe_integers(elem, path);
+                path.truncate(prev_len);
+            }
+        }
+        Term::Record(record) => {
+            for (key, field) in record.fields.iter() {
+                if field.metadata.not_exported {
+                    continue;
+                }
+                if let Some(ref value) = field.value {
+                    let prev_len = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key.label());
+                    warn_unsafe_integers(value, path);
+                    path.truncate(prev_len);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Internal function to evaluate `---`-separated documents and return the JSON
+/// array of their results, with the number of failed documents.
+fn eval_nickel_multi(code: &str) -> Result<(String, usize), String> {
+    let mut documents = Vec::new();
+    let mut current = String::new();
+    for (line_index, line) in code.split_inclusive('\n').enumerate() {
+        if line.trim_end() == "---" {
+            // Blank lines in place of the previous documents keep line numbers
+            documents.push(std::mem::replace(&mut current, "\n".repeat(line_index + 1)));
+        } else {
+            current.push_str(line);
+        }
+    }
+    documents.push(current);
+
+    let terms: Vec<Result<RichTerm, String>> = documents
+        .iter()
+        .map(|document| {
+            let term = eval_nickel_term(document)?;
+            check_no_enum_variants(&term, &mut String::new())?;
+            Ok(term)
+        })
+        .collect();
+
+    // Each evaluation clears the warnings of the previous one, so these come last
+    for (i, term) in terms.iter().enumerate() {
+        if let Ok(term) = term {
+            warn_unsafe_integers(term, &mut format!("[{}]", i));
+        }
+    }
+
+    let failures = terms.iter().filter(|term| term.is_err()).count();
+    let results = terms
+        .iter()
+        .map(|term| match term {
+            Ok(term) => TermJson::object([("value", TermJson::Term(term))]),
+            Err(e) => TermJson::object([("error", TermJson::Value(e.as_str().into()))]),
+        })
+        .collect();
+
+    let json = write_capped_json(&TermJson::Array(results), None)?;
+    Ok((json, failures))
+}
+
+/// Internal function to evaluate Nickel code and write it to `path` in the format
+/// matching its extension.
+fn eval_nickel_to_path(code: &str, path: &Path) -> Result<(), String> {
+    let result = eval_nickel_term(code)?;
+
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let format = match extension.to_ascii_lowercase().as_str() {
+        "json" => ExportFormat::Json,
+        "yaml" | "yml" => ExportFormat::Yaml,
+        "toml" => ExportFormat::Toml,
+        _ => {
+            let warning = format!(
+                "nickel_eval_to_path: unknown extension `{}` for {}, writing JSON\n",
+                extension,
+                path.display()
+            );
+            WARNINGS.with(|w| w.borrow_mut().extend_from_slice(warning.as_bytes()));
+            ExportFormat::Json
+        }
+    };
+
+    let output = serialize::to_string(format, &result)
+        .map_err(|e| format!("Serialization error: {:?}", e))?;
+    std::fs::write(path, output)
+        .map_err(|e| format!("Error writing file {}: {}", path.display(), e))
+}
+
+/// Internal function to evaluate Nickel code and return JSON and timing statistics.
+fn eval_nickel_json_with_stats(code: &str) -> Result<(String, String), String> {
+    let start = std::time::Instant::now();
+    let result = eval_nickel_term(code)?;
+    let eval_us = start.elapsed().as_micros() as u64;
+    let steps = EVAL_STEPS.with(|steps| steps.get());
+
+    let json = export_json(&result)?;
+    let stats = serde_json::json!({ "eval_us": eval_us, "steps": steps }).to_string();
+    Ok((json, stats))
+}
+
+/// Internal function to evaluate Nickel code and return minified JSON.
+///
+/// nickel-lang-core's JSON export is always pretty-printed, so the term is
+/// serialized with `serde_json` directly.
+fn eval_nickel_json_compact(code: &str) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+    warn_unsafe_integers(&result, &mut String::new());
+
+    write_json(&result, None)
+}
+
+/// Internal function to evaluate Nickel code to JSON with the source spans of the
+/// values of the first two levels.
+fn eval_nickel_json_with_spans(code: &str) -> Result<String, String> {
+    let mut program = new_program(code)?;
+
+    let result = program
+        .eval_full_for_export()
+        .map_err(|e| report_error(&mut program, e))?;
+    if strict_numbers() {
+        check_exact_numbers(&result, &mut String::new())?;
+    }
+    check_no_enum_variants(&result, &mut String::new())?;
+    warn_unsafe_integers(&result, &mut String::new());
+
+    let mut spans = Vec::new();
+    collect_spans(&result, "", 2, &mut spans);
+    let mut span_map = serde_json::Map::new();
+    // Only the file names are needed; nothing is rendered
+    program.report_as_str(SpanFiles { spans, out: &mut span_map });
+
+    let json = TermJson::object([
+        ("$spans", TermJson::Value(span_map.into())),
+        ("value", TermJson::Term(&result)),
+    ]);
+    write_capped_json(&json, Some(b"  "))
+}
+
+/// Collect the JSON Pointer and source span of the fields and elements of `term`,
+/// `depth` levels deep.
+fn collect_spans(term: &RichTerm, pointer: &str, depth: usize, out: &mut Vec<(String, RawSpan)>) {
+    if depth == 0 {
+        return;
+    }
+    let mut visit = |key: &str, value: &RichTerm, fallback: TermPos| {
+        let pointer = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+        if let Some(span) = value.pos.into_opt().or(fallback.into_opt()) {
+            out.push((pointer.clone(), span));
+        }
+        collect_spans(value, &pointer, depth - 1, out);
+    };
+
+    match term.as_ref() {
+        Term::Record(record) => {
+            for (key, field) in &record.fields {
+                if let Some(value) = &field.value {
+                    visit(key.label(), value, key.pos);
+                }
+            }
+        }
+        Term::Array(arr, _) => {
+            for (i, elem) in arr.iter().enumerate() {
+                visit(&i.to_string(), elem, TermPos::None);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Internal function to evaluate Nickel code and diff the result against
+/// `previous_json`, returning a JSON Patch.
+fn eval_nickel_diff(code: &str, previous_json: &str) -> Result<String, String> {
+    let previous: serde_json::Value =
+        serde_json::from_str(previous_json).map_err(|e| format!("Invalid previous JSON: {}", e))?;
+    let result = eval_nickel_term(code)?;
+    check_no_enum_variants(&result, &mut String::new())?;
+    warn_unsafe_integers(&result, &mut String::new());
+
+    let mut patch = Vec::new();
+    json_diff(&previous, &result, "", &mut patch)?;
+    write_capped_json(&patch, None)
+}
+
+/// Append to `patch` the JSON Patch operations turning `old` into the JSON form of
+/// the evaluated term `new`, both at the JSON Pointer `pointer`.
+fn json_diff<'a>(
+    old: &serde_json::Value,
+    new: &'a RichTerm,
+    pointer: &str,
+    patch: &mut Vec<TermJson<'a>>,
+) -> Result<(), String> {
+    use serde_json::Value;
+
+    let child = |key: &str| format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+    let op = |op: &str, path: String, value: Option<&'a RichTerm>| {
+        let mut fields = BTreeMap::from([
+            ("op".to_string(), TermJson::Value(op.into())),
+            ("path".to_string(), TermJson::Value(path.into())),
+        ]);
+        if let Some(value) = value {
+            fields.insert("value".to_string(), TermJson::Term(value));
+        }
+        TermJson::Object(fields)
+    };
+
+    match (old, new.as_ref()) {
+        (Value::Object(old_fields), Term::Record(record)) => {
+            let new_fields = record
+                .iter_serializable()
+                .map(|entry| match entry {
+                    Ok((key, value)) => Ok((key.label().to_string(), value)),
+                    Err(e) => Err(format!("Missing field definition for `{}`", e.id)),
+                })
+                .collect::<Result<BTreeMap<_, _>, _>>()?;
+            for (key, old_value) in old_fields {
+                match new_fields.get(key.as_str()) {
+                    Some(new_value) => json_diff(old_value, new_value, &child(key), patch)?,
+                    None => patch.push(op("remove", child(key), None)),
+                }
+            }
+            for (key, new_value) in new_fields {
+                if !old_fields.contains_key(&key) {
+                    patch.push(op("add", child(&key), Some(new_value)));
+                }
+            }
+        }
+        (Value::Array(old_items), Term::Array(new_items, _)) => {
+            for (i, (old_item, new_item)) in old_items.iter().zip(new_items.iter()).enumerate() {
+                json_diff(old_item, new_item, &child(&i.to_string()), patch)?;
+            }
+            // Remove from the end, so each index is still valid when it is applied
+            for i in (new_items.len()..old_items.len()).rev() {
+                patch.push(op("remove", child(&i.to_string()), None));
+            }
+            for (i, new_item) in new_items.iter().enumerate().skip(old_items.len()) {
+                patch.push(op("add", child(&i.to_string()), Some(new_item)));
+            }
+        }
+        _ => {
+            // A value holding an integer beyond 64 bits can't equal a parsed one
+            let unchanged = serde_json::to_value(ExactIntegers(new)).is_ok_and(|new| *old == new);
+            if !unchanged {
+                patch.push(op("replace", pointer.to_string(), Some(new)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Internal function to evaluate Nickel code to JSON indented by `spaces` spaces.
+fn eval_nickel_json_indent(code: &str, spaces: u32) -> Result<String, String> {
+    if spaces == 0 {
+        return eval_nickel_json_compact(code);
+    }
+    let result = eval_nickel_term(code)?;
+    warn_unsafe_integers(&result, &mut String::new());
+
+    let indent = vec![b' '; spaces as usize];
+    write_json(&result, Some(&indent))
+}
+
+/// Internal function to evaluate Nickel code and return JSON, rejecting any string
+/// longer than `max_chars` characters.
+fn eval_nickel_json_max_string(code: &str, max_chars: usize) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+    check_max_string(&result, max_chars, &mut String::new())?;
+
+    export_json(&result)
+}
+
+/// Fail on the first number in `term` that JSON can't hold exactly: anything but
+/// an integer fitting in 64 bits or a number that is exactly an `f64`. Used in
+/// strict numbers mode.
+fn check_exact_numbers(term: &RichTerm, path: &mut String) -> Result<(), String> {
+    match term.as_ref() {
+        Term::Num(n) => {
+            let exact = i64::try_from(n).is_ok()
+                || u64::try_from(n).is_ok()
+                || f64::rounding_from(n, RoundingMode::Nearest).1 == Ordering::Equal;
+            if !exact {
+                let location = if path.is_empty() { "<root>" } else { path.as_str() };
+                return Err(format!(
+                    "Number {} at `{}` can't be represented exactly (strict numbers mode)",
+                    n, location
+                ));
+            }
+        }
+        Term::Array(arr, _) => {
+            for (i, elem) in arr.iter().enumerate() {
+                let prev_len = path.len();
+                let _ = write!(path, "[{}]", i);
+                check_exact_numbers(elem, path)?;
+                path.truncate(prev_len);
+            }
+        }
+        Term::Record(record) => {
+            for (key, field) in record.fields.iter() {
+                if let Some(ref value) = field.value {
+                    let prev_len = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key.label());
+                    check_exact_numbers(value, path)?;
+                    path.truncate(prev_len);
+                }
+            }
+        }
+        Term::EnumVariant { arg, .. } => {
+            check_exact_numbers(arg, path)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Internal function to evaluate Nickel code and return JSON with enum variants
+/// flattened into their parent records.
+fn eval_nickel_json_flatten_enums(code: &str, separator: &str) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+    let flattened = flatten_enums(&result, separator)?;
+
+    export_json(&flattened)
+}
+
+/// Internal function to evaluate Nickel code and return JSON with tagged enums.
+fn eval_nickel_json_tagged(code: &str) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+
+    export_json(&tag_enums(&result))
+}
+
+/// Replace every enum in `term` by a record `{ "$enum" = tag }`, with an `arg`
+/// field for variants.
+fn tag_enums(term: &RichTerm) -> RichTerm {
+    let tagged = |tag: &LocIdent, arg: Option<RichTerm>| {
+        let mut fields = vec![(LocIdent::new("$enum"), RichTerm::from(Term::Str(tag.label().into())))];
+        fields.extend(arg.map(|arg| (LocIdent::new("arg"), arg)));
+        Term::Record(RecordData::with_field_values(fields))
+    };
+
+    let tagged_term = match term.as_ref() {
+        Term::Enum(tag) => tagged(tag, None),
+        Term::EnumVariant { tag, arg, .. } => tagged(tag, Some(tag_enums(arg))),
+        Term::Record(record) => {
+            let fields = record
+                .fields
+                .iter()
+                .filter_map(|(key, field)| Some((*key, tag_enums(field.value.as_ref()?))));
+            Term::Record(RecordData::with_field_values(fields))
+        }
+        Term::Array(arr, attrs) => Term::Array(arr.iter().map(tag_enums).collect(), attrs.clone()),
+        _ => return term.clone(),
+    };
+    RichTerm::new(tagged_term, term.pos)
+}
+
+/// Remove the record fields marked `not_exported` from a fully evaluated term.
+fn drop_not_exported(term: &RichTerm) -> RichTerm {
+    let kept = match term.as_ref() {
+        Term::Record(record) => {
+            // Keep the metadata of the other fields: the binary protocol reads annotations
+            let mut record = record.clone();
+            record.fields.retain(|_, field| !field.metadata.not_exported);
+            for field in record.fields.values_mut() {
+                field.value = field.value.as_ref().map(drop_not_exported);
+            }
+            Term::Record(record)
+        }
+        Term::Array(arr, attrs) => Term::Array(arr.iter().map(drop_not_exported).collect(), attrs.clone()),
+        Term::EnumVariant { tag, arg, attrs } => Term::EnumVariant {
+            tag: *tag,
+            arg: drop_not_exported(arg),
+            attrs: attrs.clone(),
+        },
+        _ => return term.clone(),
+    };
+    RichTerm::new(kept, term.pos)
+}
+
+/// Internal function to evaluate Nickel code with a contract applied to the result.
+///
+/// Both snippets are wrapped in parentheses on their own lines so that trailing
+/// comments in either one can't swallow the annotation.
+fn eval_nickel_json_validated(code: &str, contract_code: &str) -> Result<String, String> {
+    let wrapped = format!("(\n{}\n)\n| (\n{}\n)", code, contract_code);
+    eval_nickel_json(&wrapped)
+}
+
+/// Internal function to evaluate the field at `field_path` and return it as JSON.
+fn eval_nickel_field(code: &str, field_path: &str) -> Result<String, String> {
+    let mut program = new_program(code)?;
+
+    program.field = program
+        .parse_field_path(field_path.to_string())
+        .map_err(|e| report_error(&mut program, Error::ParseErrors(e.into())))?;
+
+    let result = program
+        .eval_full_for_export()
+        .map_err(|e| report_error(&mut program, e))?;
+    if strict_numbers() {
+        check_exact_numbers(&result, &mut String::new())?;
+    }
+
+    export_json(&result)
+}
+
+/// Internal function to evaluate a record and return the fields whose path starts
+/// with `prefix` as JSON.
+fn eval_nickel_prefix(code: &str, prefix: &str) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+    let record = match result.as_ref() {
+        Term::Record(record) => record,
+        other => return Err(format!("Expected a record, got {}", term_kind(other))),
+    };
+
+    let selected = select_prefix(record, &mut String::new(), prefix)?;
+    write_capped_json(&selected, Some(b"  "))
+}
+
+/// Collect the exported fields of `record` whose path, below `path`, starts with
+/// `prefix`, descending into records whose path is itself a prefix of `prefix`.
+fn select_prefix<'a>(
+    record: &'a RecordData,
+    path: &mut String,
+    prefix: &str,
+) -> Result<BTreeMap<String, TermJson<'a>>, String> {
+    let mut selected = BTreeMap::new();
+    for (key, field) in record.fields.iter() {
+        let Some(ref value) = field.value else { continue };
+        if field.metadata.not_exported {
+            continue;
+        }
+
+        let prev_len = path.len();
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(key.label());
+
+        if path.starts_with(prefix) {
+            check_no_enum_variants(value, path)?;
+            warn_unsafe_integers(value, path);
+            selected.insert(key.label().to_string(), TermJson::Term(value));
+        } else if let Term::Record(inner) = value.as_ref() {
+            if prefix.starts_with(path.as_str()) && prefix[path.len()..].starts_with('.') {
+                let nested = select_prefix(inner, path, prefix)?;
+                if !nested.is_empty() {
+                    selected.insert(key.label().to_string(), TermJson::Object(nested));
+                }
+            }
+        }
+        path.truncate(prev_len);
+    }
+    Ok(selected)
+}
+
+/// Internal function to evaluate Nickel code and list the ways its JSON result
+/// violates `schema_json`.
+fn eval_nickel_validate_schema(code: &str, schema_json: &str) -> Result<Vec<String>, String> {
+    let schema: serde_json::Value =
+        serde_json::from_str(schema_json).map_err(|e| format!("Invalid JSON schema: {}", e))?;
+    let validator = jsonschema::validator_for(&schema).map_err(|e| format!("Invalid JSON schema: {}", e))?;
+    let json = eval_nickel_json(code)?;
+    let instance: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| format!("Serialization error: {}", e))?;
+
+    let violations = validator
+        .iter_errors(&instance)
+        .map(|error| {
+            let path = schema_instance_path(&instance, error.instance_path.as_str());
+            let location = if path.is_empty() { "<root>" } else { path.as_str() };
+            format!("`{}`: {}", location, error)
+        })
+        .collect();
+    Ok(violations)
+}
+
+/// Turn the JSON pointer `pointer` into `instance` into a path like `tags[1]`,
+/// using the instance to tell array indices from record keys.
+fn schema_instance_path(instance: &serde_json::Value, pointer: &str) -> String {
+    let mut path = String::new();
+    let mut value = Some(instance);
+    for segment in pointer.split('/').skip(1) {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        match value {
+            Some(serde_json::Value::Array(elements)) => {
+                let _ = write!(path, "[{}]", segment);
+                value = segment.parse::<usize>().ok().and_then(|i| elements.get(i));
+            }
+            _ => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&segment);
+                value = value.and_then(|v| v.get(segment.as_str()));
+            }
+        }
+    }
+    path
+}
+
+/// Internal function to evaluate a flat record and render it as `KEY=value` lines.
+fn eval_nickel_dotenv(code: &str) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+    let record = match result.as_ref() {
+        Term::Record(record) => record,
+        other => return Err(format!("Expected a record, got {}", term_kind(other))),
+    };
+
+    let mut fields: Vec<_> = record
+        .fields
+        .iter()
+        .filter(|(_, field)| !field.metadata.not_exported)
+        .filter_map(|(key, field)| field.value.as_ref().map(|value| (key.label(), value)))
+        .collect();
+    fields.sort_by_key(|(key, _)| *key);
+
+    let mut dotenv = String::new();
+    for (key, value) in fields {
+        let valid_name = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(format!("Field name `{}` is not a valid environment variable name", key));
+        }
+
+        let text = match value.as_ref() {
+            Term::Null => String::new(),
+            Term::Bool(b) => b.to_string(),
+            Term::Num(_) => serde_json::to_string(value)
+                .map_err(|e| format!("Serialization error: {}", e))?,
+            Term::Str(s) => s.to_string(),
+            Term::Enum(tag) => tag.label().to_string(),
+            other => {
+                return Err(format!(
+                    "Field `{}` has no .env form: expected a scalar, got {}",
+                    key,
+                    term_kind(other)
+                ))
+            }
+        };
+        let _ = writeln!(dotenv, "{}={}", key, shell_quote(&text));
+    }
+    Ok(dotenv)
+}
+
+/// Quote `text` for a POSIX shell, leaving it bare when it only contains
+/// characters that need no quoting.
+fn shell_quote(text: &str) -> String {
+    let plain = text
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-.,:/@%+=".contains(c));
+    if plain {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+/// Internal function to describe a field like `nickel query`, as JSON.
+fn query_nickel_field(code: &str, field_path: &str) -> Result<String, String> {
+    let mut program = new_program(code)?;
+
+    program.field = program
+        .parse_field_path(field_path.to_string())
+        .map_err(|e| report_error(&mut program, Error::ParseErrors(e.into())))?;
+
+    let field = program
+        .query()
+        .map_err(|e| report_error(&mut program, e))?;
+
+    let result = match field.value {
+        Some(_) => {
+            let result = program
+                .eval_full_for_export()
+                .map_err(|e| report_error(&mut program, e))?;
+            if strict_numbers() {
+                check_exact_numbers(&result, &mut String::new())?;
+            }
+            check_no_enum_variants(&result, &mut String::new())?;
+            warn_unsafe_integers(&result, &mut String::new());
+            Some(result)
+        }
+        None => None,
+    };
+
+    let annotation = &field.metadata.annotation;
+    let query = TermJson::object([
+        ("value", result.as_ref().map_or(TermJson::Value(serde_json::Value::Null), TermJson::Term)),
+        ("type", TermJson::Value(annotation.typ.as_ref().map(|labeled| labeled.typ.to_string()).into())),
+        (
+            "contracts",
+            TermJson::Value(
+                annotation
+                    .contracts
+                    .iter()
+                    .map(|labeled| labeled.typ.to_string())
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+        ),
+        ("doc", TermJson::Value(field.metadata.doc.clone().into())),
+    ]);
+    write_capped_json(&query, Some(b"  "))
+}
+
+/// Internal function to get the documentation of the field at `field_path`.
+fn query_nickel_doc(code: &str, field_path: &str) -> Result<String, String> {
+    let mut program = new_program(code)?;
+
+    program.field = program
+        .parse_field_path(field_path.to_string())
+        .map_err(|e| report_error(&mut program, Error::ParseErrors(e.into())))?;
+
+    let field = program
+        .query()
+        .map_err(|e| report_error(&mut program, e))?;
+
+    field
+        .metadata
+        .doc
+        .ok_or_else(|| format!("Field `{}` has no documentation", field_path))
+}
+
+/// Internal function to evaluate Nickel code with `input` bound to a JSON document.
+fn eval_nickel_json_with_input(code: &str, input_json: &str) -> Result<String, String> {
+    let input: serde_json::Value = serde_json::from_str(input_json)
+        .map_err(|e| format!("Invalid input JSON: {}", e))?;
+
+    let mut source = String::from("let input = ");
+    json_to_nickel(&input, &mut source);
+    source.push_str(" in ");
+    source.push_str(code);
+
+    eval_nickel_json(&source)
+}
+
+/// Internal function to apply a Nickel function to a JSON array of arguments and
+/// return the result as JSON.
+fn apply_nickel_function(func_code: &str, args_json: &str) -> Result<String, String> {
+    let mut partial = new_nickel_partial(func_code, args_json)?;
+    force_nickel_application(&mut partial)
+}
+
+/// Internal function to evaluate `code` to a function and apply it to the
+/// arguments in `args_json`.
+fn new_nickel_partial(code: &str, args_json: &str) -> Result<NickelPartial, String> {
+    check_not_empty(code)?;
+    let mut cache = Cache::new(ErrorTolerance::Strict);
+    let main_id = cache.add_string(SourcePath::Path(snippet_name()), apply_prelude(code));
+
+    let mut vm = VirtualMachine::new(cache, warning_sink());
+    let term = vm
+        .prepare_eval(main_id)
+        .map_err(|e| report_cache_error(vm.import_resolver_mut(), e))?;
+    vm.reset();
+    let head = vm
+        .eval_closure(Closure::atomic_closure(term))
+        .map_err(|e| report_cache_error(vm.import_resolver_mut(), e.into()))?;
+
+    let mut partial = NickelPartial { vm, head, applied: 0 };
+    apply_nickel_arguments(&mut partial, args_json)?;
+    Ok(partial)
+}
+
+/// Apply the partial application in `partial` to the arguments in `args_json`,
+/// evaluating each application to weak head normal form. On error, `partial` is
+/// left as it was.
+fn apply_nickel_arguments(partial: &mut NickelPartial, args_json: &str) -> Result<(), String> {
+    let args: serde_json::Value = serde_json::from_str(args_json)
+        .map_err(|e| format!("Invalid arguments JSON: {}", e))?;
+    let serde_json::Value::Array(args) = args else {
+        return Err("Arguments must be a JSON array".to_string());
+    };
+
+    let vm = &mut partial.vm;
+    let mut head = partial.head.clone();
+    for (i, arg) in args.iter().enumerate() {
+        let count = partial.applied + i;
+        if !matches!(head.body.as_ref(), Term::Fun(..) | Term::FunPattern(..) | Term::Match(_)) {
+            return Err(if count == 0 {
+                format!("Expected a function, got {}", term_kind(head.body.as_ref()))
+            } else {
+                format!(
+                    "Too many arguments: the function takes {} but {} were given",
+                    count,
+                    partial.applied + args.len()
+                )
+            });
+        }
+
+        let mut text = String::new();
+        json_to_nickel(arg, &mut text);
+        let arg_id = vm
+            .import_resolver_mut()
+            .add_string(SourcePath::Generated(format!("<argument {}>", count + 1)), text);
+        let arg = vm
+            .prepare_eval(arg_id)
+            .map_err(|e| report_cache_error(vm.import_resolver_mut(), e))?;
+
+        vm.reset();
+        let Closure { body, env } = head;
+        head = vm
+            .eval_closure(Closure { body: RichTerm::from(Term::App(body, arg)), env })
+            .map_err(|e| report_cache_error(vm.import_resolver_mut(), e.into()))?;
+    }
+
+    partial.head = head;
+    partial.applied += args.len();
+    Ok(())
+}
+
+/// Fully evaluate the partial application in `partial` to JSON.
+fn force_nickel_application(partial: &mut NickelPartial) -> Result<String, String> {
+    if matches!(partial.head.body.as_ref(), Term::Fun(..) | Term::FunPattern(..) | Term::Match(_)) {
+        return Err(format!(
+            "Too few arguments: the result is still a function after {} arguments",
+            partial.applied
+        ));
+    }
+
+    let vm = &mut partial.vm;
+    vm.reset();
+    let result = vm
+        .eval_full_for_export_closure(partial.head.clone())
+        .map_err(|e| report_cache_error(vm.import_resolver_mut(), e.into()))?;
+    if strict_numbers() {
+        check_exact_numbers(&result, &mut String::new())?;
+    }
+
+    export_json(&result)
+}
+
+/// Write a JSON value as an equivalent single-line Nickel expression.
+fn json_to_nickel(value: &serde_json::Value, out: &mut String) {
+    use serde_json::Value;
+
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => {
+            let text = n.to_string();
+            if text.starts_with('-') {
+                out.push('(');
+                out.push_str(&text);
+                out.push(')');
+            } else {
+                out.push_str(&text);
+            }
+        }
+        Value::String(s) => push_nickel_string(s, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                json_to_nickel(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            out.push('{');
+            for (i, (key, item)) in fields.iter().enumerate() {
+                out.push_str(if i > 0 { ", " } else { " " });
+                push_nickel_string(key, out);
+                out.push_str(" = ");
+                json_to_nickel(item, out);
+            }
+            out.push_str(if fields.is_empty() { "}" } else { " }" });
+        }
+    }
+}
+
+/// Write `s` as a double-quoted Nickel string literal.
+fn push_nickel_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '%' => out.push_str("\\%"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_ascii_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Rebuild `term` with every enum variant stored in a record field replaced by its
+/// tag, and the variant's argument merged into the record under prefixed names.
+fn flatten_enums(term: &RichTerm, separator: &str) -> Result<RichTerm, String> {
+    match term.as_ref() {
+        Term::Record(record) => {
+            let mut fields: Vec<(LocIdent, RichTerm)> = Vec::with_capacity(record.fields.len());
+            let push_field = |fields: &mut Vec<(LocIdent, RichTerm)>, name: String, value: RichTerm| {
+                if fields.iter().any(|(id, _)| id.label() == name)
+                    || record.fields.keys().any(|id| id.label() == name)
+                {
+                    return Err(format!(
+                        "Flattening enums would overwrite existing field `{}`",
+                        name
+                    ));
+                }
+                fields.push((LocIdent::new(name), value));
+                Ok(())
+            };
+
+            for (key, field) in record.fields.iter() {
+                let Some(ref value) = field.value else {
+                    continue;
+                };
+                match value.as_ref() {
+                    Term::EnumVariant { tag, arg, .. } => {
+                        fields.push((*key, RichTerm::from(Term::Str(tag.label().into()))));
+                        let arg = flatten_enums(arg, separator)?;
+                        match arg.as_ref() {
+                            Term::Record(arg_record) => {
+                                for (arg_key, arg_field) in arg_record.fields.iter() {
+                                    if let Some(ref arg_value) = arg_field.value {
+                                        let name = format!("{}{}{}", key.label(), separator, arg_key.label());
+                                        push_field(&mut fields, name, arg_value.clone())?;
+                                    }
+                                }
+                            }
+                            _ => {
+                                let name = format!("{}{}value", key.label(), separator);
+                                push_field(&mut fields, name, arg)?;
+                            }
+                        }
+                    }
+                    _ => fields.push((*key, flatten_enums(value, separator)?)),
+                }
+            }
+
+            Ok(RichTerm::new(
+                Term::Record(RecordData::with_field_values(fields)),
+                term.pos,
+            ))
+        }
+        Term::Array(arr, attrs) => {
+            let elems = arr
+                .iter()
+                .map(|elem| flatten_enums(elem, separator))
+                .collect::<Result<Array, String>>()?;
+            Ok(RichTerm::new(Term::Array(elems, attrs.clone()), term.pos))
+        }
+        Term::EnumVariant { tag, arg, attrs } => Ok(RichTerm::new(
+            Term::EnumVariant {
+                tag: *tag,
+                arg: flatten_enums(arg, separator)?,
+                attrs: attrs.clone(),
+            },
+            term.pos,
+        )),
+        _ => Ok(term.clone()),
+    }
+}
+
+/// Recursively check that `term` has no enum variants, which plain JSON can't
+/// represent.
+///
+/// `path` holds the location of `term` from the root and is restored before returning.
+fn check_no_enum_variants(term: &RichTerm, path: &mut String) -> Result<(), String> {
+    match term.as_ref() {
+        Term::EnumVariant { tag, .. } => {
+            let location = if path.is_empty() { "<root>" } else { path.as_str() };
+            return Err(format!(
+                "Enum variant '{} at `{}` has no JSON form; use nickel_eval_json_tagged to encode enums as objects",
+                tag.label(),
+                location
+            ));
+        }
+        Term::Array(arr, _) => {
+            for (i, elem) in arr.iter().enumerate() {
+                let prev_len = path.len();
+                path.push_str(&format!("[{}]", i));
+                check_no_enum_variants(elem, path)?;
+                path.truncate(prev_len);
+            }
+        }
+        Term::Record(record) => {
+            for (key, field) in record.fields.iter() {
+                if field.metadata.not_exported {
+                    continue;
+                }
+                if let Some(ref value) = field.value {
+                    let prev_len = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key.label());
+                    check_no_enum_variants(value, path)?;
+                    path.truncate(prev_len);
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Recursively check that no string in `term` exceeds `max_chars` characters.
+///
+/// `path` holds the location of `term` from the root and is restored before returning.
+fn check_max_string(term: &RichTerm, max_chars: usize, path: &mut String) -> Result<(), String> {
+    match term.as_ref() {
+        Term::Str(s) => {
+            let len = s.as_str().chars().count();
+            if len > max_chars {
+                let location = if path.is_empty() { "<root>" } else { path.as_str() };
+                return Err(format!(
+                    "String at `{}` is {} characters long, exceeding the maximum of {}",
+                    location, len, max_chars
+                ));
+            }
+        }
+        Term::Array(arr, _) => {
+            for (i, elem) in arr.iter().enumerate() {
+                let prev_len = path.len();
+                path.push_str(&format!("[{}]", i));
+                check_max_string(elem, max_chars, path)?;
+                path.truncate(prev_len);
+            }
+        }
+        Term::Record(record) => {
+            for (key, field) in record.fields.iter() {
+                if let Some(ref value) = field.value {
+                    let prev_len = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key.label());
+                    check_max_string(value, max_chars, path)?;
+                    path.truncate(prev_len);
+                }
+            }
+        }
+        Term::EnumVariant { arg, .. } => {
+            check_max_string(arg, max_chars, path)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Internal function to evaluate Nickel code and return JSON, rejecting fields that
+/// aren't declared by the contracts of their record.
+fn eval_nickel_json_strict(code: &str) -> Result<String, String> {
+    let result = eval_nickel_term(code)?;
+    check_declared_fields(&result, &mut String::new())?;
+
+    export_json(&result)
+}
+
+/// Recursively check that in every record with an annotated field, all fields are
+/// annotated.
+///
+/// `path` holds the location of `term` from the root and is restored before returning.
+fn check_declared_fields(term: &RichTerm, path: &mut String) -> Result<(), String> {
+    match term.as_ref() {
+        Term::Array(arr, _) => {
+            for (i, elem) in arr.iter().enumerate() {
+                let prev_len = path.len();
+                let _ = write!(path, "[{}]", i);
+                check_declared_fields(elem, path)?;
+                path.truncate(prev_len);
+            }
+        }
+        Term::Record(record) => {
+            let has_schema = record
+                .fields
+                .values()
+                .any(|field| field.metadata.annotation.iter().next().is_some());
+
+            for (key, field) in record.fields.iter() {
+                let prev_len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key.label());
+                if has_schema && field.metadata.annotation.iter().next().is_none() {
+                    return Err(format!(
+                        "Field `{}` is not declared by the contract of its record",
+                        path
+                    ));
+                }
+                if let Some(ref value) = field.value {
+                    check_declared_fields(value, path)?;
+                }
+                path.truncate(prev_len);
+            }
+        }
+        Term::EnumVariant { arg, .. } => {
+            check_declared_fields(arg, path)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Internal function to evaluate Nickel code and return binary-encoded native types.
+fn eval_nickel_native(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+
+    let mut buffer = Vec::new();
+    encode_term(&result, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Internal function to evaluate Nickel code and return the varint binary encoding.
+fn eval_nickel_native_varint(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+
+    let mut buffer = vec![PROTOCOL_VARINT];
+    let mut encoder = Encoder::new(&mut buffer);
+    encoder.varint_lengths = true;
+    encoder.encode(&result)?;
+    Ok(buffer)
+}
+
+/// Internal function to evaluate Nickel code and return the binary encoding with
+/// packed scalar arrays.
+fn eval_nickel_native_packed(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+
+    let mut buffer = vec![PROTOCOL_PACKED];
+    let mut encoder = Encoder::new(&mut buffer);
+    encoder.packed_arrays = true;
+    encoder.encode(&result)?;
+    Ok(buffer)
+}
+
+/// Internal function to evaluate Nickel code and return the binary encoding with
+/// 4-byte floats.
+fn eval_nickel_native_f32(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+
+    let mut buffer = Vec::new();
+    let mut encoder = Encoder::new(&mut buffer);
+    encoder.f32_floats = true;
+    encoder.encode(&result)?;
+    Ok(buffer)
+}
+
+/// Internal function to evaluate Nickel code to the binary protocol followed by a
+/// length and checksum trailer.
+fn eval_nickel_native_checked(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+
+    let mut buffer = vec![PROTOCOL_CHECKED];
+    Encoder::new(&mut buffer).encode(&result)?;
+    let checksum = crc32(&buffer[1..]);
+    let total_len = (buffer.len() + CHECKED_TRAILER_LEN) as u64;
+    buffer.extend_from_slice(&total_len.to_le_bytes());
+    buffer.extend_from_slice(&checksum.to_le_bytes());
+    Ok(buffer)
+}
+
+/// CRC-32 with the IEEE polynomial, as used by zlib and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// 64-bit FNV-1a hash.
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Internal function to evaluate Nickel code and return MessagePack.
+///
+/// The term goes through the same serde export as JSON, and the resulting value is
+/// then written out as MessagePack.
+fn eval_nickel_msgpack(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+    let value = serde_json::to_value(&result).map_err(|e| format!("Serialization error: {}", e))?;
+
+    rmp_serde::to_vec_named(&value).map_err(|e| format!("Serialization error: {}", e))
+}
+
+/// Internal function to evaluate Nickel code and return CBOR.
+fn eval_nickel_cbor(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+    let value = serde_json::to_value(&result).map_err(|e| format!("Serialization error: {}", e))?;
+
+    let mut buffer = Vec::new();
+    ciborium::into_writer(&value, &mut buffer).map_err(|e| format!("Serialization error: {}", e))?;
+    Ok(buffer)
+}
+
+/// Internal function to evaluate Nickel code and return a protobuf `Value` message.
+fn eval_nickel_protobuf(code: &str) -> Result<Vec<u8>, String> {
+    let result = eval_nickel_term(code)?;
+    let value = protobuf_value(&result, &mut String::new())?;
+    Ok(prost::Message::encode_to_vec(&value))
+}
+
+/// Build the `google.protobuf.Value` message for `term`.
+/// `path` locates `term` from the root, for error messages.
+fn protobuf_value(term: &RichTerm, path: &mut String) -> Result<prost_types::Value, String> {
+    use prost_types::value::Kind;
+
+    let kind = match term.as_ref() {
+        Term::Null => Kind::NullValue(prost_types::NullValue::NullValue as i32),
+        Term::Bool(b) => Kind::BoolValue(*b),
+        Term::Num(n) => Kind::NumberValue(f64::rounding_from(n, rounding_mode()).0),
+        Term::Str(s) => Kind::StringValue(s.to_string()),
+        Term::Array(arr, _) => {
+            let mut values = Vec::new();
+            for (i, elem) in arr.iter().enumerate() {
+                let prev_len = path.len();
+                let _ = write!(path, "[{}]", i);
+                values.push(protobuf_value(elem, path)?);
+                path.truncate(prev_len);
+            }
+            Kind::ListValue(prost_types::ListValue { values })
+        }
+        Term::Record(record) => {
+            let entries = record
+                .iter_serializable()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Missing field definition for `{}`", e.id))?;
+
+            let mut fields = BTreeMap::new();
+            for (key, value) in entries {
+                let prev_len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key.label());
+                fields.insert(key.label().to_string(), protobuf_value(value, path)?);
+                path.truncate(prev_len);
+            }
+            Kind::StructValue(prost_types::Struct { fields })
+        }
+        Term::Enum(tag) => {
+            let tag = Kind::StringValue(tag.label().to_string());
+            let fields = BTreeMap::from([("tag".to_string(), prost_types::Value { kind: Some(tag) })]);
+            Kind::StructValue(prost_types::Struct { fields })
+        }
+        Term::EnumVariant { tag, arg, .. } => {
+            let tag = Kind::StringValue(tag.label().to_string());
+            let fields = BTreeMap::from([
+                ("tag".to_string(), prost_types::Value { kind: Some(tag) }),
+                ("arg".to_string(), protobuf_value(arg, path)?),
+            ]);
+            Kind::StructValue(prost_types::Struct { fields })
+        }
+        other => {
+            let location = if path.is_empty() { "<root>" } else { path.as_str() };
+            return Err(format!("Cannot encode {} at `{}` as protobuf", term_kind(other), location));
+        }
+    };
+    Ok(prost_types::Value { kind: Some(kind) })
+}
+
+/// Arrow type of a table column, inferred from its values by `eval_nickel_arrow`.
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Null,
+    Bool,
+    Int,
+    Float,
+    Str,
+}
+
+impl ColumnKind {
+    fn name(self) -> &'static str {
+        match self {
+            ColumnKind::Null => "null",
+            ColumnKind::Bool => "boolean",
+            ColumnKind::Int => "integer",
+            ColumnKind::Float => "decimal number",
+            ColumnKind::Str => "string",
+        }
+    }
+}
+
+/// Internal function to evaluate Nickel code to a table in the Arrow IPC stream format.
+fn eval_nickel_arrow(code: &str) -> Result<Vec<u8>, String> {
+    use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int64Array, NullArray, StringArray};
+    use arrow::datatypes::{DataType, Field as ArrowField, Schema};
+    use arrow::ipc::writer::StreamWriter;
+    use arrow::record_batch::RecordBatch;
+    use std::sync::Arc;
+
+    let result = eval_nickel_term(code)?;
+    let rows = match result.as_ref() {
+        Term::Array(arr, _) if !arr.is_empty() => arr,
+        Term::Array(..) => return Err("Expected a table, got an empty array".to_string()),
+        other => return Err(format!("Expected a table (an array of records), got {}", term_kind(other))),
+    };
+
+    // Exported fields of each row, by name
+    let rows = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| match row.as_ref() {
+            Term::Record(record) => Ok(record
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.metadata.not_exported)
+                .filter_map(|(key, field)| Some((key.label(), field.value.as_ref()?)))
+                .collect::<BTreeMap<&str, &RichTerm>>()),
+            other => Err(format!("Expected a table, but row {} is {} rather than a record", i, term_kind(other))),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let columns: Vec<&str> = rows[0].keys().copied().collect();
+    for (i, row) in rows.iter().enumerate().skip(1) {
+        if !row.keys().copied().eq(columns.iter().copied()) {
+            return Err(format!(
+                "Row {} has fields [{}], but row 0 has [{}]",
+                i,
+                row.keys().copied().collect::<Vec<_>>().join(", "),
+                columns.join(", ")
+            ));
+        }
+    }
+
+    let mut fields = Vec::with_capacity(columns.len());
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+    for name in columns {
+        let values: Vec<&Term> = rows.iter().map(|row| row[name].as_ref()).collect();
+        let kind = values.iter().try_fold(ColumnKind::Null, |kind, value| {
+            let value_kind = match value {
+                Term::Null => return Ok(kind),
+                Term::Bool(_) => ColumnKind::Bool,
+                Term::Num(n) if i64::try_from(n).is_ok() => ColumnKind::Int,
+                Term::Num(_) => ColumnKind::Float,
+                Term::Str(_) => ColumnKind::Str,
+                other => {
+                    return Err(format!("Column `{}` holds {}, which has no Arrow type", name, term_kind(other)))
+                }
+            };
+            match (kind, value_kind) {
+                (ColumnKind::Null, _) => Ok(value_kind),
+                (ColumnKind::Int, ColumnKind::Float) | (ColumnKind::Float, ColumnKind::Int) => Ok(ColumnKind::Float),
+                _ if kind == value_kind => Ok(kind),
+                _ => Err(format!("Column `{}` mixes {} and {} values", name, kind.name(), value_kind.name())),
+            }
+        })?;
+
+        let (data_type, array): (DataType, ArrayRef) = match kind {
+            ColumnKind::Null => (DataType::Null, Arc::new(NullArray::new(values.len()))),
+            ColumnKind::Bool => (
+                DataType::Boolean,
+                Arc::new(BooleanArray::from_iter(values.iter().map(|value| match value {
+                    Term::Bool(b) => Some(*b),
+                    _ => None,
+                }))),
+            ),
+            ColumnKind::Int => (
+                DataType::Int64,
+                Arc::new(Int64Array::from_iter(values.iter().map(|value| match value {
+                    Term::Num(n) => i64::try_from(n).ok(),
+                    _ => None,
+                }))),
+            ),
+            ColumnKind::Float => (
+                DataType::Float64,
+                Arc::new(Float64Array::from_iter(values.iter().map(|value| match value {
+                    Term::Num(n) => Some(f64::rounding_from(n, rounding_mode()).0),
+                    _ => None,
+                }))),
+            ),
+            ColumnKind::Str => (
+                DataType::Utf8,
+                Arc::new(StringArray::from_iter(values.iter().map(|value| match value {
+                    Term::Str(s) => Some(s.as_str()),
+                    _ => None,
+                }))),
+            ),
+        };
+        fields.push(ArrowField::new(name, data_type, array.null_count() > 0 || kind == ColumnKind::Null));
+        arrays.push(array);
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(|e| format!("Arrow error: {}", e))?;
+    let mut buffer = Vec::new();
+    let mut writer = StreamWriter::try_new(&mut buffer, &schema).map_err(|e| format!("Arrow error: {}", e))?;
+    writer.write(&batch).map_err(|e| format!("Arrow error: {}", e))?;
+    writer.finish().map_err(|e| format!("Arrow error: {}", e))?;
+    drop(writer);
+    Ok(buffer)
+}
+
+/// Internal function to evaluate Nickel code that must produce an array.
+fn eval_nickel_array(code: &str) -> Result<Array, String> {
+    let result = eval_nickel_term(code)?;
+    match result.as_ref() {
+        Term::Array(arr, _) => Ok(arr.clone()),
+        other => Err(format!(
+            "Expected an array, got {}",
+            other.type_of().unwrap_or_else(|| "an unevaluated term".to_string())
+        )),
+    }
+}
+
+/// Internal function to evaluate a Nickel file and return binary-encoded native types.
+fn eval_nickel_file_native(path: &str) -> Result<Vec<u8>, String> {
+    use std::path::PathBuf;
+
+    let file_path = PathBuf::from(path);
+    let mut program: Program<FuelCache> = Program::new_from_file(&file_path, warning_sink())
+        .map_err(|e| format!("Error loading file: {}", e))?;
+
+    let result = program
+        .eval_full_for_export()
+        .map_err(|e| report_error(&mut program, e))?;
+
+    let mut buffer = Vec::new();
+    encode_term(&result, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Encode a Nickel term to binary format
+fn encode_term(term: &RichTerm, buffer: &mut Vec<u8>) -> Result<(), String> {
+    Encoder::new(buffer).encode(term)
+}
+
+/// Encoder for the binary protocol.
+struct Encoder<'a> {
+    buffer: &'a mut Vec<u8>,
+    /// Location of the term being encoded from the root, for error messages.
+    path: String,
+    /// Write lengths as LEB128 varints instead of fixed-width u32.
+    varint_lengths: bool,
+    /// Largest length a fixed-width prefix may hold.
+    max_len: usize,
+    /// Largest number of elements or fields a collection may have.
+    max_elements: u64,
+    /// Largest size the buffer may grow to.
+    max_bytes: usize,
+    /// Encode arrays of booleans or integers as `TYPE_PACKED_ARRAY`.
+    packed_arrays: bool,
+    /// Encode records with all-digit keys as `TYPE_KEYED_RECORD`.
+    numeric_keys: bool,
+    /// Encode records made of an enum `tag` and a `value` as `TYPE_ENUM` variants.
+    union_convention: bool,
+    /// Encode values without a native form as `TYPE_UNSUPPORTED` instead of failing.
+    placeholders: bool,
+    /// Encode non-integer numbers as `TYPE_FLOAT32` instead of `TYPE_FLOAT`.
+    f32_floats: bool,
+    /// Encode integers as floats too.
+    always_float: bool,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(buffer: &'a mut Vec<u8>) -> Self {
+        Encoder {
+            buffer,
+            path: String::new(),
+            varint_lengths: false,
+            max_len: u32::MAX as usize,
+            max_elements: MAX_ELEMENTS.with(|m| m.get()),
+            max_bytes: MAX_BUFFER_BYTES.with(|m| m.get()),
+            packed_arrays: false,
+            numeric_keys: NUMERIC_KEYS.with(|n| n.get()),
+            union_convention: UNION_CONVENTION.with(|u| u.get()),
+            placeholders: UNSUPPORTED_PLACEHOLDERS.with(|p| p.get()),
+            f32_floats: false,
+            always_float: ALWAYS_FLOAT.with(|a| a.get()),
+        }
+    }
+
+    /// Encode `term`, appending to the buffer, and fail as soon as the buffer is
+    /// larger than `max_bytes`.
+    fn encode(&mut self, term: &RichTerm) -> Result<(), String> {
+        self.encode_value(term)?;
+        self.check_bytes(0)
+    }
+
+    /// Encode `term` itself; nested values go through `encode`.
+    fn encode_value(&mut self, term: &RichTerm) -> Result<(), String> {
+        match term.as_ref() {
+            Term::Null => {
+                self.buffer.push(TYPE_NULL);
+            }
+            Term::Bool(b) => {
+                self.buffer.push(TYPE_BOOL);
+                self.buffer.push(if *b { 1 } else { 0 });
+            }
+            Term::Num(n) => {
+                // Integers that fit in an i64 are encoded exactly, unless every number
+                // should be a float
+                if let Some(i) = i64::try_from(n).ok().filter(|_| !self.always_float) {
+                    self.buffer.push(TYPE_INT);
+                    self.buffer.extend_from_slice(&i.to_le_bytes());
+                    return Ok(());
+                }
+                // Everything else becomes a float, using the configured rounding mode,
+                // followed by a flag set when the float isn't exactly the original number
+                if self.f32_floats {
+                    let (f, ordering) = f32::rounding_from(n, rounding_mode());
+                    if ordering != Ordering::Equal && strict_numbers() {
+                        let location = if self.path.is_empty() { "<root>" } else { self.path.as_str() };
+                        return Err(format!(
+                            "Number {} at `{}` can't be represented exactly as a Float32 (strict numbers mode)",
+                            n, location
+                        ));
+                    }
+                    self.buffer.push(TYPE_FLOAT32);
+                    self.buffer.extend_from_slice(&f.to_le_bytes());
+                    self.buffer.push(if ordering == Ordering::Equal { 0 } else { 1 });
+                    return Ok(());
+                }
+                let (f, ordering) = f64::rounding_from(n, rounding_mode());
+                if ordering != Ordering::Equal && strict_numbers() {
+                    let location = if self.path.is_empty() { "<root>" } else { self.path.as_str() };
+                    return Err(format!(
+                        "Number {} at `{}` can't be represented exactly as a Float64 (strict numbers mode)",
+                        n, location
+                    ));
+                }
+                self.buffer.push(TYPE_FLOAT);
+                self.buffer.extend_from_slice(&f.to_le_bytes());
+                self.buffer.push(if ordering == Ordering::Equal { 0 } else { 1 });
+            }
+            Term::Str(s) => {
+                self.buffer.push(TYPE_STRING);
+                self.write_bytes(s.as_str().as_bytes())?;
+            }
+            Term::Array(arr, _) => {
+                self.check_elements(arr.len())?;
+                if self.packed_arrays && self.encode_packed(arr)? {
+                    return Ok(());
+                }
+                self.buffer.push(TYPE_ARRAY);
+                self.write_len(arr.len())?;
+                for (i, elem) in arr.iter().enumerate() {
+                    let prev_len = self.path.len();
+                    let _ = write!(self.path, "[{}]", i);
+                    self.encode(elem)?;
+                    self.path.truncate(prev_len);
+                }
+            }
+            Term::Record(record) => {
+                if self.union_convention {
+                    if let Some((tag, value)) = union_parts(record) {
+                        // Same format as an enum variant
+                        self.buffer.push(TYPE_ENUM);
+                        self.write_bytes(tag.as_bytes())?;
+                        self.buffer.push(1); // has argument
+                        let prev_len = self.path.len();
+                        if !self.path.is_empty() {
+                            self.path.push('.');
+                        }
+                        self.path.push_str("value");
+                        self.encode_field(value)?;
+                        self.path.truncate(prev_len);
+                        return Ok(());
+                    }
+                }
+                self.check_elements(record.fields.len())?;
+                // Format: TYPE_KEYED_RECORD | count | (key type (u8) | key | value)*, where
+                // a key is either TYPE_INT and an i64, or TYPE_STRING and a string
+                let keyed = self.numeric_keys && record.fields.keys().any(|key| numeric_key(key.label()).is_some());
+                self.buffer.push(if keyed { TYPE_KEYED_RECORD } else { TYPE_RECORD });
+                self.write_len(record.fields.len())?;
+                for (key, field) in &record.fields {
+                    // Encode field name
+                    match numeric_key(key.label()).filter(|_| keyed) {
+                        Some(i) => {
+                            self.buffer.push(TYPE_INT);
+                            self.buffer.extend_from_slice(&i.to_le_bytes());
+                        }
+                        None => {
+                            if keyed {
+                                self.buffer.push(TYPE_STRING);
+                            }
+                            self.write_bytes(key.label().as_bytes())?;
+                        }
+                    }
+                    // Encode field value
+                    let prev_len = self.path.len();
+                    if !self.path.is_empty() {
+                        self.path.push('.');
+                    }
+                    self.path.push_str(key.label());
+                    self.encode_field(field)?;
+                    self.path.truncate(prev_len);
+                }
+            }
+            Term::Enum(tag) => {
+                // Simple enum without argument
+                // Format: TYPE_ENUM | tag_len (u32) | tag_bytes | has_arg (u8 = 0)
+                self.buffer.push(TYPE_ENUM);
+                self.write_bytes(tag.label().as_bytes())?;
+                self.buffer.push(0); // no argument
+            }
+            Term::EnumVariant { tag, arg, .. } => {
+                // Enum with argument
+                // Format: TYPE_ENUM | tag_len (u32) | tag_bytes | has_arg (u8 = 1) | arg_value
+                self.buffer.push(TYPE_ENUM);
+                self.write_bytes(tag.label().as_bytes())?;
+                self.buffer.push(1); // has argument
+                self.encode(arg)?;
+            }
+            Term::Fun(..) | Term::FunPattern(..) | Term::Match(_) => {
+                self.encode_function(term, None)?;
+            }
+            other if self.placeholders => {
+                // Format: TYPE_UNSUPPORTED | description_len (u32) | description_bytes
+                self.buffer.push(TYPE_UNSUPPORTED);
+                self.write_bytes(term_kind(other).as_bytes())?;
+            }
+            other => {
+                let location = if self.path.is_empty() { "<root>" } else { self.path.as_str() };
+                return Err(format!(
+                    "Cannot encode {} at `{}` as a native value",
+                    term_kind(other),
+                    location
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode a record field value, taking its metadata into account.
+    ///
+    /// String values annotated with the `Date` contract are emitted as `TYPE_DATE`
+    /// (same layout as `TYPE_STRING`) so the decoder can build a date directly, as
+    /// long as `is_plain_datetime` accepts them.
+    /// Those annotated with `Duration` or `ByteSize` are parsed and emitted as
+    /// `TYPE_QUANTITY`, or left as strings when they don't parse.
+    fn encode_field(&mut self, field: &Field) -> Result<(), String> {
+        match field.value {
+            Some(ref value) => {
+                match value.as_ref() {
+                    Term::Str(s) if has_contract_named(field, DATE_CONTRACT) && is_plain_datetime(s.as_str()) => {
+                        self.buffer.push(TYPE_DATE);
+                        self.write_bytes(s.as_str().as_bytes())
+                    }
+                    Term::Str(s) if has_contract_named(field, DURATION_CONTRACT) => {
+                        match parse_quantity(s.as_str(), DURATION_UNITS) {
+                            Some(magnitude) => self.encode_quantity(magnitude, "s"),
+                            None => self.encode(value),
+                        }
+                    }
+                    Term::Str(s) if has_contract_named(field, BYTE_SIZE_CONTRACT) => {
+                        match parse_quantity(s.as_str(), BYTE_SIZE_UNITS) {
+                            Some(magnitude) => self.encode_quantity(magnitude, "B"),
+                            None => self.encode(value),
+                        }
+                    }
+                    Term::Fun(..) | Term::FunPattern(..) | Term::Match(_) => {
+                        self.encode_function(value, Some(field))
+                    }
+                    _ => self.encode(value),
+                }
+            }
+            None => {
+                // Declared without a definition, e.g. `{ x | Number }`, as opposed to `null`
+                self.buffer.push(TYPE_MISSING);
+                Ok(())
+            }
+        }
+    }
+
+    /// Encode a quantity read by `parse_quantity` as a `TYPE_QUANTITY`.
+    ///
+    /// Format: TYPE_QUANTITY | magnitude (f64) | unit_len (u32) | unit_bytes, where
+    /// the magnitude is in `base_unit`, `s` for durations and `B` for sizes.
+    fn encode_quantity(&mut self, magnitude: f64, base_unit: &str) -> Result<(), String> {
+        self.buffer.push(TYPE_QUANTITY);
+        self.buffer.extend_from_slice(&magnitude.to_le_bytes());
+        self.write_bytes(base_unit.as_bytes())
+    }
+
+    /// Encode `arr` as a `TYPE_PACKED_ARRAY` if it is non-empty and all its elements
+    /// are booleans, or all are integers fitting in an `i64`. Returns whether it was.
+    fn encode_packed(&mut self, arr: &Array) -> Result<bool, String> {
+        if arr.is_empty() {
+            return Ok(false);
+        }
+
+        let bools: Option<Vec<bool>> = arr
+            .iter()
+            .map(|elem| match elem.as_ref() {
+                Term::Bool(b) => Some(*b),
+                _ => None,
+            })
+            .collect();
+        let ints: Option<Vec<i64>> = match bools {
+            Some(_) => None,
+            None if self.always_float => None,
+            None => arr
+                .iter()
+                .map(|elem| match elem.as_ref() {
+                    Term::Num(n) => i64::try_from(n).ok(),
+                    _ => None,
+                })
+                .collect(),
+        };
+
+        match (bools, ints) {
+            (Some(bools), _) => {
+                self.buffer.extend_from_slice(&[TYPE_PACKED_ARRAY, TYPE_BOOL]);
+                self.write_len(bools.len())?;
+                for chunk in bools.chunks(8) {
+                    let byte = chunk.iter().enumerate().fold(0u8, |acc, (i, b)| acc | ((*b as u8) << i));
+                    self.buffer.push(byte);
+                }
+            }
+            (_, Some(ints)) => {
+                self.buffer.extend_from_slice(&[TYPE_PACKED_ARRAY, TYPE_INT]);
+                self.write_len(ints.len())?;
+                for i in ints {
+                    self.buffer.extend_from_slice(&i.to_le_bytes());
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Describe a function instead of its value.
+    ///
+    /// Format: TYPE_FUNCTION | arity (length prefix) | signature (length-prefixed,
+    /// empty if unknown). When the enclosing field is annotated with a function type
+    /// or contract, that annotation is the signature and gives the arity, since the
+    /// contract wraps the original function. Otherwise the arity is the number of
+    /// parameters the function literally takes, e.g. 2 for `fun x y => x + y`.
+    fn encode_function(&mut self, term: &RichTerm, field: Option<&Field>) -> Result<(), String> {
+        let signature = field.and_then(|field| {
+            let annotation = &field.metadata.annotation;
+            annotation
+                .typ
+                .iter()
+                .chain(annotation.contracts.iter())
+                .map(|labeled| &labeled.typ)
+                .find(|typ| matches!(typ.typ, TypeF::Arrow(..)))
+        });
+        let arity = match signature {
+            Some(typ) => type_arity(typ),
+            None => term_arity(term),
+        };
+
+        self.buffer.push(TYPE_FUNCTION);
+        self.write_len(arity)?;
+        let signature = signature.map(|typ| typ.to_string()).unwrap_or_default();
+        self.write_bytes(signature.as_bytes())
+    }
+
+    /// Write a length prefix, as a u32 or a varint depending on the encoding.
+    ///
+    /// Fails instead of truncating when a fixed-width prefix can't hold `len`.
+    fn write_len(&mut self, len: usize) -> Result<(), String> {
+        if self.varint_lengths {
+            let mut len = len as u64;
+            while len >= 0x80 {
+                self.buffer.push((len as u8 & 0x7F) | 0x80);
+                len >>= 7;
+            }
+            self.buffer.push(len as u8);
+        } else {
+            if len > self.max_len {
+                let location = if self.path.is_empty() { "<root>" } else { self.path.as_str() };
+                return Err(format!(
+                    "Length {} at `{}` exceeds the binary protocol maximum of {}",
+                    len, location, self.max_len
+                ));
+            }
+            self.buffer.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Fail if a collection of `len` elements exceeds the limit set by
+    /// `nickel_set_max_elements`.
+    fn check_elements(&self, len: usize) -> Result<(), String> {
+        if len as u64 > self.max_elements {
+            let location = if self.path.is_empty() { "<root>" } else { self.path.as_str() };
+            return Err(format!(
+                "Collection of {} elements at `{}` exceeds the maximum of {}",
+                len, location, self.max_elements
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fail if `extra` more bytes would make the buffer larger than the limit set by
+    /// `nickel_set_max_buffer_bytes`.
+    fn check_bytes(&self, extra: usize) -> Result<(), String> {
+        if self.buffer.len().saturating_add(extra) > self.max_bytes {
+            let location = if self.path.is_empty() { "<root>" } else { self.path.as_str() };
+            return Err(format!(
+                "Binary result exceeds the maximum of {} bytes at `{}`",
+                self.max_bytes, location
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write length-prefixed bytes.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        // Check before copying, so a single huge string is never materialized
+        self.check_bytes(bytes.len())?;
+        self.write_len(bytes.len())?;
+        self.buffer.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Number of arguments of a function type: the length of its chain of arrows.
+fn type_arity(typ: &Type) -> usize {
+    match &typ.typ {
+        TypeF::Arrow(_, codomain) => 1 + type_arity(codomain),
+        _ => 0,
+    }
+}
+
+/// Number of parameters a function term takes before its body, counting nested
+/// `fun` literals. A `match` takes one.
+fn term_arity(term: &RichTerm) -> usize {
+    match term.as_ref() {
+        Term::Fun(_, body) | Term::FunPattern(_, body) => 1 + term_arity(body),
+        Term::Match(_) => 1,
+        _ => 0,
+    }
+}
+
+/// Check whether a field carries a type or contract annotation named `name`,
+/// either directly (`| Date`) or as the last segment of a path (`| schema.Date`).
+fn has_contract_named(field: &Field, name: &str) -> bool {
+    field.metadata.annotation.iter().any(|labeled| {
+        let typ = labeled.typ.to_string();
+        typ == name || typ.strip_suffix(name).is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// Set the rounding mode used when converting Nickel numbers to `f64`.
+///
+/// Nickel numbers are arbitrary-precision rationals, so most non-integers have no
+/// exact `f64` representation. The mode applies to the current thread:
+/// - 0: Nearest (default)
+/// - 1: Down (towards zero)
+/// - 2: Up (away from zero)
+/// - 3: Floor (towards negative infinity)
+/// - 4: Ceiling (towards positive infinity)
+///
+/// # Safety
+/// - Returns 0 on success, -1 for an unknown mode (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_rounding_mode(mode: u8) -> i32 {
+    let mode = match mode {
+        0 => RoundingMode::Nearest,
+        1 => RoundingMode::Down,
+        2 => RoundingMode::Up,
+        3 => RoundingMode::Floor,
+        4 => RoundingMode::Ceiling,
+        other => {
+            set_error(&format!("Unknown rounding mode: {}", other));
+            return -1;
+        }
+    };
+    ROUNDING_MODE.with(|m| m.set(mode));
+    0
+}
+
+/// The rounding mode configured by `nickel_set_rounding_mode`.
+fn rounding_mode() -> RoundingMode {
+    ROUNDING_MODE.with(|m| m.get())
+}
+
+/// Enable or disable strict numbers mode for the current thread.
+///
+/// By default numbers that have no exact representation in the output format are
+/// rounded (e.g. `1/3` becomes the nearest `f64`). In strict mode this is an error
+/// instead, both for the binary protocol and for JSON results, while numbers that
+/// convert exactly, like `0.5`, are unaffected.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_strict_numbers(enabled: bool) {
+    STRICT_NUMBERS.with(|s| s.set(enabled));
+}
+
+/// Enable or disable ANSI colors in error messages for the current thread.
+///
+/// Error messages are rendered like Nickel's command line reports them. By
+/// default they are plain text, for logs and programs to read; when enabled they
+/// keep the escape sequences that color them in a terminal.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_color_diagnostics(enabled: bool) {
+    COLOR_DIAGNOSTICS.with(|c| c.set(enabled));
+}
+
+/// Whether strict numbers mode is enabled by `nickel_set_strict_numbers`.
+fn strict_numbers() -> bool {
+    STRICT_NUMBERS.with(|s| s.get())
+}
+
+/// Enable or disable numeric record keys in binary results for the current thread.
+///
+/// Nickel record keys are always strings. When enabled, a record with at least one
+/// key made only of digits, without leading zeros and fitting in an `i64`, is
+/// encoded as `TYPE_KEYED_RECORD` instead of `TYPE_RECORD`: each key is preceded
+/// by a key type byte, `TYPE_INT` followed by the key as an `i64`, or `TYPE_STRING`
+/// followed by the key as usual. Other records are unaffected. Disabled by default.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_numeric_keys(enabled: bool) {
+    NUMERIC_KEYS.with(|n| n.set(enabled));
+}
+
+/// Enable or disable the tagged union convention in binary results for the current
+/// thread.
+///
+/// When enabled, a record with exactly two fields, `tag` holding an enum tag
+/// without argument and `value` holding anything, is encoded as `TYPE_ENUM` with
+/// that tag and `value` as its argument, just like the enum variant `'Foo value`.
+/// This lets decoders treat both styles of tagged unions the same way. Other
+/// records are unaffected. Disabled by default.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_union_convention(enabled: bool) {
+    UNION_CONVENTION.with(|u| u.set(enabled));
+}
+
+/// The tag and the `value` field of a record following the `{ tag = 'Foo, value = .. }`
+/// convention, or `None` for any other record.
+fn union_parts(record: &RecordData) -> Option<(&str, &Field)> {
+    if record.fields.len() != 2 {
+        return None;
+    }
+    let mut tag = None;
+    let mut value = None;
+    for (key, field) in &record.fields {
+        match key.label() {
+            "tag" => match field.value.as_ref().map(|v| v.as_ref()) {
+                Some(Term::Enum(t)) => tag = Some(t.label()),
+                _ => return None,
+            },
+            "value" => value = Some(field),
+            _ => return None,
+        }
+    }
+    Some((tag?, value?))
+}
+
+/// Enable or disable encoding every number as a float in binary results for the
+/// current thread.
+///
+/// By default, numbers that are integers fitting in an `i64` are encoded as
+/// `TYPE_INT` and others as `TYPE_FLOAT`. When enabled, every number is encoded as
+/// `TYPE_FLOAT` (or `TYPE_FLOAT32` with `nickel_eval_native_f32`), so decoders get
+/// a single numeric type; integer arrays are no longer packed either. Disabled by
+/// default.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_always_float(enabled: bool) {
+    ALWAYS_FLOAT.with(|a| a.set(enabled));
+}
+
+/// Choose what the binary protocol does with values it can't encode, for the
+/// current thread.
+///
+/// Values such as contracts, types or sealed terms have no native form:
+/// - 0: Fail the whole evaluation with an error naming the value's location (default)
+/// - 1: Encode each such value as `TYPE_UNSUPPORTED` followed by a short
+///   description of it, like `a type`, in the same layout as `TYPE_STRING`, and
+///   keep the rest of the result
+///
+/// # Safety
+/// - Returns 0 on success, -1 for an unknown mode (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_encode_mode(mode: u8) -> i32 {
+    let placeholders = match mode {
+        0 => false,
+        1 => true,
+        other => {
+            set_error(&format!("Unknown encode mode: {}", other));
+            return -1;
+        }
+    };
+    UNSUPPORTED_PLACEHOLDERS.with(|p| p.set(placeholders));
+    0
+}
+
+/// The integer spelled by a record key, if it is all digits in canonical form.
+///
+/// Keys like `01` are left alone, so decoding an integer key always gives back the
+/// original string.
+fn numeric_key(key: &str) -> Option<i64> {
+    let canonical = !key.is_empty()
+        && key.bytes().all(|b| b.is_ascii_digit())
+        && (key == "0" || !key.starts_with('0'));
+    if canonical { key.parse().ok() } else { None }
+}
+
+/// Set the number of steps each subsequent evaluation on this thread may take.
+///
+/// A step is the evaluator entering a variable or a thunk, so the count is the
+/// same on every run: once it exceeds `steps`, evaluation stops with an
+/// `Evaluation fuel exhausted` error, even for code that would loop forever.
+/// Typechecking isn't counted. The budget is unbounded by default, and
+/// `u64::MAX` restores that.
+///
+/// Values that depend on themselves, like `let rec r = { a = r.a } in r`, fail
+/// with an `infinite recursion` error whatever the budget; the fuel is for code
+/// that keeps doing new work, such as a function calling itself forever.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_eval_fuel(steps: u64) {
+    EVAL_FUEL.with(|f| f.set(steps));
+}
+
+/// Start counting the steps of a new evaluation, which gets the full budget set by
+/// `nickel_set_eval_fuel`.
+fn reset_eval_steps() {
+    EVAL_STEPS.with(|steps| steps.set(0));
+}
+
+/// Report evaluation progress to a callback.
+///
+/// `cb` is called with the number of steps taken so far, as counted by
+/// `nickel_set_eval_fuel`, every 10,000 steps of an evaluation: each call means
+/// another 10,000 variables or thunks were entered. Evaluations shorter than that
+/// never call it, and the total isn't known in advance, so this suits an activity
+/// indicator rather than a percentage. The callback applies to the current
+/// thread; pass NULL to remove it.
+///
+/// # Safety
+/// - `cb` must be NULL or a function that can be called from the evaluating thread
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_progress_callback(cb: Option<NickelProgressCallback>) {
+    PROGRESS_CALLBACK.with(|c| c.set(cb));
+}
+
+/// Set the largest number of elements an array, or fields a record, may have when
+/// encoded to the binary protocol.
+///
+/// Larger collections make the encoding fail instead of being materialized. The
+/// limit applies to the current thread; it is unbounded by default, and `u64::MAX`
+/// restores that.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_max_elements(n: u64) {
+    MAX_ELEMENTS.with(|m| m.set(n));
+}
+
+/// Set the largest size, in bytes, of a result encoded to the binary protocol.
+///
+/// Encoding stops with an error as soon as the buffer grows past `n` bytes, at the
+/// latest after the value that crossed the limit, so a result that is too large is
+/// never fully materialized. The limit applies to the current thread; it is
+/// unbounded by default, and `usize::MAX` restores that.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_max_buffer_bytes(n: usize) {
+    MAX_BUFFER_BYTES.with(|m| m.set(n));
+}
+
+/// Set the largest size, in bytes, of a JSON result.
+///
+/// Applies to every function returning an evaluated value as JSON, from
+/// `nickel_eval_string` and its compact and indented variants to `nickel_eval_field`,
+/// `nickel_apply`, `nickel_eval_multi`, `nickel_eval_diff`, `nickel_query` and
+/// program handles. Evaluators ignore it, like other thread-local settings.
+/// Serialization stops with an error as soon as the output grows past `n` bytes, so
+/// a result that is too large is never fully materialized. The limit applies to the
+/// current thread; it is unbounded by default, and `usize::MAX` restores that.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_max_json_bytes(n: usize) {
+    MAX_JSON_BYTES.with(|m| m.set(n));
+}
+
+/// Set a prelude of helper definitions for subsequent evaluations on this thread.
+///
+/// `code` must evaluate to a record, e.g. `{ double = fun x => x * 2 }`. Each of
+/// its fields is then in scope in every snippet evaluated from a string, as if
+/// bound by a `let`. Snippets can shadow prelude names. The prelude is checked
+/// once here; field names must be plain identifiers. Setting a new prelude
+/// replaces the previous one.
+///
+/// # Safety
+/// - `code` must be a valid null-terminated C string
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details); on
+///   error the previous prelude is kept
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_prelude(code: *const c_char) -> i32 {
+    if code.is_null() {
+        set_error("Null pointer passed to nickel_set_prelude");
+        return -1;
+    }
+
+    let code_str = match CStr::from_ptr(code).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in input: {}", e));
+            return -1;
+        }
+    };
+
+    match build_prelude(code_str) {
+        Ok(prelude) => {
+            PRELUDE.with(|p| *p.borrow_mut() = prelude);
+            0
+        }
+        Err(e) => {
+            set_error(&e);
+            -1
+        }
+    }
+}
+
+/// Set a string variable for subsequent evaluations on this thread.
+///
+/// The variables set so far are in scope in every snippet evaluated from a string
+/// as the record `env`, e.g. `env.FOO`, like environment variables: values are
+/// always strings, and setting a key again replaces its value. As long as no
+/// variable is set, `env` is not bound at all.
+///
+/// # Safety
+/// - `key` and `value` must be valid null-terminated C strings
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_env(key: *const c_char, value: *const c_char) -> i32 {
+    if key.is_null() || value.is_null() {
+        set_error("Null pointer passed to nickel_set_env");
+        return -1;
+    }
+
+    let key_str = match CStr::from_ptr(key).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in key: {}", e));
+            return -1;
+        }
+    };
+
+    let value_str = match CStr::from_ptr(value).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in value: {}", e));
+            return -1;
+        }
+    };
+
+    ENV.with(|env| env.borrow_mut().insert(key_str.to_string(), value_str.to_string()));
+    0
+}
+
+/// Remove every variable set by `nickel_set_env`.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_clear_env() {
+    ENV.with(|env| env.borrow_mut().clear());
+}
+
+/// Register a secret value to redact from error messages on this thread.
+///
+/// Every occurrence of `value` in an error message, and in the details returned by
+/// `nickel_get_contract_failure`, is replaced with `***`, so a secret quoted by a
+/// failing contract doesn't end up in logs. Registering an empty string has no
+/// effect. Errors of `NickelEvaluator` objects, which don't use thread-local state,
+/// are not redacted.
+///
+/// # Safety
+/// - `value` must be a valid null-terminated C string
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_register_secret(value: *const c_char) -> i32 {
+    if value.is_null() {
+        set_error("Null pointer passed to nickel_register_secret");
+        return -1;
+    }
+
+    let value_str = match CStr::from_ptr(value).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in value: {}", e));
+            return -1;
+        }
+    };
+
+    if !value_str.is_empty() {
+        SECRETS.with(|secrets| {
+            let mut secrets = secrets.borrow_mut();
+            if !secrets.iter().any(|s| s == value_str) {
+                secrets.push(value_str.to_string());
+                // Longest first, so a secret containing another is redacted whole
+                secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+            }
+        });
+    }
+    0
+}
+
+/// Forget every secret registered by `nickel_register_secret`.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_clear_secrets() {
+    SECRETS.with(|secrets| secrets.borrow_mut().clear());
+}
+
+/// Replace the secrets registered by `nickel_register_secret` in `msg` with `***`.
+fn redact_secrets(msg: &str) -> String {
+    SECRETS.with(|secrets| {
+        secrets
+            .borrow()
+            .iter()
+            .fold(msg.to_string(), |msg, secret| msg.replace(secret.as_str(), "***"))
+    })
+}
+
+/// Set the directory that imports in evaluated code strings are relative to.
+///
+/// By default, `import "lib.ncl"` in a code string is looked up in the current
+/// working directory. Once a base directory is set, it's looked up there instead,
+/// as if the code string were a file in that directory; a relative `path` is made
+/// absolute first, so later changes of working directory don't affect it. Error
+/// messages then name the code string `<path>/<ffi>` rather than `<ffi>`. Imports
+/// served by `nickel_set_import_resolver` are unaffected. Pass NULL to go back to
+/// the working directory.
+///
+/// # Safety
+/// - `path` must be NULL or a valid null-terminated C string naming a directory
+/// - Returns 0 on success, -1 on error (use `nickel_get_error` for details)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_base_dir(path: *const c_char) -> i32 {
+    if path.is_null() {
+        BASE_DIR.with(|dir| *dir.borrow_mut() = None);
+        return 0;
+    }
+
+    let path_str = match CStr::from_ptr(path).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(&format!("Invalid UTF-8 in path: {}", e));
+            return -1;
+        }
+    };
+
+    let path = Path::new(path_str);
+    if !path.is_dir() {
+        set_error(&format!("Not a directory: {}", path_str));
+        return -1;
+    }
+
+    match std::path::absolute(path) {
+        Ok(path) => {
+            BASE_DIR.with(|dir| *dir.borrow_mut() = Some(path));
+            0
+        }
+        Err(e) => {
+            set_error(&format!("Cannot resolve {}: {}", path_str, e));
+            -1
+        }
+    }
+}
+
+/// Serve `import` statements from a callback instead of the filesystem.
+///
+/// While a resolver is set, evaluating a code string first calls `cb` with the path
+/// of every file it imports, directly or through other served files. Paths are
+/// joined to the directory of the importing file as Nickel does: `import "b.ncl"`
+/// inside the served file `lib/a.ncl` asks for `lib/b.ncl`, while imports in the
+/// code string itself are passed as written. Each path is asked for at most once
+/// per evaluation.
+///
+/// `cb` returns the source text of the file as a null-terminated UTF-8 string, or
+/// NULL if it doesn't know the path, in which case the file is looked up on the
+/// filesystem as usual. The returned string remains owned by the caller: it is
+/// copied before `cb` is called again and never freed here, so it only has to stay
+/// valid until then.
+///
+/// The resolver applies to the current thread and to functions that fully evaluate
+/// a code string, like `nickel_eval_string` and `nickel_eval_native`. Pass NULL to
+/// remove it.
+///
+/// # Safety
+/// - `cb` must be NULL or a function returning NULL or a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn nickel_set_import_resolver(cb: Option<NickelImportResolver>) {
+    IMPORT_RESOLVER.with(|r| r.set(cb));
+}
+
+/// The callback set by `nickel_set_import_resolver`.
+fn import_resolver() -> Option<NickelImportResolver> {
+    IMPORT_RESOLVER.with(|r| r.get())
+}
+
+/// Remove the prelude set by `nickel_set_prelude`.
+///
+/// # Safety
+/// - Always safe to call; a no-op if no prelude is set
+#[no_mangle]
+pub unsafe extern "C" fn nickel_clear_prelude() {
+    PRELUDE.with(|p| *p.borrow_mut() = None);
+}
+
+/// Restore the current thread's state to its defaults.
+///
+/// Clears the last error (with its position and contract failure), the warnings,
+/// the prelude, the `env` variables, the registered secrets, the base directory,
+/// the import resolver and the progress callback, and resets the rounding mode,
+/// strict numbers mode, always float mode, numeric keys, union convention, encode
+/// mode, colored diagnostics, evaluation fuel, element limit, buffer size limit and
+/// JSON size limit. Import paths belong to `NickelEvaluator` objects and are not
+/// affected.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_reset() {
+    LAST_ERROR.with(|e| *e.borrow_mut() = None);
+    for slot in [&LAST_ERROR_POSITION, &PENDING_ERROR_POSITION] {
+        slot.with(|p| *p.borrow_mut() = None);
+    }
+    for slot in [&LAST_CONTRACT_FAILURE, &PENDING_CONTRACT_FAILURE] {
+        slot.with(|f| *f.borrow_mut() = None);
+    }
+    clear_warnings();
+    LAST_WARNINGS.with(|w| *w.borrow_mut() = None);
+    PRELUDE.with(|p| *p.borrow_mut() = None);
+    ROUNDING_MODE.with(|m| m.set(RoundingMode::Nearest));
+    STRICT_NUMBERS.with(|s| s.set(false));
+    ALWAYS_FLOAT.with(|a| a.set(false));
+    NUMERIC_KEYS.with(|n| n.set(false));
+    UNION_CONVENTION.with(|u| u.set(false));
+    UNSUPPORTED_PLACEHOLDERS.with(|p| p.set(false));
+    COLOR_DIAGNOSTICS.with(|c| c.set(false));
+    MAX_ELEMENTS.with(|m| m.set(u64::MAX));
+    MAX_BUFFER_BYTES.with(|m| m.set(usize::MAX));
+    MAX_JSON_BYTES.with(|m| m.set(usize::MAX));
+    EVAL_FUEL.with(|f| f.set(u64::MAX));
+    PROGRESS_CALLBACK.with(|c| c.set(None));
+    ENV.with(|env| env.borrow_mut().clear());
+    SECRETS.with(|secrets| secrets.borrow_mut().clear());
+    BASE_DIR.with(|dir| *dir.borrow_mut() = None);
+    IMPORT_RESOLVER.with(|r| r.set(None));
+}
+
+/// Check that `code` evaluates to a record of identifiers and build the text
+/// wrapped around snippets. An empty record yields no prelude.
+fn build_prelude(code: &str) -> Result<Option<Prelude>, String> {
+    let source = Cursor::new(code.as_bytes());
+    let mut program: Program<FuelCache> = Program::new_from_source(source, "<prelude>", warning_sink())
+        .map_err(|e| format!("Parse error: {}", e))?;
+
+    let result = program
+        .eval()
+        .map_err(|e| report_error(&mut program, e))?;
+
+    let names: Vec<String> = match result.as_ref() {
+        Term::Record(record) => record.fields.keys().map(|key| key.label().to_string()).collect(),
+        other => return Err(format!("Prelude must be a record, got {}", term_kind(other))),
+    };
+
+    if let Some(name) = names.iter().find(|name| !is_nickel_identifier(name)) {
+        return Err(format!("Prelude field `{}` is not a valid identifier", name));
+    }
+
+    if names.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Prelude {
+        prefix: format!("(fun {{ {}, .. }} => ", names.join(", ")),
+        suffix: format!("\n) (\n{}\n)", code),
+        source: code.to_string(),
+        names,
+    }))
+}
+
+/// Wrap `code` so the fields of the current prelude, and the `env` record if any
+/// variable is set, are in scope.
+fn apply_prelude(code: &str) -> String {
+    let code = bind_env(code);
+    PRELUDE.with(|p| match p.borrow().as_ref() {
+        Some(prelude) => format!("{}{}{}", prelude.prefix, code, prelude.suffix),
+        None => code,
+    })
+}
+
+/// Bind the variables set by `nickel_set_env` as the record `env` around `code`,
+/// on the snippet's first line so line numbers are unaffected.
+fn bind_env(code: &str) -> String {
+    ENV.with(|env| {
+        let env = env.borrow();
+        if env.is_empty() {
+            return code.to_string();
+        }
+
+        let mut source = String::from("let env = {");
+        for (i, (key, value)) in env.iter().enumerate() {
+            source.push_str(if i > 0 { ", " } else { " " });
+            push_nickel_string(key, &mut source);
+            source.push_str(" = ");
+            push_nickel_string(value, &mut source);
+        }
+        source.push_str(" } in ");
+        source.push_str(code);
+        source
+    })
+}
+
+/// Whether `name` can be bound as a Nickel variable.
+fn is_nickel_identifier(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "if", "then", "else", "forall", "in", "let", "rec", "match", "null", "true", "false",
+        "or", "fun", "import",
+    ];
+
+    let rest = name.trim_start_matches('_');
+    let mut chars = rest.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''))
+        && !KEYWORDS.contains(&name)
+}
+
+/// Get the last error message.
+///
+/// # Safety
+/// - The returned pointer is valid until the next call to any nickel_* function
+/// - Do not free this pointer; it is managed internally
+#[no_mangle]
+pub unsafe extern "C" fn nickel_get_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|s| s.as_ptr())
+            .unwrap_or(ptr::null())
+    })
+}
+
+/// Get the length in bytes of the last error message, excluding the null terminator.
+///
+/// Returns 0 if no error has been set. Lets callers size a buffer before copying
+/// the message returned by `nickel_get_error`.
+///
+/// # Safety
+/// - Always safe to call
+#[no_mangle]
+pub unsafe extern "C" fn nickel_get_error_len() -> usize {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(0, |s| s.as_bytes().len()))
+}
+
+/// Get a copy of the last error message.
+///
+/// Unlike `nickel_get_error`, the message is copied into a new string owned by
+/// the caller, so later calls can't invalidate it.
+///
+/// # Safety
+/// - The returned pointer must be freed with `nickel_free_string`
+/// - Returns NULL if there is no error
+#[no_mangle]
+pub unsafe extern "C" fn nickel_get_error_owned() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|s| s.clone().into_raw() as *const c_char)
+            .unwrap_or(ptr::null())
+    })
+}
+
+/// Get the source position of the last error.
+///
+/// Positions are available when the last error's diagnostic points at source
+/// code: parse and type errors, but also evaluation errors such as a contract
+/// violation, where the position is that of the offending value, or a missing
+/// field, where it is that of the field access. Lines and columns are 1-based, the
+/// byte offset is 0-based from the start of the file containing the error. Any of
+/// the output pointers may be NULL.
+///
+/// # Safety
+/// - Non-null output pointers must be valid for writes
+///
+/// # Returns
+/// 0 if a position was written, non-zero if no position is available
+#[no_mangle]
+pub unsafe extern "C" fn nickel_get_error_position(
+    out_line: *mut u32,
+    out_col: *mut u32,
+    out_byte: *mut u32,
+) -> i32 {
+    let position = LAST_ERROR_POSITION.with(|p| *p.borrow());
+    match position {
+        Some(pos) => {
+            if !out_line.is_null() {
+                *out_line = pos.line;
+            }
+            if !out_col.is_null() {
+                *out_col = pos.col;
+            }
+            if !out_byte.is_null() {
+                *out_byte = pos.byte;
+            }
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Get the details of the last error, if it was a contract violation.
+///
+/// `*out_field` receives the name of the record field the contract was attached
+/// to (empty if the contract wasn't on a field), and `*out_message` a summary such
+/// as ``expected `Number`, got `"abc"` ``, including the contract's own message
+/// when it provides one. Either output pointer may be NULL.
+///
+/// # Safety
+/// - Non-null output pointers must be valid for writes
+/// - The strings are valid until the next call to any nickel_* function; do not free them
+///
+/// # Returns
+/// 0 if details were written, non-zero if the last error wasn't a contract violation
+#[no_mangle]
+pub unsafe extern "C" fn nickel_get_contract_failure(
+    out_field: *mut *const c_char,
+    out_message: *mut *const c_char,
+) -> i32 {
+    LAST_CONTRACT_FAILURE.with(|f| match f.borrow().as_ref() {
+        Some(failure) => {
+            if !out_field.is_null() {
+                *out_field = failure.field.as_ptr();
+            }
+            if !out_message.is_null() {
+                *out_message = failure.message.as_ptr();
+            }
+            0
+        }
+        None => 1,
+    })
+}
+
+/// Get the diagnostic messages emitted by the last evaluation, one per line.
+///
+/// Messages are collected even when the evaluation succeeds. nickel-lang-core
+/// currently reports its non-fatal diagnostics through `std.trace`, so each line
+/// has the form `std.trace: <message>`.
+///
+/// # Safety
+/// - The returned pointer is valid until the next call to any nickel_* function
+/// - Do not free this pointer; it is managed internally
+/// - Returns NULL if the last evaluation emitted nothing
+#[no_mangle]
+pub unsafe extern "C" fn nickel_get_warnings() -> *const c_char {
+    let text = WARNINGS.with(|w| {
+        let bytes = w.borrow();
+        String::from_utf8_lossy(&bytes).trim_end_matches('\n').replace('\0', "")
+    });
+    let warnings = if text.is_empty() { None } else { CString::new(text).ok() };
+
+    LAST_WARNINGS.with(|w| {
+        let mut w = w.borrow_mut();
+        *w = warnings;
+        w.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null())
+    })
+}
+
+/// Free a string allocated by this library.
+///
+/// # Safety
+/// - `ptr` must have been returned by `nickel_eval_string`
+/// - `ptr` must not be used after this call
+/// - Passing NULL is safe (no-op)
+#[no_mangle]
+pub unsafe extern "C" fn nickel_free_string(ptr: *const c_char) {
+    if !ptr.is_null() {
+        drop(CString::from_raw(ptr as *mut c_char));
+    }
+}
+
+/// Free a binary buffer allocated by this library.
+///
+/// # Safety
+/// - `buffer` must have been returned by `nickel_eval_native`
+/// - The buffer must not be used after this call
+/// - Passing a buffer with null data is safe (no-op); a non-null buffer is freed
+///   even when `len` is 0
+#[no_mangle]
+pub unsafe extern "C" fn nickel_free_buffer(buffer: NativeBuffer) {
+    if !buffer.data.is_null() {
+        // `native_buffer_from_vec` hands out a boxed slice, whose allocation is
+        // exactly `len` bytes long (and none at all when `len` is 0)
+        let _ = Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len));
+    }
+}
+
+/// Hand a byte vector over to the caller as a `NativeBuffer`.
+///
+/// The vector is converted to a boxed slice, so the allocation has no spare
+/// capacity and `nickel_free_buffer` can rebuild it from `data` and `len` alone.
+fn native_buffer_from_vec(buffer: Vec<u8>) -> NativeBuffer {
+    let len = buffer.len();
+    let boxed = buffer.into_boxed_slice();
+    let data = Box::into_raw(boxed) as *mut u8;
+    NativeBuffer { data, len }
+}
+
+/// Writer that collects evaluator diagnostics into `WARNINGS`.
+struct WarningSink;
+
+impl std::io::Write for WarningSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        WARNINGS.with(|w| w.borrow_mut().extend_from_slice(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Clear the warnings of the previous evaluation and return a writer for the next one.
+fn warning_sink() -> WarningSink {
+    clear_warnings();
+    WarningSink
+}
+
+/// Forget the warnings collected so far.
+fn clear_warnings() {
+    WARNINGS.with(|w| w.borrow_mut().clear());
+}
+
+/// Evaluation cache counting the steps taken, to enforce the budget set by
+/// `nickel_set_eval_fuel` and report progress to `nickel_set_progress_callback`.
+///
+/// nickel-lang-core has no step limit or progress hook of its own, so this wraps
+/// the default call-by-need cache, which the evaluator goes through to enter every
+/// variable.
+/// Once the budget is spent, entering a variable yields an error term instead of
+/// its value, and the evaluator aborts on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FuelCache(CBNCache);
+
+impl EvalCache for FuelCache {
+    type UpdateIndex = ThunkUpdateFrame;
+
+    fn get(&self, idx: CacheIndex) -> Closure {
+        let steps = EVAL_STEPS.with(|steps| {
+            let taken = steps.get() + 1;
+            steps.set(taken);
+            taken
+        });
+        if steps.is_multiple_of(PROGRESS_INTERVAL) {
+            if let Some(cb) = PROGRESS_CALLBACK.with(|c| c.get()) {
+                cb(steps);
+            }
+        }
+
+        let budget = EVAL_FUEL.with(|f| f.get());
+        if steps > budget {
+            let message = format!("Evaluation fuel exhausted: the budget of {} steps is spent", budget);
+            return Closure::atomic_closure(RichTerm::from(Term::RuntimeError(EvalError::Other(
+                message,
+                TermPos::None,
+            ))));
+        }
+        self.0.get(idx)
+    }
+
+    fn get_update_index(
+        &mut self,
+        idx: &mut CacheIndex,
+    ) -> Result<Option<Self::UpdateIndex>, BlackholedError> {
+        self.0.get_update_index(idx)
+    }
+
+    fn add(&mut self, clos: Closure, bty: BindingType) -> CacheIndex {
+        self.0.add(clos, bty)
+    }
+
+    fn patch<F: Fn(&mut Closure)>(&mut self, idx: CacheIndex, f: F) {
+        self.0.patch(idx, f)
+    }
+
+    fn get_then<T, F: FnOnce(&Closure) -> T>(&self, idx: CacheIndex, f: F) -> T {
+        self.0.get_then(idx, f)
+    }
+
+    fn update(&mut self, clos: Closure, idx: Self::UpdateIndex) {
+        self.0.update(clos, idx)
+    }
+
+    fn new() -> Self {
+        reset_eval_steps();
+        FuelCache(CBNCache::new())
+    }
+
+    fn reset_index_state(&mut self, idx: &mut Self::UpdateIndex) {
+        self.0.reset_index_state(idx)
+    }
+
+    fn map_at_index<F: FnMut(&mut Self, &Closure) -> Closure>(
+        &mut self,
+        idx: &CacheIndex,
+        mut f: F,
+    ) -> CacheIndex {
+        idx.map(|closure| f(self, closure))
+    }
+
+    fn build_cached(&mut self, idx: &mut CacheIndex, rec_env: &[(Ident, CacheIndex)]) {
+        self.0.build_cached(idx, rec_env)
+    }
+
+    fn saturate<I: DoubleEndedIterator<Item = Ident> + Clone>(
+        &mut self,
+        idx: CacheIndex,
+        fields: I,
+    ) -> RichTerm {
+        self.0.saturate(idx, fields)
+    }
+
+    fn revert(&mut self, idx: &CacheIndex) -> CacheIndex {
+        self.0.revert(idx)
+    }
+
+    fn deps(&self, idx: &CacheIndex) -> Option<FieldDeps> {
+        self.0.deps(idx)
+    }
+
+    fn make_update_index(
+        &mut self,
+        idx: &mut CacheIndex,
+    ) -> Result<Self::UpdateIndex, BlackholedError> {
+        self.0.make_update_index(idx)
+    }
+}
+
+/// Convert an error message to a C string, replacing interior null bytes (which
+/// can come from the source text quoted in diagnostics) with `\0` so the message
+/// is never lost.
+fn error_cstring(msg: &str) -> CString {
+    CString::new(msg.replace('\0', "\\0")).expect("interior null bytes were replaced")
+}
+
+/// Run `f`, turning a panic into an error so it doesn't unwind across the FFI
+/// boundary.
+fn catch_panic<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".to_string());
+        Err(format!("Internal error: Nickel panicked: {}", message))
+    })
+}
+
+fn set_error(msg: &str) {
+    LAST_ERROR.with(|e| {
+        *e.borrow_mut() = Some(error_cstring(&redact_secrets(msg)));
+    });
+    let position = PENDING_ERROR_POSITION.with(|p| p.borrow_mut().take());
+    LAST_ERROR_POSITION.with(|p| *p.borrow_mut() = position);
+    let failure = PENDING_CONTRACT_FAILURE.with(|f| f.borrow_mut().take()).map(|failure| ContractFailure {
+        field: error_cstring(&redact_secrets(&failure.field.to_string_lossy())),
+        message: error_cstring(&redact_secrets(&failure.message.to_string_lossy())),
+    });
+    LAST_CONTRACT_FAILURE.with(|f| *f.borrow_mut() = failure);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use std::ffi::CString;
 
     #[test]
-    fn test_null_input() {
+    fn test_null_input() {
+        unsafe {
+            let result = nickel_eval_string(ptr::null());
+            assert!(result.is_null());
+            let error = nickel_get_error();
+            assert!(!error.is_null());
+        }
+    }
+
+    #[test]
+    fn test_free_null() {
+        unsafe {
+            nickel_free_string(ptr::null());
+        }
+    }
+
+    #[test]
+    fn test_eval_simple_number() {
+        unsafe {
+            let code = CString::new("1 + 2").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let result_str = CStr::from_ptr(result).to_str().unwrap();
+            assert_eq!(result_str, "3");
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_string() {
+        unsafe {
+            let code = CString::new(r#""hello""#).unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let result_str = CStr::from_ptr(result).to_str().unwrap();
+            assert_eq!(result_str, "\"hello\"");
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_record() {
+        unsafe {
+            let code = CString::new("{ x = 1, y = 2 }").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let result_str = CStr::from_ptr(result).to_str().unwrap();
+            assert!(result_str.contains("\"x\""));
+            assert!(result_str.contains("\"y\""));
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_array() {
+        unsafe {
+            let code = CString::new("[1, 2, 3]").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let result_str = CStr::from_ptr(result).to_str().unwrap();
+            assert!(result_str.contains("1"));
+            assert!(result_str.contains("2"));
+            assert!(result_str.contains("3"));
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_function_application() {
+        unsafe {
+            let code = CString::new("let add = fun x y => x + y in add 3 4").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let result_str = CStr::from_ptr(result).to_str().unwrap();
+            assert_eq!(result_str, "7");
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_syntax_error() {
+        unsafe {
+            let code = CString::new("{ x = }").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(result.is_null());
+            let error = nickel_get_error();
+            assert!(!error.is_null());
+            let error_str = CStr::from_ptr(error).to_str().unwrap();
+            assert!(!error_str.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        // No Nickel input is known to panic, so panic directly
+        let result: Result<(), String> = catch_panic(|| panic!("invariant violated"));
+        let error = result.unwrap_err();
+        assert!(error.contains("panicked: invariant violated"), "Unexpected error: {}", error);
+
+        let result: Result<(), String> = catch_panic(|| panic!("at index {}", 3));
+        assert!(result.unwrap_err().contains("at index 3"));
+
+        assert_eq!(catch_panic(|| Ok(42)), Ok(42));
+    }
+
+    #[test]
+    fn test_get_error_len() {
+        unsafe {
+            assert_eq!(nickel_get_error_len(), 0);
+
+            set_error("héllo");
+            assert_eq!(nickel_get_error_len(), 6);
+
+            let code = CString::new("{ x = }").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_bytes();
+            assert_eq!(nickel_get_error_len(), error.len());
+        }
+    }
+
+    #[test]
+    fn test_get_error_owned() {
+        unsafe {
+            nickel_reset();
+            assert!(nickel_get_error_owned().is_null());
+
+            let code = CString::new("{ x = }").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let owned = nickel_get_error_owned();
+            let message = CStr::from_ptr(nickel_get_error()).to_owned();
+            assert_eq!(CStr::from_ptr(owned), message.as_c_str());
+
+            // The copy outlives later evaluations, successful or not
+            let code = CString::new("1 + 1").unwrap();
+            nickel_free_string(nickel_eval_string(code.as_ptr()));
+            let code = CString::new("1 + \"a\"").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            assert_ne!(CStr::from_ptr(nickel_get_error()), message.as_c_str());
+            assert_eq!(CStr::from_ptr(owned), message.as_c_str());
+            nickel_free_string(owned);
+        }
+    }
+
+    #[test]
+    fn test_empty_input() {
+        unsafe {
+            for input in ["", "   ", "\n\t "] {
+                let code = CString::new(input).unwrap();
+                assert!(nickel_eval_string(code.as_ptr()).is_null());
+                let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+                assert_eq!(error, EMPTY_INPUT_ERROR);
+                assert_eq!(nickel_check_syntax(code.as_ptr()), -1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_position_parse_error() {
+        unsafe {
+            let code = CString::new("{\n  x = 1,\n  y = ,\n}").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(result.is_null());
+
+            let (mut line, mut col, mut byte) = (0u32, 0u32, 0u32);
+            let status = nickel_get_error_position(&mut line, &mut col, &mut byte);
+            assert_eq!(status, 0);
+            assert_eq!(line, 3);
+            assert_eq!(col, 7);
+            assert_eq!(byte, 17);
+        }
+    }
+
+    #[test]
+    fn test_error_position_eval_error() {
+        unsafe {
+            let code = CString::new("{\n  port | Number = \"abc\",\n}").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(result.is_null());
+
+            let (mut line, mut col, mut byte) = (0u32, 0u32, 0u32);
+            let status = nickel_get_error_position(&mut line, &mut col, &mut byte);
+            assert_eq!(status, 0);
+            assert_eq!((line, col, byte), (2, 19, 20));
+
+            let code = CString::new("let x = { a = 1 } in\nx.b").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let status = nickel_get_error_position(&mut line, &mut col, &mut byte);
+            assert_eq!(status, 0);
+            assert_eq!((line, col, byte), (2, 1, 21));
+        }
+    }
+
+    #[test]
+    fn test_eval_diagnostics() {
+        unsafe {
+            let code = CString::new("let x : Number = \"a\" in x").unwrap();
+            let result = nickel_eval_diagnostics(code.as_ptr());
+            assert!(!result.is_null());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            nickel_free_string(result);
+
+            let diagnostics = json.as_array().unwrap();
+            assert!(!diagnostics.is_empty());
+            let first = &diagnostics[0];
+            assert_eq!(first["severity"], "error");
+            assert!(first["message"].as_str().unwrap().contains("incompatible types"), "Unexpected: {}", first);
+            assert_eq!(first["line"], 1);
+            assert_eq!(first["column"], 18);
+            assert_eq!(first["byte_span"], serde_json::json!([17, 20]));
+
+            let code = CString::new("{ x = 1 }").unwrap();
+            let result = nickel_eval_diagnostics(code.as_ptr());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "[]");
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_error_position_unavailable() {
+        unsafe {
+            let result = nickel_eval_string(ptr::null());
+            assert!(result.is_null());
+            let status = nickel_get_error_position(ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            assert_ne!(status, 0);
+        }
+    }
+
+    #[test]
+    fn test_native_int() {
+        unsafe {
+            let code = CString::new("42").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_INT);
+            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert_eq!(value, 42);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_native_float() {
+        unsafe {
+            let code = CString::new("3.14").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            if buffer.data.is_null() {
+                let err = nickel_get_error();
+                if !err.is_null() {
+                    panic!("Error: {:?}", CStr::from_ptr(err).to_str());
+                }
+            }
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_FLOAT);
+            let value = f64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert!((value - 3.14).abs() < 0.001);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_string() {
+        unsafe {
+            let code = CString::new(r#""hello""#).unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_STRING);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
+            let s = std::str::from_utf8(&data[5..5+len]).unwrap();
+            assert_eq!(s, "hello");
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_bool() {
+        unsafe {
+            let code = CString::new("true").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_BOOL);
+            assert_eq!(data[1], 1);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_array() {
+        unsafe {
+            let code = CString::new("[1, 2, 3]").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_ARRAY);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(len, 3);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_record() {
+        unsafe {
+            let code = CString::new("{ x = 1 }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_RECORD);
+            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(field_count, 1);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_eval_json_internal() {
+        let result = eval_nickel_json("42").unwrap();
+        assert_eq!(result, "42");
+
+        let result = eval_nickel_json("{ a = 1 }").unwrap();
+        assert!(result.contains("\"a\""));
+        assert!(result.contains("1"));
+    }
+
+    // Comprehensive tests for all Nickel types
+
+    #[test]
+    fn test_native_null() {
+        unsafe {
+            let code = CString::new("null").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_NULL);
+            assert_eq!(buffer.len, 1);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_bool_false() {
+        unsafe {
+            let code = CString::new("false").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_BOOL);
+            assert_eq!(data[1], 0);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_negative_int() {
+        unsafe {
+            let code = CString::new("-42").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_INT);
+            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert_eq!(value, -42);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_large_int() {
+        unsafe {
+            let code = CString::new("1000000000000").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_INT);
+            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert_eq!(value, 1000000000000i64);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_native_negative_float() {
+        unsafe {
+            let code = CString::new("-2.718").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_FLOAT);
+            let value = f64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert!((value - (-2.718)).abs() < 0.001);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_empty_string() {
+        unsafe {
+            let code = CString::new(r#""""#).unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_STRING);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
+            assert_eq!(len, 0);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_unicode_string() {
+        unsafe {
+            let code = CString::new(r#""hello 世界 🌍""#).unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_STRING);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
+            let s = std::str::from_utf8(&data[5..5+len]).unwrap();
+            assert_eq!(s, "hello 世界 🌍");
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_multiline_string() {
+        unsafe {
+            let decode = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let data = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                assert_eq!(data[0], TYPE_STRING);
+                let len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
+                assert_eq!(data.len(), 5 + len);
+                String::from_utf8(data[5..].to_vec()).unwrap()
+            };
+
+            assert_eq!(decode(r#""one\ntwo\n""#), "one\ntwo\n");
+            assert_eq!(decode(r#""one\r\ntwo\r\n""#), "one\r\ntwo\r\n");
+            assert_eq!(decode("m%\"\n  one\n  two\n\n\"%"), "one\ntwo\n");
+            // Escapes are kept byte for byte, while CRLF line breaks in the source of a
+            // multiline string are line endings, which Nickel reads as `\n` like `nickel export`
+            assert_eq!(decode("m%\"\r\n  one\r\n  two\r\n\r\n\"%"), "one\ntwo\n");
+            assert_eq!(decode("m%\"\n  one%{\"\\r\\n\"}two\n  \"%"), "one\r\ntwo");
+        }
+    }
+
+    #[test]
+    fn test_native_empty_array() {
+        unsafe {
+            let code = CString::new("[]").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_ARRAY);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(len, 0);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_mixed_array() {
+        unsafe {
+            // Array with int, string, bool
+            let code = CString::new(r#"[1, "two", true]"#).unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_ARRAY);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(len, 3);
+            // First element: int 1
+            assert_eq!(data[5], TYPE_INT);
+            // (rest of elements follow)
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_nested_array() {
+        unsafe {
+            let code = CString::new("[[1, 2], [3, 4]]").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_ARRAY);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(len, 2);
+            // First element should be an array
+            assert_eq!(data[5], TYPE_ARRAY);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_empty_record() {
+        unsafe {
+            let code = CString::new("{}").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_RECORD);
+            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(field_count, 0);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_nested_record() {
+        unsafe {
+            let code = CString::new("{ outer = { inner = 42 } }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_RECORD);
+            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(field_count, 1);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_record_with_mixed_types() {
+        unsafe {
+            let code = CString::new(r#"{ name = "test", count = 42, active = true, data = null }"#).unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_RECORD);
+            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(field_count, 4);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_computed_value() {
+        unsafe {
+            let code = CString::new("let x = 10 in let y = 20 in x + y").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_INT);
+            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert_eq!(value, 30);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_function_result() {
+        unsafe {
+            let code = CString::new("let double = fun x => x * 2 in double 21").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_INT);
+            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
+            assert_eq!(value, 42);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_array_operations() {
+        unsafe {
+            // Test array map
+            let code = CString::new("[1, 2, 3] |> std.array.map (fun x => x * 2)").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_ARRAY);
+            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(len, 3);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_record_merge() {
+        unsafe {
+            let code = CString::new("{ a = 1 } & { b = 2 }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_RECORD);
+            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(field_count, 2);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_json_all_types() {
+        // Test JSON serialization for all types
+        assert_eq!(eval_nickel_json("null").unwrap(), "null");
+        assert_eq!(eval_nickel_json("true").unwrap(), "true");
+        assert_eq!(eval_nickel_json("false").unwrap(), "false");
+        assert_eq!(eval_nickel_json("42").unwrap(), "42");
+        assert!(eval_nickel_json("3.14").unwrap().starts_with("3.14"));
+        assert_eq!(eval_nickel_json(r#""hello""#).unwrap(), "\"hello\"");
+        assert!(eval_nickel_json("[]").unwrap().contains("[]") || eval_nickel_json("[]").unwrap().contains("[\n]"));
+    }
+
+    #[test]
+    fn test_eval_json_max_string() {
+        unsafe {
+            let code = CString::new(r#"{ db = { name = "accounts", comment = "far too long" } }"#).unwrap();
+            let result = nickel_eval_json_max_string(code.as_ptr(), 8);
+            assert!(result.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("db.comment"), "Unexpected error: {}", error);
+            assert!(error.contains("12"), "Unexpected error: {}", error);
+
+            let code = CString::new(r#"{ db = { name = "accounts", tags = ["a", "b"] } }"#).unwrap();
+            let result = nickel_eval_json_max_string(code.as_ptr(), 8);
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let result_str = CStr::from_ptr(result).to_str().unwrap();
+            assert!(result_str.contains("\"accounts\""));
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_evaluators_concurrently() {
+        let workers: Vec<_> = (0..2)
+            .map(|worker| {
+                std::thread::spawn(move || unsafe {
+                    let ev = nickel_evaluator_new();
+                    for i in 0..20 {
+                        let (code, should_fail) = if (i + worker) % 2 == 0 {
+                            (format!("{} + {}", worker, i), false)
+                        } else {
+                            (format!("{{ worker = {} }}.missing_{}", worker, i), true)
+                        };
+                        let code = CString::new(code).unwrap();
+                        let mut err: *const c_char = ptr::null();
+                        let result = nickel_evaluator_eval_string(ev, code.as_ptr(), &mut err);
+                        if should_fail {
+                            assert!(result.is_null());
+                            let message = CStr::from_ptr(err).to_str().unwrap();
+                            assert!(message.contains(&format!("missing_{}", i)), "Unexpected error: {}", message);
+                        } else {
+                            assert!(err.is_null());
+                            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), (worker + i).to_string());
+                            nickel_free_string(result);
+                        }
+                    }
+                    let contract = CString::new("{ port | Number = \"http\" }").unwrap();
+                    let mut err: *const c_char = ptr::null();
+                    assert!(nickel_evaluator_eval_string(ev, contract.as_ptr(), &mut err).is_null());
+                    assert!(CStr::from_ptr(err).to_str().unwrap().contains("contract broken"));
+                    nickel_evaluator_free(ev);
+                    // Evaluator errors never touch the thread-local slot
+                    assert!(nickel_get_error().is_null());
+                    assert_ne!(nickel_get_contract_failure(ptr::null_mut(), ptr::null_mut()), 0);
+
+                    // Nor are they attached to the next thread-local error
+                    assert!(nickel_eval_string(ptr::null()).is_null());
+                    assert_ne!(nickel_get_contract_failure(ptr::null_mut(), ptr::null_mut()), 0);
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_evaluator_import_path() {
+        let dir = std::env::temp_dir().join(format!("nickel_jl_evaluator_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shared.ncl"), "{ answer = 42 }").unwrap();
+
+        unsafe {
+            let ev = nickel_evaluator_new();
+            let code = CString::new("(import \"shared.ncl\").answer").unwrap();
+            let mut err: *const c_char = ptr::null();
+            assert!(nickel_evaluator_eval_string(ev, code.as_ptr(), &mut err).is_null());
+            assert!(!err.is_null());
+
+            let path = CString::new(dir.to_str().unwrap()).unwrap();
+            assert_eq!(nickel_evaluator_add_import_path(ev, path.as_ptr()), 0);
+            let result = nickel_evaluator_eval_string(ev, code.as_ptr(), &mut err);
+            assert!(err.is_null());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "42");
+            nickel_free_string(result);
+            nickel_evaluator_free(ev);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_program_eval_native_twice() {
+        unsafe {
+            let code = CString::new(r#"{ name = "test", values = [1, 2.5, 'Ok], total = 1 + 2 }"#).unwrap();
+            let handle = nickel_program_new(code.as_ptr());
+            assert!(!handle.is_null());
+
+            let first = nickel_program_eval_native(handle);
+            assert!(!first.data.is_null());
+            let second = nickel_program_eval_native(handle);
+            assert!(!second.data.is_null());
+
+            let first_bytes = std::slice::from_raw_parts(first.data, first.len).to_vec();
+            let second_bytes = std::slice::from_raw_parts(second.data, second.len).to_vec();
+            assert_eq!(first_bytes, second_bytes);
+
+            let direct = nickel_eval_native(code.as_ptr());
+            assert_eq!(std::slice::from_raw_parts(direct.data, direct.len), &first_bytes[..]);
+
+            nickel_free_buffer(first);
+            nickel_free_buffer(second);
+            nickel_free_buffer(direct);
+            nickel_program_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_program_new_syntax_error() {
+        unsafe {
+            let code = CString::new("{ x = }").unwrap();
+            let handle = nickel_program_new(code.as_ptr());
+            assert!(handle.is_null());
+            assert!(!nickel_get_error().is_null());
+            nickel_program_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_program_new_type_error() {
+        unsafe {
+            let code = CString::new("let x : Number = \"one\" in x").unwrap();
+            let handle = nickel_program_new(code.as_ptr());
+            assert!(handle.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("incompatible types"), "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_program_override_reeval() {
+        unsafe {
+            let code = CString::new("{ server = { port | Number = 80, host = \"localhost\" }, debug = false }").unwrap();
+            let handle = nickel_program_new(code.as_ptr());
+            assert!(!handle.is_null());
+
+            let reeval = |handle| {
+                let result = nickel_program_reeval_json(handle);
+                assert!(!result.is_null());
+                let json: serde_json::Value =
+                    serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+                nickel_free_string(result);
+                json
+            };
+
+            let port = CString::new("server.port").unwrap();
+            let debug = CString::new("debug").unwrap();
+            assert_eq!(nickel_program_set_override(handle, port.as_ptr(), c"8080".as_ptr()), 0);
+            assert_eq!(nickel_program_set_override(handle, debug.as_ptr(), c"true".as_ptr()), 0);
+            let first = reeval(handle);
+            assert_eq!(first["server"]["port"], 8080);
+            assert_eq!(first["debug"], true);
+
+            assert_eq!(nickel_program_set_override(handle, port.as_ptr(), c"9090".as_ptr()), 0);
+            let second = reeval(handle);
+            assert_eq!(second["server"]["port"], 9090);
+            assert_eq!(second["server"]["host"], "localhost");
+            assert_eq!(second["debug"], true);
+
+            assert_eq!(nickel_program_set_override(handle, port.as_ptr(), c"\"http\"".as_ptr()), 0);
+            assert!(nickel_program_reeval_json(handle).is_null());
+            assert_eq!(nickel_program_set_override(handle, port.as_ptr(), c"{ oops".as_ptr()), -1);
+            assert!(!nickel_get_error().is_null());
+
+            nickel_program_free(handle);
+
+            // Fields depending on an overridden one follow it on every evaluation
+            let code = CString::new("{ port = 80, url = \"http://localhost:%{std.to_string port}\" }").unwrap();
+            let handle = nickel_program_new(code.as_ptr());
+            let port = CString::new("port").unwrap();
+            for value in [c"8080", c"9090"] {
+                assert_eq!(nickel_program_set_override(handle, port.as_ptr(), value.as_ptr()), 0);
+                let url = format!("http://localhost:{}", value.to_str().unwrap());
+                assert_eq!(reeval(handle)["url"], url.as_str());
+            }
+            nickel_program_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_native_float_lossy_flag() {
+        unsafe {
+            let decode = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                assert_eq!(bytes.len(), 10);
+                assert_eq!(bytes[0], TYPE_FLOAT);
+                (f64::from_le_bytes(bytes[1..9].try_into().unwrap()), bytes[9])
+            };
+
+            assert_eq!(decode("2.5"), (2.5, 0));
+            // 10^20 exceeds i64 but is exactly representable as an f64
+            assert_eq!(decode("std.number.pow 10 20"), (1e20, 0));
+            assert_eq!(decode("std.number.pow 10 20 + 1"), (1e20, 1));
+            assert_eq!(decode("0.1"), (0.1, 1));
+            // Rounds to a whole f64, but the rational itself isn't an integer
+            assert_eq!(decode("9007199254740993 / 2"), (4503599627370496.0, 1));
+        }
+    }
+
+    #[test]
+    fn test_native_integral_literals() {
+        unsafe {
+            // `2.0` is the rational 2, indistinguishable from `2` after parsing
+            for code in ["2", "2.0", "4 / 2"] {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                assert_eq!(bytes, [&[TYPE_INT][..], &2i64.to_le_bytes()].concat());
+            }
+        }
+    }
+
+    #[test]
+    fn test_native_i64_bounds() {
+        unsafe {
+            let decode = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                (bytes[0], <[u8; 8]>::try_from(&bytes[1..9]).unwrap())
+            };
+
+            let (tag, bytes) = decode("9223372036854775807");
+            assert_eq!(tag, TYPE_INT);
+            assert_eq!(i64::from_le_bytes(bytes), i64::MAX);
+
+            let (tag, bytes) = decode("9223372036854775806");
+            assert_eq!(tag, TYPE_INT);
+            assert_eq!(i64::from_le_bytes(bytes), i64::MAX - 1);
+
+            let (tag, bytes) = decode("-9223372036854775808");
+            assert_eq!(tag, TYPE_INT);
+            assert_eq!(i64::from_le_bytes(bytes), i64::MIN);
+
+            // One past i64::MAX no longer fits and falls back to a float
+            let (tag, bytes) = decode("9223372036854775808");
+            assert_eq!(tag, TYPE_FLOAT);
+            assert_eq!(f64::from_le_bytes(bytes), 9223372036854775808.0);
+        }
+    }
+
+    #[test]
+    fn test_rounding_mode() {
+        unsafe {
+            let code = CString::new("0.1").unwrap();
+            let decode = |buffer: &NativeBuffer| {
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len);
+                assert_eq!(bytes[0], TYPE_FLOAT);
+                f64::from_le_bytes(bytes[1..9].try_into().unwrap())
+            };
+
+            let nearest = nickel_eval_native(code.as_ptr());
+            let nearest_value = decode(&nearest);
+            nickel_free_buffer(nearest);
+
+            assert_eq!(nickel_set_rounding_mode(1), 0);
+            let down = nickel_eval_native(code.as_ptr());
+            let down_value = decode(&down);
+            nickel_free_buffer(down);
+
+            let mut scalar = 0f64;
+            assert_eq!(nickel_eval_f64(code.as_ptr(), &mut scalar), 0);
+            assert_eq!(nickel_set_rounding_mode(0), 0);
+
+            // 0.1 is slightly below its nearest f64, so rounding down gives the predecessor
+            assert_eq!(nearest_value, 0.1);
+            assert!(down_value < nearest_value);
+            assert_eq!(down_value, f64::from_bits(nearest_value.to_bits() - 1));
+            assert_eq!(scalar, down_value);
+
+            assert_eq!(nickel_set_rounding_mode(9), -1);
+        }
+    }
+
+    #[test]
+    fn test_strict_numbers() {
+        unsafe {
+            nickel_set_strict_numbers(true);
+
+            let code = CString::new("{ ratio = 1 / 3 }").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("at `ratio`"), "Unexpected error: {}", error);
+            assert!(nickel_eval_native(code.as_ptr()).data.is_null());
+            let mut float = 0f64;
+            assert_eq!(nickel_eval_f64(code.as_ptr(), &mut float), -1);
+
+            let code = CString::new("{ ratio = 0.5, big = std.number.pow 2 63 }").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null());
+            nickel_free_string(result);
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            nickel_free_buffer(buffer);
+
+            // Exact in JSON as a u64, but not as an i64 or f64
+            let code = CString::new("std.number.pow 2 63 + 1").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null());
+            nickel_free_string(result);
+            assert!(nickel_eval_native(code.as_ptr()).data.is_null());
+
+            nickel_set_strict_numbers(false);
+            let code = CString::new("1 / 3").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_max_elements() {
+        unsafe {
+            nickel_set_max_elements(3);
+
+            let code = CString::new("{ small = [1, 2, 3] }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            nickel_free_buffer(buffer);
+
+            let code = CString::new("{ big = std.array.range 0 1000 }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(buffer.data.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("1000 elements at `big`"), "Unexpected error: {}", error);
+
+            let code = CString::new("{ a = 1, b = 2, c = 3, d = 4 }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(buffer.data.is_null());
+
+            nickel_set_max_elements(u64::MAX);
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_prelude() {
+        unsafe {
+            let prelude = CString::new("{ double = fun x => x * 2, greeting = \"hello\" }").unwrap();
+            assert_eq!(nickel_set_prelude(prelude.as_ptr()), 0);
+
+            let code = CString::new("{ value = double 21, message = \"%{greeting} world\" }").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json, serde_json::json!({ "value": 42, "message": "hello world" }));
+            nickel_free_string(result);
+
+            // The prelude is also in scope for type inference
+            let code = CString::new("double 21").unwrap();
+            let mut json: *const c_char = ptr::null();
+            let mut typ: *const c_char = ptr::null();
+            assert_eq!(nickel_eval_with_type(code.as_ptr(), &mut json, &mut typ), 0);
+            assert_eq!(CStr::from_ptr(json).to_str().unwrap(), "42");
+            nickel_free_string(json);
+            nickel_free_string(typ);
+
+            // Snippets can shadow prelude definitions
+            let code = CString::new("let double = fun x => x in double 21").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "21");
+            nickel_free_string(result);
+
+            nickel_clear_prelude();
+            let code = CString::new("double 21").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(result.is_null());
+        }
+    }
+
+    #[test]
+    fn test_eval_deterministic() {
+        unsafe {
+            // Hash-ordered internals (record fields, merges) must not leak into results
+            let code = CString::new(
+                r#"let base = std.array.fold_left (fun acc i => acc & { "k%{std.to_string i}" = i }) {} (std.array.range 0 200) in
+                { fields = std.record.fields base, merged = base & { extra = 1 } }"#,
+            )
+            .unwrap();
+            let eval = || {
+                let result = nickel_eval_string(code.as_ptr());
+                assert!(!result.is_null());
+                let json = CStr::from_ptr(result).to_str().unwrap().to_string();
+                nickel_free_string(result);
+                json
+            };
+            assert_eq!(eval(), eval());
+        }
+    }
+
+    #[test]
+    fn test_reset() {
+        unsafe {
+            let prelude = CString::new("{ double = fun x => x * 2 }").unwrap();
+            assert_eq!(nickel_set_prelude(prelude.as_ptr()), 0);
+            assert_eq!(nickel_set_rounding_mode(3), 0);
+            nickel_set_strict_numbers(true);
+            nickel_set_max_elements(1);
+            nickel_set_eval_fuel(1);
+            nickel_set_progress_callback(Some(record_progress));
+            nickel_set_import_resolver(Some(serve_modules));
+            let key = CString::new("FOO").unwrap();
+            assert_eq!(nickel_set_env(key.as_ptr(), key.as_ptr()), 0);
+            let code = CString::new("{ x = 1 / 3 }").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            assert!(!nickel_get_error().is_null());
+
+            nickel_reset();
+
+            assert!(nickel_get_error().is_null());
+            assert_eq!(nickel_get_error_len(), 0);
+            assert!(PRELUDE.with(|p| p.borrow().is_none()));
+            assert_eq!(rounding_mode(), RoundingMode::Nearest);
+            assert!(!strict_numbers());
+            assert!(import_resolver().is_none());
+            assert!(PROGRESS_CALLBACK.with(|c| c.get()).is_none());
+            assert!(ENV.with(|env| env.borrow().is_empty()));
+
+            let code = CString::new("[1, 1 / 3]").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    extern "C" fn serve_modules(path: *const c_char) -> *const c_char {
+        let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+        let source: &CStr = match path {
+            "lib.ncl" => c"{ double = fun x => x * 2, scale = (import \"util/scale.ncl\").factor }",
+            "util/scale.ncl" => c"{ factor = import \"factor.ncl\" }",
+            "util/factor.ncl" => c"10",
+            "config.ncl" => c"{ name = \"app\", secret | not_exported = \"hidden\" }",
+            _ => return ptr::null(),
+        };
+        source.as_ptr()
+    }
+
+    #[test]
+    fn test_import_resolver() {
+        unsafe {
+            nickel_set_import_resolver(Some(serve_modules));
+
+            // Imports of served files are resolved relative to the importing file
+            let code = CString::new("let lib = import \"lib.ncl\" in lib.double lib.scale").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "20");
+            nickel_free_string(result);
+
+            // Served files are exported like any other source
+            let code = CString::new("import \"config.ncl\"").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json, serde_json::json!({ "name": "app" }));
+            nickel_free_string(result);
+
+            // Paths the resolver doesn't know fall back to the filesystem
+            let code = CString::new("import \"nowhere.ncl\"").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("could not find import"), "Unexpected error: {}", error);
+
+            nickel_set_import_resolver(None);
+            let code = CString::new("import \"lib.ncl\"").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+        }
+    }
+
+    #[test]
+    fn test_env() {
+        unsafe {
+            let key = CString::new("FOO").unwrap();
+            let value = CString::new("bar \"%{baz}\"").unwrap();
+            assert_eq!(nickel_set_env(key.as_ptr(), value.as_ptr()), 0);
+            let key = CString::new("PORT").unwrap();
+            let value = CString::new("8080").unwrap();
+            assert_eq!(nickel_set_env(key.as_ptr(), value.as_ptr()), 0);
+
+            // Values are always strings, taken verbatim
+            let code = CString::new("{ foo = env.FOO, port = env.PORT, keys = std.record.fields env }").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json, serde_json::json!({
+                "foo": "bar \"%{baz}\"",
+                "port": "8080",
+                "keys": ["FOO", "PORT"],
+            }));
+            nickel_free_string(result);
+
+            // Line numbers in errors are those of the snippet
+            let code = CString::new("{\n  x = env.MISSING,\n}").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("<ffi>:2:"), "Unexpected error: {}", error);
+
+            nickel_clear_env();
+            let code = CString::new("env.FOO").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+        }
+    }
+
+    #[test]
+    fn test_eval_fuel() {
+        unsafe {
+            nickel_set_eval_fuel(10_000);
+
+            let code = CString::new("let rec loop = fun n => if n < 0 then n else loop (n + 1) in { x = loop 0 }").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let first = CStr::from_ptr(nickel_get_error()).to_str().unwrap().to_string();
+            assert!(first.contains("Evaluation fuel exhausted"), "Unexpected error: {}", first);
+
+            // Runs fail identically, and each evaluation gets the full budget
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            assert_eq!(CStr::from_ptr(nickel_get_error()).to_str().unwrap(), first);
+            let code = CString::new("std.array.fold_left (+) 0 (std.array.range 0 100)").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "4950");
+            nickel_free_string(result);
+
+            nickel_set_eval_fuel(u64::MAX);
+        }
+    }
+
+    thread_local! {
+        static PROGRESS_REPORTS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "C" fn record_progress(forced: u64) {
+        PROGRESS_REPORTS.with(|r| r.borrow_mut().push(forced));
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        unsafe {
+            nickel_set_progress_callback(Some(record_progress));
+
+            let code = CString::new("std.array.generate (fun i => i * 2) 100000").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            nickel_free_buffer(buffer);
+
+            let reports = PROGRESS_REPORTS.with(|r| r.take());
+            assert!(reports.len() >= 10, "Expected at least 10 reports, got {}", reports.len());
+            let expected: Vec<u64> = (1..=reports.len() as u64).map(|i| i * PROGRESS_INTERVAL).collect();
+            assert_eq!(reports, expected);
+
+            // Short evaluations don't report, and the count restarts with each one
+            let code = CString::new("1 + 1").unwrap();
+            nickel_free_string(nickel_eval_string(code.as_ptr()));
+            assert!(PROGRESS_REPORTS.with(|r| r.borrow().is_empty()));
+
+            nickel_set_progress_callback(None);
+            let code = CString::new("std.array.generate (fun i => i * 2) 100000").unwrap();
+            nickel_free_string(nickel_eval_string(code.as_ptr()));
+            assert!(PROGRESS_REPORTS.with(|r| r.borrow().is_empty()));
+        }
+    }
+
+    #[test]
+    fn test_prelude_must_be_record() {
+        unsafe {
+            let prelude = CString::new("[1, 2]").unwrap();
+            assert_eq!(nickel_set_prelude(prelude.as_ptr()), -1);
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("must be a record"), "Unexpected error: {}", error);
+
+            let prelude = CString::new("{ \"not an ident\" = 1 }").unwrap();
+            assert_eq!(nickel_set_prelude(prelude.as_ptr()), -1);
+        }
+    }
+
+    #[test]
+    fn test_encoder_length_guard() {
+        let term = eval_nickel_term(r#"{ a = "ab", b = [1, 2, 3], c = "abcd" }"#).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut encoder = Encoder::new(&mut buffer);
+        encoder.max_len = 3;
+        let error = encoder.encode(&term).unwrap_err();
+        assert!(error.contains("Length 4 at `c`"), "Unexpected error: {}", error);
+
+        // Arrays and records are checked too
+        let mut buffer = Vec::new();
+        let mut encoder = Encoder::new(&mut buffer);
+        encoder.max_len = 2;
+        let error = encoder.encode(&term).unwrap_err();
+        assert!(error.contains("Length 3 at `<root>`"), "Unexpected error: {}", error);
+
+        // Varint lengths have no such limit
+        let mut buffer = Vec::new();
+        let mut encoder = Encoder::new(&mut buffer);
+        encoder.max_len = 2;
+        encoder.varint_lengths = true;
+        assert!(encoder.encode(&term).is_ok());
+    }
+
+    #[test]
+    fn test_native_varint_lengths() {
+        unsafe {
+            let fields: Vec<String> = (0..100).map(|i| format!("k{} = \"v{}\"", i, i)).collect();
+            let code = CString::new(format!("{{ {} }}", fields.join(", "))).unwrap();
+
+            let fixed = nickel_eval_native(code.as_ptr());
+            let varint = nickel_eval_native_varint(code.as_ptr());
+            assert!(!fixed.data.is_null() && !varint.data.is_null());
+            let fixed_bytes = std::slice::from_raw_parts(fixed.data, fixed.len).to_vec();
+            let varint_bytes = std::slice::from_raw_parts(varint.data, varint.len).to_vec();
+            nickel_free_buffer(fixed);
+            nickel_free_buffer(varint);
+
+            // Each key and value saves 3 bytes, the field count 3, at the cost of the protocol byte
+            assert_eq!(fixed_bytes.len() - varint_bytes.len(), 100 * 2 * 3 + 3 - 1);
+            assert_eq!(&varint_bytes[..3], &[PROTOCOL_VARINT, TYPE_RECORD, 100]);
+        }
+    }
+
+    /// Check the trailer of a `nickel_eval_native_checked` buffer, as a decoder
+    /// would, and return the encoded value.
+    fn verify_checked(bytes: &[u8]) -> Result<&[u8], String> {
+        if bytes.len() < 1 + CHECKED_TRAILER_LEN || bytes[0] != PROTOCOL_CHECKED {
+            return Err("not a checked buffer".to_string());
+        }
+        let (rest, trailer) = bytes.split_at(bytes.len() - CHECKED_TRAILER_LEN);
+        let total_len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        if total_len != bytes.len() as u64 {
+            return Err(format!("length mismatch: expected {}, got {}", total_len, bytes.len()));
+        }
+        let checksum = u32::from_le_bytes(trailer[8..].try_into().unwrap());
+        if checksum != crc32(&rest[1..]) {
+            return Err("checksum mismatch".to_string());
+        }
+        Ok(&rest[1..])
+    }
+
+    #[test]
+    fn test_native_checked() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        unsafe {
+            let code = CString::new("{ name = \"test\", values = [1, 2, 3] }").unwrap();
+            let plain = nickel_eval_native(code.as_ptr());
+            let checked = nickel_eval_native_checked(code.as_ptr());
+            assert!(!plain.data.is_null() && !checked.data.is_null());
+            let plain_bytes = std::slice::from_raw_parts(plain.data, plain.len).to_vec();
+            let checked_bytes = std::slice::from_raw_parts(checked.data, checked.len).to_vec();
+            nickel_free_buffer(plain);
+            nickel_free_buffer(checked);
+
+            assert_eq!(verify_checked(&checked_bytes), Ok(&plain_bytes[..]));
+
+            let mut corrupted = checked_bytes.clone();
+            corrupted[5] ^= 0x01;
+            assert_eq!(verify_checked(&corrupted), Err("checksum mismatch".to_string()));
+
+            // A truncated buffer no longer ends with its own trailer
+            let truncated = &checked_bytes[..checked_bytes.len() - 3];
+            assert!(verify_checked(truncated).is_err());
+        }
+    }
+
+    #[test]
+    fn test_native_packed_arrays() {
+        unsafe {
+            let code = CString::new("std.array.generate (fun i => i % 3 == 0) 10000").unwrap();
+            let plain = nickel_eval_native(code.as_ptr());
+            let packed = nickel_eval_native_packed(code.as_ptr());
+            assert!(!plain.data.is_null() && !packed.data.is_null());
+            let plain_bytes = std::slice::from_raw_parts(plain.data, plain.len).to_vec();
+            let packed_bytes = std::slice::from_raw_parts(packed.data, packed.len).to_vec();
+            nickel_free_buffer(plain);
+            nickel_free_buffer(packed);
+
+            // Two bytes per element versus one bit, plus headers
+            assert_eq!(plain_bytes.len(), 1 + 4 + 2 * 10000);
+            assert_eq!(packed_bytes.len(), 1 + 2 + 4 + 10000 / 8);
+            assert_eq!(&packed_bytes[..7], &[PROTOCOL_PACKED, TYPE_PACKED_ARRAY, TYPE_BOOL, 0x10, 0x27, 0, 0]);
+            // Elements 0, 3 and 6 are true
+            assert_eq!(packed_bytes[7], 0b0100_1001);
+
+            let code = CString::new("{ ints = [1, -2], mixed = [1, true], empty = [] }").unwrap();
+            let packed = nickel_eval_native_packed(code.as_ptr());
+            assert!(!packed.data.is_null());
+            let bytes = std::slice::from_raw_parts(packed.data, packed.len).to_vec();
+            nickel_free_buffer(packed);
+            let ints = [&[TYPE_PACKED_ARRAY, TYPE_INT, 2, 0, 0, 0][..], &1i64.to_le_bytes(), &(-2i64).to_le_bytes()].concat();
+            assert!(bytes.windows(ints.len()).any(|w| w == ints));
+            assert!(bytes.windows(5).any(|w| w == [TYPE_ARRAY, 2, 0, 0, 0]));
+            assert!(bytes.windows(5).any(|w| w == [TYPE_ARRAY, 0, 0, 0, 0]));
+        }
+    }
+
+    #[test]
+    fn test_native_varint_multibyte_length() {
+        unsafe {
+            let code = CString::new("std.string.join \"\" (std.array.replicate 300 \"x\")").unwrap();
+            let buffer = nickel_eval_native_varint(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+            nickel_free_buffer(buffer);
+
+            // 300 = 0b10_0101100 -> [0xAC, 0x02]
+            assert_eq!(&bytes[..4], &[PROTOCOL_VARINT, TYPE_STRING, 0xAC, 0x02]);
+            assert_eq!(bytes.len(), 4 + 300);
+        }
+    }
+
+    #[test]
+    fn test_eval_native_into() {
+        unsafe {
+            let code = CString::new("{ name = \"test\", values = [1, 2, 3] }").unwrap();
+            let expected = nickel_eval_native(code.as_ptr());
+            let expected_bytes = std::slice::from_raw_parts(expected.data, expected.len).to_vec();
+            nickel_free_buffer(expected);
+
+            // Query the size first
+            let mut len = 0usize;
+            assert_eq!(nickel_eval_native_into(code.as_ptr(), ptr::null_mut(), 0, &mut len), 1);
+            assert_eq!(len, expected_bytes.len());
+
+            // Too small by one byte: nothing is written
+            let mut buf = vec![0xAAu8; len - 1];
+            assert_eq!(nickel_eval_native_into(code.as_ptr(), buf.as_mut_ptr(), buf.len(), &mut len), 1);
+            assert_eq!(len, expected_bytes.len());
+            assert!(buf.iter().all(|&b| b == 0xAA));
+
+            let mut buf = vec![0u8; len + 8];
+            let mut written = 0usize;
+            assert_eq!(nickel_eval_native_into(code.as_ptr(), buf.as_mut_ptr(), buf.len(), &mut written), 0);
+            assert_eq!(written, expected_bytes.len());
+            assert_eq!(&buf[..written], &expected_bytes[..]);
+        }
+    }
+
+    #[test]
+    fn test_native_function() {
+        unsafe {
+            let decode = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                bytes
+            };
+
+            assert_eq!(decode("fun x y => x + y"), [TYPE_FUNCTION, 2, 0, 0, 0, 0, 0, 0, 0]);
+            assert_eq!(decode("match { 'A => 1, _ => 2 }"), [TYPE_FUNCTION, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+            // The annotation gives the signature and the arity of the wrapped function
+            let bytes = decode("{ add | Number -> Number -> Number = fun x y => x + y }");
+            let signature = b"Number -> Number -> Number";
+            assert_eq!(bytes[12..17], [TYPE_FUNCTION, 2, 0, 0, 0]);
+            assert_eq!(bytes[17..21], (signature.len() as u32).to_le_bytes());
+            assert_eq!(&bytes[21..], signature);
+        }
+    }
+
+    #[test]
+    fn test_eval_with_stats() {
+        unsafe {
+            let code = CString::new("std.array.fold_left (+) 0 (std.array.range 0 1000)").unwrap();
+            let mut json: *const c_char = ptr::null();
+            let mut stats: *const c_char = ptr::null();
+            assert_eq!(nickel_eval_with_stats(code.as_ptr(), &mut json, &mut stats), 0);
+            assert_eq!(CStr::from_ptr(json).to_str().unwrap(), "499500");
+            let parsed: serde_json::Value = serde_json::from_str(CStr::from_ptr(stats).to_str().unwrap()).unwrap();
+            assert!(parsed["eval_us"].as_u64().is_some(), "Unexpected stats: {}", parsed);
+            let steps = parsed["steps"].as_u64().unwrap();
+            assert!(steps > 1000, "Unexpected stats: {}", parsed);
+            nickel_free_string(json);
+            nickel_free_string(stats);
+
+            // Steps count the work done, so a smaller fold takes fewer of them
+            let code = CString::new("std.array.fold_left (+) 0 (std.array.range 0 10)").unwrap();
+            assert_eq!(nickel_eval_with_stats(code.as_ptr(), &mut json, &mut stats), 0);
+            let parsed: serde_json::Value = serde_json::from_str(CStr::from_ptr(stats).to_str().unwrap()).unwrap();
+            assert!(parsed["steps"].as_u64().unwrap() < steps, "Unexpected stats: {}", parsed);
+            nickel_free_string(json);
+            nickel_free_string(stats);
+
+            let code = CString::new("1 + \"a\"").unwrap();
+            assert_eq!(nickel_eval_with_stats(code.as_ptr(), &mut json, &mut stats), -1);
+            assert!(json.is_null() && stats.is_null());
+        }
+    }
+
+    #[test]
+    fn test_eval_with_type() {
+        unsafe {
+            let mut json: *const c_char = ptr::null();
+            let mut typ: *const c_char = ptr::null();
+
+            let code = CString::new("1 + 1").unwrap();
+            assert_eq!(nickel_eval_with_type(code.as_ptr(), &mut json, &mut typ), 0);
+            assert_eq!(CStr::from_ptr(json).to_str().unwrap(), "2");
+            assert_eq!(CStr::from_ptr(typ).to_str().unwrap(), "Number");
+            nickel_free_string(json);
+            nickel_free_string(typ);
+
+            let code = CString::new("{ name = \"x\", count = 1 }").unwrap();
+            assert_eq!(nickel_eval_with_type(code.as_ptr(), &mut json, &mut typ), 0);
+            assert_eq!(CStr::from_ptr(typ).to_str().unwrap(), "{ count : Number, name : String }");
+            nickel_free_string(json);
+            nickel_free_string(typ);
+
+            // A function has a type but no JSON value
+            let code = CString::new("(fun x => x + 1) : Number -> Number").unwrap();
+            assert_eq!(nickel_eval_with_type(code.as_ptr(), &mut json, &mut typ), 1);
+            assert!(json.is_null());
+            assert_eq!(CStr::from_ptr(typ).to_str().unwrap(), "Number -> Number");
+            nickel_free_string(typ);
+
+            // Dynamically typed code falls back to its apparent type
+            let code = CString::new("std.array.at 0 [1, \"a\"]").unwrap();
+            assert_eq!(nickel_eval_with_type(code.as_ptr(), &mut json, &mut typ), 0);
+            assert_eq!(CStr::from_ptr(typ).to_str().unwrap(), "Dyn");
+            nickel_free_string(json);
+            nickel_free_string(typ);
+        }
+    }
+
+    #[test]
+    fn test_check_syntax() {
+        unsafe {
+            // Valid syntax that would never finish evaluating
+            let code = CString::new("let rec loop = fun x => loop x in loop 1").unwrap();
+            assert_eq!(nickel_check_syntax(code.as_ptr()), 0);
+
+            // Parsing doesn't typecheck or resolve variables
+            let code = CString::new("{ a = undefined_variable + \"text\" }").unwrap();
+            assert_eq!(nickel_check_syntax(code.as_ptr()), 0);
+
+            let code = CString::new("{ a = 1,\n  b = }").unwrap();
+            assert_eq!(nickel_check_syntax(code.as_ptr()), -1);
+            assert!(!nickel_get_error().is_null());
+            let mut line = 0u32;
+            assert_eq!(nickel_get_error_position(&mut line, ptr::null_mut(), ptr::null_mut()), 0);
+            assert_eq!(line, 2);
+
+            assert_eq!(nickel_check_syntax(ptr::null()), -1);
+        }
+    }
+
+    #[test]
+    fn test_eval_scalars() {
+        unsafe {
+            let code = CString::new("40 + 2").unwrap();
+            let mut int = 0i64;
+            assert_eq!(nickel_eval_i64(code.as_ptr(), &mut int), 0);
+            assert_eq!(int, 42);
+
+            let mut float = 0f64;
+            assert_eq!(nickel_eval_f64(code.as_ptr(), &mut float), 0);
+            assert_eq!(float, 42.0);
+
+            let code = CString::new("1 < 2").unwrap();
+            let mut flag = false;
+            assert_eq!(nickel_eval_bool(code.as_ptr(), &mut flag), 0);
+            assert!(flag);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        unsafe {
+            let contract = "let Port = std.contract.from_predicate (fun p => std.is_number p && p > 0) in";
+            for (code, expected) in [
+                (format!("{} {{ port | Port = 8080 }}", contract), 1),
+                (format!("{} std.contract.apply Port 80", contract), 1),
+                ("1 + 1 == 2".to_string(), 1),
+                (format!("{} {{ port | Port = -1 }}", contract), 0),
+                ("std.fail_with \"no\"".to_string(), 0),
+                ("1 + 1 == 3".to_string(), 0),
+                ("{ port = }".to_string(), -1),
+                ("1 + \"a\"".to_string(), -1),
+            ] {
+                let c_code = CString::new(code.as_str()).unwrap();
+                assert_eq!(nickel_validate(c_code.as_ptr()), expected, "for `{}`", code);
+            }
+
+            // Contract failures are described like evaluation errors
+            let code = CString::new(format!("{} {{ port | Port = -1 }}", contract)).unwrap();
+            assert_eq!(nickel_validate(code.as_ptr()), 0);
+            let mut field: *const c_char = ptr::null();
+            let mut message: *const c_char = ptr::null();
+            assert_eq!(nickel_get_contract_failure(&mut field, &mut message), 0);
+            assert_eq!(CStr::from_ptr(field).to_str().unwrap(), "port");
+        }
+    }
+
+    #[test]
+    fn test_eval_scalars_type_mismatch() {
+        unsafe {
+            let code = CString::new("\"42\"").unwrap();
+            let mut int = 7i64;
+            assert_ne!(nickel_eval_i64(code.as_ptr(), &mut int), 0);
+            assert_eq!(int, 7);
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("got a string"), "Unexpected error: {}", error);
+
+            let code = CString::new("1.5").unwrap();
+            assert_ne!(nickel_eval_i64(code.as_ptr(), &mut int), 0);
+
+            let mut flag = false;
+            assert_ne!(nickel_eval_bool(code.as_ptr(), &mut flag), 0);
+        }
+    }
+
+    #[test]
+    fn test_eval_shape() {
+        unsafe {
+            // Neither the expensive field nor the failing one is evaluated
+            let code = CString::new(
+                r#"{
+                    cheap = 1,
+                    expensive = std.array.fold_left (+) 0 (std.array.range 0 10000000),
+                    broken = std.fail_with "forced",
+                }"#,
+            )
+            .unwrap();
+            let mut count = 0u64;
+            assert_eq!(nickel_eval_shape(code.as_ptr(), &mut count), TYPE_RECORD);
+            assert_eq!(count, 3);
+
+            // Fields marked `not_exported` aren't counted, as in `nickel_record_keys`
+            let code = CString::new("{ a = 1, b | not_exported = 2 }").unwrap();
+            assert_eq!(nickel_eval_shape(code.as_ptr(), &mut count), TYPE_RECORD);
+            assert_eq!(count, 1);
+            let keys = nickel_record_keys(code.as_ptr());
+            assert_eq!(CStr::from_ptr(keys).to_str().unwrap(), "a");
+            nickel_free_string(keys);
+
+            let code = CString::new("[std.fail_with \"forced\", 2]").unwrap();
+            assert_eq!(nickel_eval_shape(code.as_ptr(), &mut count), TYPE_ARRAY);
+            assert_eq!(count, 2);
+
+            let code = CString::new("1.5").unwrap();
+            assert_eq!(nickel_eval_shape(code.as_ptr(), &mut count), TYPE_FLOAT);
+            assert_eq!(count, 0);
+            let code = CString::new("'Foo 1").unwrap();
+            assert_eq!(nickel_eval_shape(code.as_ptr(), ptr::null_mut()), TYPE_ENUM);
+
+            let code = CString::new("fun x => x").unwrap();
+            assert_eq!(nickel_eval_shape(code.as_ptr(), &mut count), SHAPE_ERROR);
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("a function"), "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_record_keys() {
+        unsafe {
+            let code = CString::new(
+                r#"{
+                    b = 2,
+                    a = std.array.fold_left (+) 0 (std.array.range 0 10000000),
+                    c | not_exported = std.fail_with "forced",
+                }"#,
+            )
+            .unwrap();
+            let result = nickel_record_keys(code.as_ptr());
+            assert!(!result.is_null());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "a\nb");
+            nickel_free_string(result);
+
+            let code = CString::new("[1, 2]").unwrap();
+            assert!(nickel_record_keys(code.as_ptr()).is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("got an array"), "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_get_contract_failure() {
+        unsafe {
+            let code = CString::new(r#"{ port | Number = "8080" }"#).unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(result.is_null());
+
+            let mut field: *const c_char = ptr::null();
+            let mut message: *const c_char = ptr::null();
+            assert_eq!(nickel_get_contract_failure(&mut field, &mut message), 0);
+            assert_eq!(CStr::from_ptr(field).to_str().unwrap(), "port");
+            let message = CStr::from_ptr(message).to_str().unwrap();
+            assert!(message.starts_with("expected `Number`"), "Unexpected message: {}", message);
+            assert!(message.contains("\"8080\""), "Unexpected message: {}", message);
+
+            // Custom contracts are reported too
+            let code = CString::new(r#"
+                let Port = std.contract.from_predicate (fun x => x < 65536) in
+                { port | Port = 70000 }
+            "#).unwrap();
+            nickel_eval_string(code.as_ptr());
+            let mut message: *const c_char = ptr::null();
+            assert_eq!(nickel_get_contract_failure(ptr::null_mut(), &mut message), 0);
+            assert!(CStr::from_ptr(message).to_str().unwrap().contains("70000"));
+
+            // Other errors clear the details
+            let code = CString::new("{ a = 1 }.b").unwrap();
+            nickel_eval_string(code.as_ptr());
+            assert_ne!(nickel_get_contract_failure(&mut field, ptr::null_mut()), 0);
+        }
+    }
+
+    #[test]
+    fn test_get_warnings() {
+        unsafe {
+            let code = CString::new(r#"{ a = std.trace "first" 1, b = std.trace "second" 2 }"#).unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null());
+            nickel_free_string(result);
+
+            let warnings = nickel_get_warnings();
+            assert!(!warnings.is_null());
+            let warnings = CStr::from_ptr(warnings).to_str().unwrap();
+            assert_eq!(warnings.lines().count(), 2);
+            assert!(warnings.contains("std.trace: first"));
+            assert!(warnings.contains("std.trace: second"));
+
+            // Warnings are reset by the next evaluation
+            let code = CString::new("1 + 1").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            nickel_free_string(result);
+            assert!(nickel_get_warnings().is_null());
+        }
+    }
+
+    #[test]
+    fn test_eval_string_utf16() {
+        unsafe {
+            let code = CString::new(r#"{ greeting = "héllo 世界 🎉" }"#).unwrap();
+            let buffer = nickel_eval_string_utf16(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            assert_eq!(buffer.len % 2, 0);
+
+            let bytes = std::slice::from_raw_parts(buffer.data, buffer.len);
+            let units: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+            let json = String::from_utf16(&units).unwrap();
+            nickel_free_buffer(buffer);
+
+            let expected = nickel_eval_string(code.as_ptr());
+            assert_eq!(json, CStr::from_ptr(expected).to_str().unwrap());
+            nickel_free_string(expected);
+
+            let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(value["greeting"], "héllo 世界 🎉");
+        }
+    }
+
+    #[test]
+    fn test_eval_json_compact() {
+        unsafe {
+            let code = CString::new(r#"{ server = { host = "localhost", ports = [80, 443] }, debug = true }"#).unwrap();
+            let result = nickel_eval_json_compact(code.as_ptr());
+            assert!(!result.is_null());
+            let compact = CStr::from_ptr(result).to_str().unwrap().to_string();
+            nickel_free_string(result);
+            assert!(!compact.contains('\n'));
+            assert!(!compact.contains(' '));
+
+            let result = nickel_eval_string(code.as_ptr());
+            let pretty: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            nickel_free_string(result);
+            assert_eq!(serde_json::from_str::<serde_json::Value>(&compact).unwrap(), pretty);
+        }
+    }
+
+    #[test]
+    fn test_eval_json_with_spans() {
+        unsafe {
+            let code = CString::new("{\n  name = \"app\",\n  server = { port = 8000 + 80, hosts = [\"a\"] },\n}").unwrap();
+            let result = nickel_eval_json_with_spans(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            nickel_free_string(result);
+
+            assert_eq!(json["value"], serde_json::json!({ "name": "app", "server": { "port": 8080, "hosts": ["a"] } }));
+            let source = code.to_str().unwrap();
+            let spanned = |pointer: &str| {
+                let span = &json["$spans"][pointer];
+                assert_eq!(span["file"], "<ffi>");
+                let (start, end) = (span["start"].as_u64().unwrap() as usize, span["end"].as_u64().unwrap() as usize);
+                &source[start..end]
+            };
+            assert_eq!(spanned("/name"), "\"app\"");
+            assert_eq!(spanned("/server/port"), "8000 + 80");
+            assert_eq!(spanned("/server/hosts"), "[\"a\"]");
+            // Only the first two levels are annotated
+            assert!(json["$spans"].get("/server/hosts/0").is_none());
+        }
+    }
+
+    #[test]
+    fn test_eval_json_indent() {
+        unsafe {
+            let code = CString::new(r#"{ name = "app", ports = [80] }"#).unwrap();
+            let indented = |spaces: u32| {
+                let result = nickel_eval_json_indent(code.as_ptr(), spaces);
+                assert!(!result.is_null());
+                let json = CStr::from_ptr(result).to_str().unwrap().to_string();
+                nickel_free_string(result);
+                json
+            };
+
+            assert_eq!(indented(4), "{\n    \"name\": \"app\",\n    \"ports\": [\n        80\n    ]\n}");
+            assert_eq!(indented(0), r#"{"name":"app","ports":[80]}"#);
+
+            // Two spaces is the default layout
+            let result = nickel_eval_string(code.as_ptr());
+            assert_eq!(indented(2), CStr::from_ptr(result).to_str().unwrap());
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_msgpack() {
+        unsafe {
+            let code = CString::new(
+                r#"{
+                    name = "a longer string that needs a str8 header",
+                    count = 300,
+                    offset = -5,
+                    low = -40000,
+                    ratio = 0.25,
+                    tags = ['a, 'b],
+                    nested = { enabled = true, missing = null },
+                }"#,
+            )
+            .unwrap();
+            let buffer = nickel_eval_msgpack(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let bytes = std::slice::from_raw_parts(buffer.data, buffer.len);
+            let decoded: serde_json::Value = rmp_serde::from_slice(bytes).unwrap();
+            nickel_free_buffer(buffer);
+
+            let result = nickel_eval_string(code.as_ptr());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            nickel_free_string(result);
+            assert_eq!(decoded, json);
+            assert_eq!(decoded["count"], 300);
+            assert_eq!(decoded["low"], -40000);
+
+            let code = CString::new("{ f = fun x => x }").unwrap();
+            let buffer = nickel_eval_msgpack(code.as_ptr());
+            assert!(buffer.data.is_null());
+        }
+    }
+
+    #[test]
+    fn test_eval_cbor() {
+        unsafe {
+            let code = CString::new(
+                r#"{
+                    name = "a string longer than twenty-three bytes",
+                    count = 300,
+                    offset = -5,
+                    low = -40000,
+                    ratio = 0.25,
+                    tags = ['a, 'b],
+                    nested = { enabled = true, missing = null },
+                }"#,
+            )
+            .unwrap();
+            let buffer = nickel_eval_cbor(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let bytes = std::slice::from_raw_parts(buffer.data, buffer.len);
+            let decoded: serde_json::Value = ciborium::from_reader(bytes).unwrap();
+            nickel_free_buffer(buffer);
+
+            let result = nickel_eval_string(code.as_ptr());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            nickel_free_string(result);
+            assert_eq!(decoded, json);
+            assert_eq!(decoded["count"], 300);
+            assert_eq!(decoded["nested"]["enabled"], true);
+
+            let code = CString::new("{ f = fun x => x }").unwrap();
+            let buffer = nickel_eval_cbor(code.as_ptr());
+            assert!(buffer.data.is_null());
+        }
+    }
+
+    #[test]
+    fn test_free_zero_length_buffer() {
+        unsafe {
+            let buffer = native_buffer_from_vec(Vec::new());
+            assert!(!buffer.data.is_null());
+            assert_eq!(buffer.len, 0);
+            nickel_free_buffer(buffer);
+
+            // Spare capacity is dropped before the buffer is handed out
+            let mut bytes = Vec::with_capacity(64);
+            bytes.push(TYPE_NULL);
+            let buffer = native_buffer_from_vec(bytes);
+            assert_eq!(buffer.len, 1);
+            nickel_free_buffer(buffer);
+
+            nickel_free_buffer(NativeBuffer { data: ptr::null_mut(), len: 0 });
+        }
+    }
+
+    #[test]
+    fn test_eval_strict() {
+        unsafe {
+            let cases = [
+                // Closed contracts reject extra fields on their own
+                "{ a = 1, b = 2 } | { a | Number }",
+                // Fields merged in after the contract was applied
+                "{ a | Number } & { a = 1, b = 2 }",
+                "let Schema = { a | Number } in ({ a = 1 } | Schema) & { b = 2 }",
+                // Open contracts
+                "{ config = { a = 1, b = 2 } | { a | Number, .. } }",
+            ];
+            for case in cases {
+                let code = CString::new(case).unwrap();
+                let result = nickel_eval_strict(code.as_ptr());
+                assert!(result.is_null(), "Expected an error for {}", case);
+                let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+                assert!(error.contains('b'), "Unexpected error for {}: {}", case, error);
+            }
+
+            let error = {
+                let code = CString::new("{ config = { a = 1, b = 2 } | { a | Number, .. } }").unwrap();
+                nickel_eval_strict(code.as_ptr());
+                CStr::from_ptr(nickel_get_error()).to_str().unwrap().to_string()
+            };
+            assert!(error.contains("`config.b`"), "Unexpected error: {}", error);
+
+            // Declared fields and unannotated records pass
+            let code = CString::new("{ schema = { a = 1 } | { a | Number }, plain = { x = 1, y = 2 } }").unwrap();
+            let result = nickel_eval_strict(code.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_to_path() {
+        let dir = std::env::temp_dir().join(format!("nickel_jl_to_path_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        unsafe {
+            let code = CString::new("{ name = \"app\", ports = [80, 443] }").unwrap();
+            let yaml_path = dir.join("out.yaml");
+            let path = CString::new(yaml_path.to_str().unwrap()).unwrap();
+            assert_eq!(nickel_eval_to_path(code.as_ptr(), path.as_ptr()), 0);
+            let yaml = std::fs::read_to_string(&yaml_path).unwrap();
+            assert_eq!(yaml.trim_end(), "name: app\nports:\n- 80\n- 443");
+
+            let toml_path = dir.join("out.toml");
+            let path = CString::new(toml_path.to_str().unwrap()).unwrap();
+            assert_eq!(nickel_eval_to_path(code.as_ptr(), path.as_ptr()), 0);
+            let toml = std::fs::read_to_string(&toml_path).unwrap();
+            assert!(toml.contains("name = \"app\""), "Unexpected TOML: {}", toml);
+            assert!(nickel_get_warnings().is_null());
+
+            // Unknown extensions fall back to JSON, with a warning
+            let txt_path = dir.join("out.txt");
+            let path = CString::new(txt_path.to_str().unwrap()).unwrap();
+            assert_eq!(nickel_eval_to_path(code.as_ptr(), path.as_ptr()), 0);
+            let json: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&txt_path).unwrap()).unwrap();
+            assert_eq!(json, serde_json::json!({ "name": "app", "ports": [80, 443] }));
+            let warnings = CStr::from_ptr(nickel_get_warnings()).to_str().unwrap();
+            assert!(warnings.contains("unknown extension `txt`"), "Unexpected warnings: {}", warnings);
+
+            // Nothing is written when evaluation fails
+            let code = CString::new("{ a = 1 + \"x\" }").unwrap();
+            let failed_path = dir.join("failed.json");
+            let path = CString::new(failed_path.to_str().unwrap()).unwrap();
+            assert_eq!(nickel_eval_to_path(code.as_ptr(), path.as_ptr()), -1);
+            assert!(!failed_path.exists());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_eval_multi() {
+        unsafe {
+            let code = CString::new("{ a = 1 }\n---\n{ b = }\n---  \n[1, 2]\n").unwrap();
+            let mut out: *const c_char = ptr::null();
+            assert_eq!(nickel_eval_multi(code.as_ptr(), &mut out), 1);
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(out).to_str().unwrap()).unwrap();
+            nickel_free_string(out);
+
+            let results = json.as_array().unwrap();
+            assert_eq!(results.len(), 3);
+            assert_eq!(results[0], serde_json::json!({ "value": { "a": 1 } }));
+            assert_eq!(results[2], serde_json::json!({ "value": [1, 2] }));
+            let error = results[1]["error"].as_str().unwrap();
+            assert!(error.contains("<ffi>:3:"), "Unexpected error: {}", error);
+
+            let code = CString::new("1 + 1").unwrap();
+            assert_eq!(nickel_eval_multi(code.as_ptr(), &mut out), 0);
+            assert_eq!(CStr::from_ptr(out).to_str().unwrap(), r#"[{"value":2}]"#);
+            nickel_free_string(out);
+        }
+    }
+
+    #[test]
+    fn test_eval_batch() {
+        unsafe {
+            let snippets = [
+                CString::new("1 + 1").unwrap(),
+                CString::new("{ a = 1 }.b").unwrap(),
+                CString::new("\"hello\"").unwrap(),
+            ];
+            let codes: Vec<*const c_char> = snippets.iter().map(|c| c.as_ptr()).collect();
+            let mut out = [ptr::null::<c_char>(); 3];
+            let mut errors = [ptr::null::<c_char>(); 3];
+
+            let failures = nickel_eval_batch(codes.as_ptr(), codes.len(), out.as_mut_ptr(), errors.as_mut_ptr());
+            assert_eq!(failures, 1);
+
+            assert_eq!(CStr::from_ptr(out[0]).to_str().unwrap(), "2");
+            assert!(errors[0].is_null());
+
+            assert!(out[1].is_null());
+            let error = CStr::from_ptr(errors[1]).to_str().unwrap();
+            assert!(error.contains("missing field"), "Unexpected error: {}", error);
+
+            assert_eq!(CStr::from_ptr(out[2]).to_str().unwrap(), "\"hello\"");
+            assert!(errors[2].is_null());
+
+            for ptr in out.iter().chain(errors.iter()) {
+                nickel_free_string(*ptr);
+            }
+
+            // Without an error array, failures are still counted
+            let failures = nickel_eval_batch(codes.as_ptr(), codes.len(), out.as_mut_ptr(), ptr::null_mut());
+            assert_eq!(failures, 1);
+            for ptr in out.iter() {
+                nickel_free_string(*ptr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_doc() {
+        unsafe {
+            let code = CString::new(r#"{
+                server = {
+                    port | Number | doc "Port the server listens on" = 8080,
+                    host = "localhost",
+                },
+            }"#).unwrap();
+
+            let path = CString::new("server.port").unwrap();
+            let result = nickel_query_doc(code.as_ptr(), path.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "Port the server listens on");
+            nickel_free_string(result);
+
+            let path = CString::new("server.host").unwrap();
+            let result = nickel_query_doc(code.as_ptr(), path.as_ptr());
+            assert!(result.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("has no documentation"), "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        unsafe {
+            let code = CString::new(r#"{
+                server = {
+                    port | Number | std.number.PosNat | doc "Port the server listens on" = 8000 + 80,
+                    host : String = "localhost",
+                    name | String,
+                },
+            }"#).unwrap();
+
+            let query = |path: &str| {
+                let path = CString::new(path).unwrap();
+                let result = nickel_query(code.as_ptr(), path.as_ptr());
+                assert!(!result.is_null(), "Expected result, got error: {:?}",
+                    CStr::from_ptr(nickel_get_error()).to_str());
+                let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+                nickel_free_string(result);
+                json
+            };
+
+            assert_eq!(query("server.port"), serde_json::json!({
+                "value": 8080,
+                "type": null,
+                "contracts": ["Number", "std.number.PosNat"],
+                "doc": "Port the server listens on",
+            }));
+            assert_eq!(query("server.host"), serde_json::json!({
+                "value": "localhost",
+                "type": "String",
+                "contracts": [],
+                "doc": null,
+            }));
+            // Fields without a definition can still be queried
+            assert_eq!(query("server.name")["value"], serde_json::Value::Null);
+
+            let path = CString::new("server.missing").unwrap();
+            assert!(nickel_query(code.as_ptr(), path.as_ptr()).is_null());
+        }
+    }
+
+    #[test]
+    fn test_eval_with_json_input() {
         unsafe {
-            let result = nickel_eval_string(ptr::null());
+            let code = CString::new("{ next = input.foo + 1, name = input.meta.\"display name\" }").unwrap();
+            let input = CString::new(r#"{"foo": 41, "meta": {"display name": "a \"quoted\" %{x}\n"}}"#).unwrap();
+            let result = nickel_eval_with_json_input(code.as_ptr(), input.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json["next"], 42);
+            assert_eq!(json["name"], "a \"quoted\" %{x}\n");
+            nickel_free_string(result);
+
+            let input = CString::new(r#"[-1.5, null, true, []]"#).unwrap();
+            let code = CString::new("std.array.length input").unwrap();
+            let result = nickel_eval_with_json_input(code.as_ptr(), input.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "4");
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_with_malformed_json_input() {
+        unsafe {
+            let code = CString::new("input").unwrap();
+            let input = CString::new(r#"{"foo": }"#).unwrap();
+            let result = nickel_eval_with_json_input(code.as_ptr(), input.as_ptr());
+            assert!(result.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.starts_with("Invalid input JSON"), "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        unsafe {
+            let apply = |func: &str, args: &str| {
+                let func = CString::new(func).unwrap();
+                let args = CString::new(args).unwrap();
+                let mut json: *const c_char = ptr::null();
+                let status = nickel_apply(func.as_ptr(), args.as_ptr(), &mut json);
+                if status != 0 {
+                    assert!(json.is_null());
+                    return Err(CStr::from_ptr(nickel_get_error()).to_str().unwrap().to_string());
+                }
+                let value: serde_json::Value = serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+                nickel_free_string(json);
+                Ok(value)
+            };
+
+            assert_eq!(apply("fun x => x + 1", "[41]").unwrap(), 42);
+            assert_eq!(apply("fun x y => x - y", "[5, 2]").unwrap(), 3);
+            assert_eq!(
+                apply("fun cfg => cfg & { port | default = 80 }", r#"[{ "host": "localhost" }]"#).unwrap(),
+                serde_json::json!({ "host": "localhost", "port": 80 })
+            );
+
+            let error = apply("42", "[1]").unwrap_err();
+            assert!(error.contains("Expected a function, got a number"), "Unexpected error: {}", error);
+            let error = apply("fun x => x", "[1, 2]").unwrap_err();
+            assert!(error.contains("takes 1 but 2 were given"), "Unexpected error: {}", error);
+            let error = apply("fun x y => x", "[1]").unwrap_err();
+            assert!(error.contains("Too few arguments"), "Unexpected error: {}", error);
+            let error = apply("fun x => x", "{}").unwrap_err();
+            assert!(error.contains("JSON array"), "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_eval_field() {
+        unsafe {
+            let code = CString::new(r#"{
+                config = {
+                    database = { host = "db.local", port = 5432 },
+                    cache = { size = 1 / 0 },
+                },
+            }"#).unwrap();
+
+            // Nested path; the broken sibling `cache` is never evaluated
+            let path = CString::new("config.database").unwrap();
+            let result = nickel_eval_field(code.as_ptr(), path.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json["host"], "db.local");
+            assert_eq!(json["port"], 5432);
+            nickel_free_string(result);
+
+            let path = CString::new("config.database.port").unwrap();
+            let result = nickel_eval_field(code.as_ptr(), path.as_ptr());
+            assert!(!result.is_null());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "5432");
+            nickel_free_string(result);
+
+            // Missing field
+            let path = CString::new("config.missing").unwrap();
+            let result = nickel_eval_field(code.as_ptr(), path.as_ptr());
+            assert!(result.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("missing"), "Unexpected error: {}", error);
+
+            // Path going through a non-record
+            let path = CString::new("config.database.port.value").unwrap();
+            let result = nickel_eval_field(code.as_ptr(), path.as_ptr());
+            assert!(result.is_null());
+        }
+    }
+
+    #[test]
+    fn test_eval_json_validated() {
+        unsafe {
+            let contract = CString::new("{ port | Number, host | String }").unwrap();
+
+            let code = CString::new(r#"{ port = "8080", host = "localhost" }"#).unwrap();
+            let result = nickel_eval_json_validated(code.as_ptr(), contract.as_ptr());
             assert!(result.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("contract broken"), "Unexpected error: {}", error);
+
+            let code = CString::new(r#"{ port = 8080, host = "localhost" } # trailing comment"#).unwrap();
+            let result = nickel_eval_json_validated(code.as_ptr(), contract.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json["port"], 8080);
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_eval_json_tagged() {
+        unsafe {
+            let eval = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let result = nickel_eval_json_tagged(code.as_ptr());
+                assert!(!result.is_null());
+                let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+                nickel_free_string(result);
+                json
+            };
+
+            assert_eq!(eval("'Foo"), serde_json::json!({ "$enum": "Foo" }));
+            assert_eq!(eval("'Some 42"), serde_json::json!({ "$enum": "Some", "arg": 42 }));
+            assert_eq!(
+                eval(r#"{ name = "Foo", states = ['On, 'Pending { since = 'Monday }] }"#),
+                serde_json::json!({
+                    "name": "Foo",
+                    "states": [
+                        { "$enum": "On" },
+                        { "$enum": "Pending", "arg": { "since": { "$enum": "Monday" } } },
+                    ],
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_json_flatten_enums() {
+        unsafe {
+            let code = CString::new("{ name = \"svc\", status = 'Active { since = 2020 } }").unwrap();
+            let sep = CString::new("_").unwrap();
+            let result = nickel_eval_json_flatten_enums(code.as_ptr(), sep.as_ptr());
+            assert!(!result.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let result_str = CStr::from_ptr(result).to_str().unwrap();
+            let json: serde_json::Value = serde_json::from_str(result_str).unwrap();
+            assert_eq!(json["status"], "Active");
+            assert_eq!(json["status_since"], 2020);
+            assert_eq!(json["name"], "svc");
+            nickel_free_string(result);
+
+            // Non-record arguments land under `<field><sep>value`
+            let code = CString::new("{ count = 'Some 3 }").unwrap();
+            let sep = CString::new(".").unwrap();
+            let result = nickel_eval_json_flatten_enums(code.as_ptr(), sep.as_ptr());
+            assert!(!result.is_null());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json["count"], "Some");
+            assert_eq!(json["count.value"], 3);
+            nickel_free_string(result);
+        }
+    }
+
+    #[test]
+    fn test_encode_missing_field() {
+        // Full evaluation rejects fields without a definition, so encode the record
+        // in weak head normal form, where the declared field has no value
+        let mut program = new_program("{ x | Number, y = null }").unwrap();
+        let term = program.eval().unwrap();
+        let mut buffer = Vec::new();
+        encode_term(&term, &mut buffer).unwrap();
+        assert_eq!(
+            buffer,
+            [&[TYPE_RECORD, 2, 0, 0, 0][..], &[1, 0, 0, 0, b'x', TYPE_MISSING], &[1, 0, 0, 0, b'y', TYPE_NULL]].concat()
+        );
+    }
+
+    #[test]
+    fn test_native_explicit_null_field() {
+        unsafe {
+            let code = CString::new("{ x | Dyn = null }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let bytes = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(bytes, [TYPE_RECORD, 1, 0, 0, 0, 1, 0, 0, 0, b'x', TYPE_NULL]);
+            nickel_free_buffer(buffer);
+
+            let code = CString::new("{ x | Number }").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(buffer.data.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("missing definition for `x`"), "Unexpected error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_native_date_field() {
+        unsafe {
+            let code = CString::new(r#"
+                let Date = std.contract.from_predicate std.is_string in
+                { created | Date = "2024-01-15T10:30:00" }
+            "#).unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            // TYPE_RECORD | count | key_len | "created" | TYPE_DATE | len | bytes
+            assert_eq!(data[0], TYPE_RECORD);
+            assert_eq!(&data[9..16], b"created");
+            assert_eq!(data[16], TYPE_DATE);
+            let len = u32::from_le_bytes(data[17..21].try_into().unwrap()) as usize;
+            assert_eq!(&data[21..21 + len], b"2024-01-15T10:30:00");
+            nickel_free_buffer(buffer);
+
+            // Without the annotation the same value stays a plain string
+            let code = CString::new(r#"{ created = "2024-01-15T10:30:00" }"#).unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[16], TYPE_STRING);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_simple_enum() {
+        unsafe {
+            let code = CString::new("let x = 'Foo in x").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            // TYPE_ENUM | tag_len | "Foo" | has_arg=0
+            assert_eq!(data[0], TYPE_ENUM);
+            let tag_len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
+            assert_eq!(tag_len, 3); // "Foo"
+            assert_eq!(&data[5..8], b"Foo");
+            assert_eq!(data[8], 0); // no argument
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_enum_variant() {
+        unsafe {
+            let code = CString::new("let x = 'Some 42 in x").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            // TYPE_ENUM | tag_len | "Some" | has_arg=1 | TYPE_INT | 42
+            assert_eq!(data[0], TYPE_ENUM);
+            let tag_len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
+            assert_eq!(tag_len, 4); // "Some"
+            assert_eq!(&data[5..9], b"Some");
+            assert_eq!(data[9], 1); // has argument
+            assert_eq!(data[10], TYPE_INT);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_native_enum_with_record() {
+        unsafe {
+            let code = CString::new("let x = 'Ok { value = 123 } in x").unwrap();
+            let buffer = nickel_eval_native(code.as_ptr());
+            assert!(!buffer.data.is_null());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            // TYPE_ENUM | tag_len | "Ok" | has_arg=1 | TYPE_RECORD | ...
+            assert_eq!(data[0], TYPE_ENUM);
+            let tag_len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
+            assert_eq!(tag_len, 2); // "Ok"
+            assert_eq!(&data[5..7], b"Ok");
+            assert_eq!(data[7], 1); // has argument
+            assert_eq!(data[8], TYPE_RECORD);
+            nickel_free_buffer(buffer);
+        }
+    }
+
+    #[test]
+    fn test_array_iter() {
+        unsafe {
+            let code = CString::new("std.array.generate (fun i => { id = i }) 1000").unwrap();
+            let iter = nickel_eval_array_open(code.as_ptr());
+            assert!(!iter.is_null(), "Expected iterator, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+
+            let mut out = NativeBuffer { data: ptr::null_mut(), len: 0 };
+            let mut count = 0;
+            while nickel_array_next(iter, &mut out) == 0 {
+                let data = std::slice::from_raw_parts(out.data, out.len);
+                assert_eq!(data[0], TYPE_RECORD);
+                // key "id" followed by the element index
+                assert_eq!(&data[9..11], b"id");
+                assert_eq!(data[11], TYPE_INT);
+                let id = i64::from_le_bytes(data[12..20].try_into().unwrap());
+                assert_eq!(id, count);
+                nickel_free_buffer(NativeBuffer { data: out.data, len: out.len });
+                count += 1;
+            }
+            assert_eq!(count, 1000);
+            assert!(out.data.is_null());
+            // Exhausted iterators stay exhausted
+            assert_eq!(nickel_array_next(iter, &mut out), 1);
+            nickel_array_free(iter);
+        }
+    }
+
+    #[test]
+    fn test_array_iter_not_an_array() {
+        unsafe {
+            let code = CString::new("{ x = 1 }").unwrap();
+            let iter = nickel_eval_array_open(code.as_ptr());
+            assert!(iter.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("Expected an array"), "Unexpected error: {}", error);
+            nickel_array_free(iter);
+        }
+    }
+
+    #[test]
+    fn test_list_imports() {
+        use std::fs;
+
+        let temp_dir = std::env::temp_dir().join(format!("nickel_imports_{}", std::process::id()));
+        fs::create_dir_all(temp_dir.join("lib")).unwrap();
+        fs::write(temp_dir.join("main.ncl"), r#"{ a = import "a.ncl", b = import "lib/b.ncl" }"#).unwrap();
+        fs::write(temp_dir.join("a.ncl"), r#"{ x = (import "lib/b.ncl").y }"#).unwrap();
+        fs::write(temp_dir.join("lib/b.ncl"), r#"{ y = (import "data.json").z }"#).unwrap();
+        fs::write(temp_dir.join("lib/data.json"), r#"{ "z": 1 }"#).unwrap();
+
+        unsafe {
+            let path = CString::new(temp_dir.join("main.ncl").to_str().unwrap()).unwrap();
+            let mut out: *const c_char = ptr::null();
+            assert_eq!(nickel_list_imports(path.as_ptr(), &mut out), 0, "Unexpected error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let imports: Vec<String> = CStr::from_ptr(out).to_str().unwrap().lines().map(String::from).collect();
+            nickel_free_string(out);
+
+            assert_eq!(imports.len(), 3, "Unexpected imports: {:?}", imports);
+            for expected in ["a.ncl", "b.ncl", "data.json"] {
+                assert!(imports.iter().any(|p| p.ends_with(expected)), "Missing {} in {:?}", expected, imports);
+            }
+            assert!(!imports.iter().any(|p| p.ends_with("main.ncl")));
+
+            // A missing import is reported as an error
+            fs::write(temp_dir.join("broken.ncl"), r#"import "missing.ncl""#).unwrap();
+            let path = CString::new(temp_dir.join("broken.ncl").to_str().unwrap()).unwrap();
+            assert_eq!(nickel_list_imports(path.as_ptr(), &mut out), -1);
+            assert!(out.is_null());
+        }
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_eval_native() {
+        use std::fs;
+        use std::io::Write;
+
+        // Create a temp directory with test files
+        let temp_dir = std::env::temp_dir().join("nickel_test");
+        fs::create_dir_all(&temp_dir).unwrap();
+
+        // Create a simple file
+        let simple_file = temp_dir.join("simple.ncl");
+        let mut f = fs::File::create(&simple_file).unwrap();
+        writeln!(f, "{{ x = 42 }}").unwrap();
+
+        unsafe {
+            let path = CString::new(simple_file.to_str().unwrap()).unwrap();
+            let buffer = nickel_eval_file_native(path.as_ptr());
+            assert!(!buffer.data.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(data[0], TYPE_RECORD);
+            nickel_free_buffer(buffer);
+        }
+
+        // Clean up
+        fs::remove_file(simple_file).unwrap();
+    }
+
+    #[test]
+    fn test_file_eval_with_imports() {
+        use std::fs;
+        use std::io::Write;
+
+        // Create a temp directory with test files
+        let temp_dir = std::env::temp_dir().join("nickel_import_test");
+        fs::create_dir_all(&temp_dir).unwrap();
+
+        // Create shared.ncl
+        let shared_file = temp_dir.join("shared.ncl");
+        let mut f = fs::File::create(&shared_file).unwrap();
+        writeln!(f, "{{ name = \"test\", value = 42 }}").unwrap();
+
+        // Create main.ncl that imports shared.ncl
+        let main_file = temp_dir.join("main.ncl");
+        let mut f = fs::File::create(&main_file).unwrap();
+        writeln!(f, "let shared = import \"shared.ncl\" in").unwrap();
+        writeln!(f, "{{ imported_name = shared.name, extra = \"added\" }}").unwrap();
+
+        unsafe {
+            let path = CString::new(main_file.to_str().unwrap()).unwrap();
+            let buffer = nickel_eval_file_native(path.as_ptr());
+            assert!(!buffer.data.is_null(), "Expected result, got error: {:?}",
+                CStr::from_ptr(nickel_get_error()).to_str());
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
+            // Should be a record with two fields
+            assert_eq!(data[0], TYPE_RECORD);
+            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            assert_eq!(field_count, 2);
+            nickel_free_buffer(buffer);
+        }
+
+        // Clean up
+        fs::remove_file(main_file).unwrap();
+        fs::remove_file(shared_file).unwrap();
+        fs::remove_dir(temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_error_with_null_byte() {
+        use std::fs;
+
+        // The diagnostic quotes the offending source line, null byte included
+        let file = std::env::temp_dir().join("nickel_test_null_byte.ncl");
+        fs::write(&file, b"{ x = 1 + \0 }").unwrap();
+
+        unsafe {
+            let path = CString::new(file.to_str().unwrap()).unwrap();
+            let buffer = nickel_eval_file_native(path.as_ptr());
+            assert!(buffer.data.is_null());
             let error = nickel_get_error();
             assert!(!error.is_null());
+            let error = CStr::from_ptr(error).to_str().unwrap();
+            assert!(error.contains("\\0"), "Unexpected error: {}", error);
         }
+
+        fs::remove_file(file).unwrap();
     }
 
     #[test]
-    fn test_free_null() {
+    fn test_file_eval_not_found() {
         unsafe {
-            nickel_free_string(ptr::null());
+            let path = CString::new("/nonexistent/path/file.ncl").unwrap();
+            let buffer = nickel_eval_file_native(path.as_ptr());
+            assert!(buffer.data.is_null());
+            let error = nickel_get_error();
+            assert!(!error.is_null());
         }
     }
 
     #[test]
-    fn test_eval_simple_number() {
-        unsafe {
-            let code = CString::new("1 + 2").unwrap();
-            let result = nickel_eval_string(code.as_ptr());
-            assert!(!result.is_null(), "Expected result, got error: {:?}",
-                CStr::from_ptr(nickel_get_error()).to_str());
-            let result_str = CStr::from_ptr(result).to_str().unwrap();
-            assert_eq!(result_str, "3");
-            nickel_free_string(result);
+    fn test_header_declares_exports() {
+        let header = include_str!("../include/nickel_jl.h");
+        let source = include_str!("lib.rs");
+
+        let mut exported = 0;
+        for line in source.lines() {
+            let Some(rest) = line
+                .strip_prefix("pub unsafe extern \"C\" fn ")
+                .or_else(|| line.strip_prefix("pub extern \"C\" fn "))
+            else {
+                continue;
+            };
+            let name = &rest[..rest.find('(').unwrap()];
+            assert!(header.contains(&format!(" {}(", name)) || header.contains(&format!("*{}(", name)),
+                "{} is missing from the header", name);
+            exported += 1;
+        }
+        assert!(exported > 60);
+
+        assert!(header.contains("} NativeBuffer;"));
+        for (name, tag) in [
+            ("TYPE_NULL", TYPE_NULL),
+            ("TYPE_BOOL", TYPE_BOOL),
+            ("TYPE_INT", TYPE_INT),
+            ("TYPE_FLOAT", TYPE_FLOAT),
+            ("TYPE_STRING", TYPE_STRING),
+            ("TYPE_ARRAY", TYPE_ARRAY),
+            ("TYPE_RECORD", TYPE_RECORD),
+            ("TYPE_ENUM", TYPE_ENUM),
+            ("TYPE_DATE", TYPE_DATE),
+            ("TYPE_MISSING", TYPE_MISSING),
+            ("TYPE_FUNCTION", TYPE_FUNCTION),
+            ("TYPE_PACKED_ARRAY", TYPE_PACKED_ARRAY),
+            ("TYPE_KEYED_RECORD", TYPE_KEYED_RECORD),
+            ("TYPE_QUANTITY", TYPE_QUANTITY),
+            ("TYPE_UNSUPPORTED", TYPE_UNSUPPORTED),
+            ("TYPE_FLOAT32", TYPE_FLOAT32),
+        ] {
+            assert!(header.contains(&format!("#define {} {}\n", name, tag)), "{} is missing", name);
         }
     }
 
     #[test]
-    fn test_eval_string() {
+    fn test_type_tag() {
         unsafe {
-            let code = CString::new(r#""hello""#).unwrap();
-            let result = nickel_eval_string(code.as_ptr());
-            assert!(!result.is_null(), "Expected result, got error: {:?}",
-                CStr::from_ptr(nickel_get_error()).to_str());
-            let result_str = CStr::from_ptr(result).to_str().unwrap();
-            assert_eq!(result_str, "\"hello\"");
-            nickel_free_string(result);
+            assert_eq!(nickel_type_tag(c"int".as_ptr()), i32::from(TYPE_INT));
+            assert_eq!(nickel_type_tag(c"record".as_ptr()), i32::from(TYPE_RECORD));
+            assert_eq!(nickel_type_tag(c"packed_array".as_ptr()), i32::from(TYPE_PACKED_ARRAY));
+            assert_eq!(nickel_type_tag(c"Int".as_ptr()), -1);
+            assert_eq!(nickel_type_tag(c"complex".as_ptr()), -1);
+            assert_eq!(nickel_type_tag(ptr::null()), -1);
         }
     }
 
     #[test]
-    fn test_eval_record() {
+    fn test_eval_string_enums() {
         unsafe {
-            let code = CString::new("{ x = 1, y = 2 }").unwrap();
-            let result = nickel_eval_string(code.as_ptr());
-            assert!(!result.is_null(), "Expected result, got error: {:?}",
-                CStr::from_ptr(nickel_get_error()).to_str());
-            let result_str = CStr::from_ptr(result).to_str().unwrap();
-            assert!(result_str.contains("\"x\""));
-            assert!(result_str.contains("\"y\""));
+            let result = nickel_eval_string(c"{ status = 'Active, modes = ['Fast, 'Slow] }".as_ptr());
+            assert!(!result.is_null());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            nickel_free_string(result);
+            assert_eq!(json, serde_json::json!({ "status": "Active", "modes": ["Fast", "Slow"] }));
+
+            let result = nickel_eval_string(c"{ results = ['Ok 1, 'Error \"oops\"] }".as_ptr());
+            assert!(result.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("'Ok at `results[0]`"), "{}", error);
+            assert!(error.contains("nickel_eval_json_tagged"), "{}", error);
+
+            let result = nickel_eval_json_tagged(c"'Error \"oops\"".as_ptr());
+            assert!(!result.is_null());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
             nickel_free_string(result);
+            assert_eq!(json, serde_json::json!({ "$enum": "Error", "arg": "oops" }));
         }
     }
 
     #[test]
-    fn test_eval_array() {
+    fn test_eval_ok() {
         unsafe {
-            let code = CString::new("[1, 2, 3]").unwrap();
-            let result = nickel_eval_string(code.as_ptr());
-            assert!(!result.is_null(), "Expected result, got error: {:?}",
-                CStr::from_ptr(nickel_get_error()).to_str());
-            let result_str = CStr::from_ptr(result).to_str().unwrap();
-            assert!(result_str.contains("1"));
-            assert!(result_str.contains("2"));
-            assert!(result_str.contains("3"));
-            nickel_free_string(result);
+            assert_eq!(nickel_eval_ok(c"{ port | Number = 8080, hosts = std.array.map (fun x => x ++ \".local\") [\"a\", \"b\"] }".as_ptr()), 0);
+
+            assert_eq!(nickel_eval_ok(c"{ port | Number = \"8080\" }".as_ptr()), -1);
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("contract"), "{}", error);
+
+            assert_eq!(nickel_eval_ok(ptr::null()), -1);
         }
     }
 
+
     #[test]
-    fn test_eval_function_application() {
-        unsafe {
-            let code = CString::new("let add = fun x y => x + y in add 3 4").unwrap();
-            let result = nickel_eval_string(code.as_ptr());
-            assert!(!result.is_null(), "Expected result, got error: {:?}",
-                CStr::from_ptr(nickel_get_error()).to_str());
-            let result_str = CStr::from_ptr(result).to_str().unwrap();
-            assert_eq!(result_str, "7");
-            nickel_free_string(result);
-        }
+    fn test_eval_string_integer_valued_floats() {
+        let json: serde_json::Value =
+            serde_json::from_str(&eval_nickel_json("{ a = 2, b = 2.0, c = 2.5, d = 4 / 2, e = -3.0 }").unwrap()).unwrap();
+        assert!(json["a"].is_i64() && json["a"] == 2);
+        assert!(json["b"].is_i64() && json["b"] == 2);
+        assert!(json["c"].is_f64() && json["c"] == 2.5);
+        assert!(json["d"].is_i64() && json["d"] == 2);
+        assert!(json["e"].is_i64() && json["e"] == -3);
     }
 
     #[test]
-    fn test_eval_syntax_error() {
+    fn test_numeric_keys() {
         unsafe {
-            let code = CString::new("{ x = }").unwrap();
-            let result = nickel_eval_string(code.as_ptr());
-            assert!(result.is_null());
-            let error = nickel_get_error();
-            assert!(!error.is_null());
-            let error_str = CStr::from_ptr(error).to_str().unwrap();
-            assert!(!error_str.is_empty());
+            let encode = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                bytes
+            };
+            let code = r#"{ "10" = true, name = "x" }"#;
+
+            let default = encode(code);
+            assert_eq!(default[0], TYPE_RECORD);
+
+            nickel_set_numeric_keys(true);
+            let keyed = encode(code);
+            let plain = encode(r#"{ "01" = true, name = "x" }"#);
+            nickel_set_numeric_keys(false);
+
+            // Fields are sorted by key in the result
+            let mut expected = vec![TYPE_KEYED_RECORD, 2, 0, 0, 0];
+            expected.push(TYPE_INT);
+            expected.extend_from_slice(&10i64.to_le_bytes());
+            expected.extend_from_slice(&[TYPE_BOOL, 1]);
+            expected.push(TYPE_STRING);
+            expected.extend_from_slice(&[4, 0, 0, 0]);
+            expected.extend_from_slice(b"name");
+            expected.extend_from_slice(&[TYPE_STRING, 1, 0, 0, 0, b'x']);
+            assert_eq!(keyed, expected);
+
+            assert_eq!(plain[0], TYPE_RECORD);
+            assert_eq!(encode(code), default);
+            assert_eq!(numeric_key("0"), Some(0));
+            assert_eq!(numeric_key("99999999999999999999"), None);
+            assert_eq!(numeric_key("-1"), None);
         }
     }
 
     #[test]
-    fn test_native_int() {
+    fn test_eval_arrow() {
+        use arrow::array::{Array as _, AsArray};
+        use arrow::datatypes::{DataType, Float64Type, Int64Type};
+        use arrow::ipc::reader::StreamReader;
+
         unsafe {
-            let code = CString::new("42").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
+            let code = CString::new(
+                r#"[
+                    { name = "a", count = 1, ratio = 0.5, active = true, note = null },
+                    { name = "b", count = 2, ratio = 1, active = false, note = "x" },
+                    { name = "c", count = 3, ratio = 1.5, active = true, note = null },
+                ]"#,
+            )
+            .unwrap();
+            let buffer = nickel_eval_arrow(code.as_ptr());
             assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_INT);
-            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
-            assert_eq!(value, 42);
+            let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
             nickel_free_buffer(buffer);
+
+            let batches: Vec<_> = StreamReader::try_new(Cursor::new(bytes), None)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(batches.len(), 1);
+            let batch = &batches[0];
+            assert_eq!(batch.num_rows(), 3);
+
+            let schema = batch.schema();
+            let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+            assert_eq!(names, ["active", "count", "name", "note", "ratio"]);
+            assert_eq!(schema.field(1).data_type(), &DataType::Int64);
+            assert_eq!(schema.field(4).data_type(), &DataType::Float64);
+            assert!(schema.field(3).is_nullable() && !schema.field(2).is_nullable());
+            assert_eq!(batch.column(1).as_primitive::<Int64Type>().values(), &[1, 2, 3]);
+            assert_eq!(batch.column(4).as_primitive::<Float64Type>().values(), &[0.5, 1.0, 1.5]);
+            assert_eq!(batch.column(3).null_count(), 2);
+
+            for code in ["{ a = 1 }", "[]", "[{ a = 1 }, { b = 1 }]", "[{ a = 1 }, { a = \"x\" }]", "[{ a = [1] }]"] {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_arrow(code.as_ptr());
+                assert!(buffer.data.is_null(), "{:?}", code);
+            }
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("Column `a` holds an array"), "{}", error);
         }
     }
 
     #[test]
-    fn test_native_float() {
+    fn test_divergent_code() {
         unsafe {
-            let code = CString::new("3.14").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            if buffer.data.is_null() {
-                let err = nickel_get_error();
-                if !err.is_null() {
-                    panic!("Error: {:?}", CStr::from_ptr(err).to_str());
-                }
+            // Self-reference is detected by the evaluator, without a budget
+            for code in ["let rec r = { a = r.a } in r", "{ a = b, b = a }"] {
+                let code = CString::new(code).unwrap();
+                assert!(nickel_eval_string(code.as_ptr()).is_null());
+                let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+                assert!(error.contains("infinite recursion"), "{}", error);
             }
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_FLOAT);
-            let value = f64::from_le_bytes(data[1..9].try_into().unwrap());
-            assert!((value - 3.14).abs() < 0.001);
-            nickel_free_buffer(buffer);
+
+            // A function calling itself forever is stopped by the fuel cap
+            nickel_set_eval_fuel(100);
+            let code = CString::new("let rec f = fun x => f x in { a = f 1 }").unwrap();
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("Evaluation fuel exhausted"), "{}", error);
+            nickel_set_eval_fuel(u64::MAX);
         }
     }
 
     #[test]
-    fn test_native_string() {
+    fn test_base_dir() {
+        let dir = std::env::temp_dir().join(format!("nickel_jl_base_dir_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.ncl"), "{ port = 8080 }").unwrap();
+
         unsafe {
-            let code = CString::new(r#""hello""#).unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_STRING);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-            let s = std::str::from_utf8(&data[5..5+len]).unwrap();
-            assert_eq!(s, "hello");
-            nickel_free_buffer(buffer);
+            let code = c"{ server = (import \"./lib.ncl\").port }";
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
+
+            let path = CString::new(dir.to_str().unwrap()).unwrap();
+            assert_eq!(nickel_set_base_dir(path.as_ptr()), 0);
+            let result = nickel_eval_string(code.as_ptr());
+            assert!(!result.is_null());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "{\n  \"server\": 8080\n}");
+            nickel_free_string(result);
+
+            let missing = CString::new(dir.join("missing").to_str().unwrap()).unwrap();
+            assert_eq!(nickel_set_base_dir(missing.as_ptr()), -1);
+            assert_eq!(nickel_set_base_dir(ptr::null()), 0);
+            assert!(nickel_eval_string(code.as_ptr()).is_null());
         }
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_native_bool() {
+    fn test_native_quantity() {
         unsafe {
-            let code = CString::new("true").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_BOOL);
-            assert_eq!(data[1], 1);
-            nickel_free_buffer(buffer);
+            let encode = |field: &str| {
+                let code = CString::new(format!(
+                    "let Duration = std.contract.from_predicate std.is_string in
+                    let ByteSize = std.contract.from_predicate std.is_string in
+                    {{ {} }}",
+                    field
+                ))
+                .unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let data = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                // Skip the record header and the field name
+                let name_len = u32::from_le_bytes(data[5..9].try_into().unwrap()) as usize;
+                let value = data[9 + name_len..].to_vec();
+                if value[0] == TYPE_STRING {
+                    return Err(String::from_utf8(value[5..].to_vec()).unwrap());
+                }
+                assert_eq!(value[0], TYPE_QUANTITY);
+                let magnitude = f64::from_le_bytes(value[1..9].try_into().unwrap());
+                assert_eq!(&value[9..13], &[1, 0, 0, 0]);
+                Ok((magnitude, value[13] as char))
+            };
+
+            assert_eq!(encode(r#"timeout | Duration = "1.5 min""#), Ok((90.0, 's')));
+            assert_eq!(encode(r#"timeout | Duration = "30s""#), Ok((30.0, 's')));
+            assert_eq!(encode(r#"memory | ByteSize = "4GiB""#), Ok((4294967296.0, 'B')));
+            assert_eq!(encode(r#"memory | ByteSize = "512 kB""#), Ok((512000.0, 'B')));
+
+            // Strings outside the grammar are kept as they are
+            for text in ["soon", "PT30S", "1h30m", "-5s", "1e3s"] {
+                assert_eq!(encode(&format!(r#"timeout | Duration = "{}""#, text)), Err(text.to_string()));
+            }
+            assert_eq!(encode(r#"memory | ByteSize = "lots""#), Err("lots".to_string()));
         }
     }
 
     #[test]
-    fn test_native_array() {
+    fn test_encode_mode_placeholders() {
         unsafe {
-            let code = CString::new("[1, 2, 3]").unwrap();
+            let code = CString::new("{ schema = Number, values = [1, String] }").unwrap();
             let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_ARRAY);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(len, 3);
-            nickel_free_buffer(buffer);
-        }
-    }
+            assert!(buffer.data.is_null());
 
-    #[test]
-    fn test_native_record() {
-        unsafe {
-            let code = CString::new("{ x = 1 }").unwrap();
+            assert_eq!(nickel_set_encode_mode(1), 0);
             let buffer = nickel_eval_native(code.as_ptr());
+            assert_eq!(nickel_set_encode_mode(0), 0);
             assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_RECORD);
-            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(field_count, 1);
+            let data = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
             nickel_free_buffer(buffer);
+
+            let placeholder = [&[TYPE_UNSUPPORTED, 6, 0, 0, 0][..], b"a type"].concat();
+            let mut values = vec![TYPE_ARRAY, 2, 0, 0, 0, TYPE_INT];
+            values.extend_from_slice(&1i64.to_le_bytes());
+            values.extend_from_slice(&placeholder);
+            let windows = |needle: &[u8]| data.windows(needle.len()).filter(|w| *w == needle).count();
+            assert_eq!(data[0], TYPE_RECORD);
+            assert_eq!(windows(&placeholder), 2);
+            assert_eq!(windows(&values), 1);
+
+            assert_eq!(nickel_set_encode_mode(2), -1);
         }
     }
 
     #[test]
-    fn test_eval_json_internal() {
-        let result = eval_nickel_json("42").unwrap();
-        assert_eq!(result, "42");
+    fn test_eval_json_hashed() {
+        unsafe {
+            let eval = |code: &CStr| {
+                let mut json = ptr::null();
+                let mut hash = 0u64;
+                assert_eq!(nickel_eval_json_hashed(code.as_ptr(), &mut json, &mut hash), 0);
+                let text = CStr::from_ptr(json).to_str().unwrap().to_string();
+                nickel_free_string(json);
+                (text, hash)
+            };
 
-        let result = eval_nickel_json("{ a = 1 }").unwrap();
-        assert!(result.contains("\"a\""));
-        assert!(result.contains("1"));
-    }
+            let code = c"{ name = \"svc\", ports = [80, 443], limits = { cpu = 2, memory = \"1G\" } }";
+            let (json, hash) = eval(code);
+            assert_eq!(eval(code), (json.clone(), hash));
+            assert_eq!(hash, fnv1a64(json.as_bytes()));
+            // Field order in the source doesn't matter
+            assert_eq!(eval(c"{ limits = { memory = \"1G\", cpu = 2 }, ports = [80, 443], name = \"svc\" }").1, hash);
+            assert_ne!(eval(c"{ name = \"svc\", ports = [80, 443], limits = { cpu = 4, memory = \"1G\" } }").1, hash);
 
-    // Comprehensive tests for all Nickel types
+            assert_eq!(fnv1a64(b""), 0xcbf2_9ce4_8422_2325);
+            assert_eq!(fnv1a64(b"a"), 0xaf63_dc4c_8601_ec8c);
 
-    #[test]
-    fn test_native_null() {
-        unsafe {
-            let code = CString::new("null").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_NULL);
-            assert_eq!(buffer.len, 1);
-            nickel_free_buffer(buffer);
+            let mut json = ptr::null();
+            let mut hash = 0u64;
+            assert_eq!(nickel_eval_json_hashed(c"{ x = }".as_ptr(), &mut json, &mut hash), -1);
+            assert!(json.is_null());
         }
     }
 
     #[test]
-    fn test_native_bool_false() {
+    fn test_eval_diff() {
         unsafe {
-            let code = CString::new("false").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_BOOL);
-            assert_eq!(data[1], 0);
-            nickel_free_buffer(buffer);
-        }
-    }
+            let diff = |code: &CStr, previous: &CStr| {
+                let result = nickel_eval_diff(code.as_ptr(), previous.as_ptr());
+                assert!(!result.is_null());
+                let patch: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+                nickel_free_string(result);
+                patch
+            };
 
-    #[test]
-    fn test_native_negative_int() {
-        unsafe {
-            let code = CString::new("-42").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_INT);
-            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
-            assert_eq!(value, -42);
-            nickel_free_buffer(buffer);
+            let previous = c"{ \"name\": \"svc\", \"server\": { \"port\": 80 }, \"tags\": [\"a\"] }";
+            assert_eq!(
+                diff(c"{ name = \"svc\", server.port = 8080, tags = [\"a\"] }", previous),
+                serde_json::json!([{ "op": "replace", "path": "/server/port", "value": 8080 }])
+            );
+            assert_eq!(diff(c"{ name = \"svc\", server.port = 80, tags = [\"a\"] }", previous), serde_json::json!([]));
+            assert_eq!(
+                diff(c"{ server = { port = 80 }, tags = [\"a\", \"b\"], \"a/b\" = 1 }", previous),
+                serde_json::json!([
+                    { "op": "remove", "path": "/name" },
+                    { "op": "add", "path": "/tags/1", "value": "b" },
+                    { "op": "add", "path": "/a~1b", "value": 1 },
+                ])
+            );
+
+            assert!(nickel_eval_diff(c"{ a = 1 }".as_ptr(), c"{ oops".as_ptr()).is_null());
         }
     }
 
     #[test]
-    fn test_native_large_int() {
+    fn test_max_buffer_bytes() {
         unsafe {
-            let code = CString::new("1000000000000").unwrap();
+            let code = CString::new("std.array.generate (fun i => { id = i, name = \"item\" }) 10000").unwrap();
+            nickel_set_max_buffer_bytes(1024);
             let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_INT);
-            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
-            assert_eq!(value, 1000000000000i64);
-            nickel_free_buffer(buffer);
-        }
-    }
+            assert!(buffer.data.is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("exceeds the maximum of 1024 bytes"), "{}", error);
 
-    #[test]
-    fn test_native_negative_float() {
-        unsafe {
-            let code = CString::new("-2.718").unwrap();
+            let small = nickel_eval_native(c"[1, 2, 3]".as_ptr());
+            assert!(!small.data.is_null());
+            nickel_free_buffer(small);
+
+            // A single string is rejected before it is copied
+            let buffer = nickel_eval_native(c"std.string.join \"\" (std.array.replicate 2000 \"x\")".as_ptr());
+            assert!(buffer.data.is_null());
+
+            nickel_set_max_buffer_bytes(usize::MAX);
             let buffer = nickel_eval_native(code.as_ptr());
             assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_FLOAT);
-            let value = f64::from_le_bytes(data[1..9].try_into().unwrap());
-            assert!((value - (-2.718)).abs() < 0.001);
+            assert!(buffer.len > 1024);
             nickel_free_buffer(buffer);
         }
     }
 
     #[test]
-    fn test_native_empty_string() {
-        unsafe {
-            let code = CString::new(r#""""#).unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_STRING);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-            assert_eq!(len, 0);
-            nickel_free_buffer(buffer);
-        }
+    fn test_eval_json_tagged_nested_arguments() {
+        let eval = |code: &str| serde_json::from_str::<serde_json::Value>(&eval_nickel_json_tagged(code).unwrap()).unwrap();
+
+        assert_eq!(
+            eval("'Ok { data = [1, 2] }"),
+            serde_json::json!({ "$enum": "Ok", "arg": { "data": [1, 2] } })
+        );
+        assert_eq!(
+            eval("'Batch [[1, 2], ['Some { x = 1.5 }, 'None]]"),
+            serde_json::json!({
+                "$enum": "Batch",
+                "arg": [[1, 2], [{ "$enum": "Some", "arg": { "x": 1.5 } }, { "$enum": "None" }]],
+            })
+        );
     }
 
     #[test]
-    fn test_native_unicode_string() {
+    fn test_source_chunks() {
         unsafe {
-            let code = CString::new(r#""hello 世界 🌍""#).unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_STRING);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-            let s = std::str::from_utf8(&data[5..5+len]).unwrap();
-            assert_eq!(s, "hello 世界 🌍");
-            nickel_free_buffer(buffer);
+            let src = nickel_source_new();
+            // "é" is 0xC3 0xA9; split it across the two chunks.
+            let first = b"{ name = \"h\xC3";
+            let second = b"\xA9\" }";
+            assert_eq!(nickel_source_push(src, first.as_ptr(), first.len()), 0);
+
+            let result = nickel_source_eval_json(src);
+            assert!(result.is_null());
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(err.contains("Invalid UTF-8"), "unexpected error: {}", err);
+
+            assert_eq!(nickel_source_push(src, second.as_ptr(), second.len()), 0);
+            assert_eq!(nickel_source_push(src, ptr::null(), 0), 0);
+            let result = nickel_source_eval_json(src);
+            assert!(!result.is_null());
+            let json: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json, serde_json::json!({ "name": "hé" }));
+            nickel_free_string(result);
+
+            assert_eq!(nickel_source_push(src, ptr::null(), 1), -1);
+            nickel_source_free(src);
+            nickel_source_free(ptr::null_mut());
         }
     }
 
     #[test]
-    fn test_native_empty_array() {
+    fn test_eval_prefix() {
         unsafe {
-            let code = CString::new("[]").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_ARRAY);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(len, 0);
-            nickel_free_buffer(buffer);
+            let code = CString::new(
+                "{ service = { port = 80, ports = [1, 2], name = \"web\" }, db = { port = 5432 } }",
+            )
+            .unwrap();
+            let eval = |prefix: &str| {
+                let prefix = CString::new(prefix).unwrap();
+                let result = nickel_eval_prefix(code.as_ptr(), prefix.as_ptr());
+                assert!(!result.is_null());
+                let json: serde_json::Value =
+                    serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+                nickel_free_string(result);
+                json
+            };
+
+            assert_eq!(
+                eval("service."),
+                serde_json::json!({ "service": { "port": 80, "ports": [1, 2], "name": "web" } })
+            );
+            assert_eq!(
+                eval("service.port"),
+                serde_json::json!({ "service": { "port": 80, "ports": [1, 2] } })
+            );
+            assert_eq!(eval("db"), serde_json::json!({ "db": { "port": 5432 } }));
+            assert_eq!(eval("cache"), serde_json::json!({}));
+            assert_eq!(eval("db.host"), serde_json::json!({}));
+            assert_eq!(eval("").as_object().unwrap().len(), 2);
+
+            let code = CString::new("[1]").unwrap();
+            let prefix = CString::new("").unwrap();
+            assert!(nickel_eval_prefix(code.as_ptr(), prefix.as_ptr()).is_null());
         }
     }
 
+
     #[test]
-    fn test_native_mixed_array() {
+    fn test_eval_dotenv() {
         unsafe {
-            // Array with int, string, bool
-            let code = CString::new(r#"[1, "two", true]"#).unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_ARRAY);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(len, 3);
-            // First element: int 1
-            assert_eq!(data[5], TYPE_INT);
-            // (rest of elements follow)
-            nickel_free_buffer(buffer);
+            let code = CString::new(
+                r#"{ PORT = 8080, DEBUG = true, RATIO = 0.5, EMPTY = null, MODE = 'Fast, GREETING = "it's here", URL = "http://x.org/a" }"#,
+            )
+            .unwrap();
+            let result = nickel_eval_dotenv(code.as_ptr());
+            assert!(!result.is_null());
+            assert_eq!(
+                CStr::from_ptr(result).to_str().unwrap(),
+                "DEBUG=true\nEMPTY=\nGREETING='it'\\''s here'\nMODE=Fast\nPORT=8080\nRATIO=0.5\nURL=http://x.org/a\n"
+            );
+            nickel_free_string(result);
+
+            let code = CString::new("{ DB = { HOST = \"localhost\" } }").unwrap();
+            assert!(nickel_eval_dotenv(code.as_ptr()).is_null());
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(err.contains("`DB`") && err.contains("a record"), "unexpected error: {}", err);
+
+            let code = CString::new("{ HOSTS = [\"a\"] }").unwrap();
+            assert!(nickel_eval_dotenv(code.as_ptr()).is_null());
+
+            let code = CString::new("{ \"my-key\" = 1 }").unwrap();
+            assert!(nickel_eval_dotenv(code.as_ptr()).is_null());
         }
     }
 
     #[test]
-    fn test_native_nested_array() {
+    fn test_eval_string_large_integers() {
         unsafe {
-            let code = CString::new("[[1, 2], [3, 4]]").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_ARRAY);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(len, 2);
-            // First element should be an array
-            assert_eq!(data[5], TYPE_ARRAY);
-            nickel_free_buffer(buffer);
+            for (code, expected) in [
+                ("10000000000000000", "10000000000000000"),
+                ("1e21", "1000000000000000000000"),
+                ("-1e21", "-1000000000000000000000"),
+                ("[1e21]", "[\n  1000000000000000000000\n]"),
+            ] {
+                let code = CString::new(code).unwrap();
+                let result = nickel_eval_string(code.as_ptr());
+                assert!(!result.is_null());
+                assert_eq!(CStr::from_ptr(result).to_str().unwrap(), expected);
+                nickel_free_string(result);
+                let warnings = nickel_get_warnings();
+                assert!(!warnings.is_null());
+                assert!(CStr::from_ptr(warnings).to_str().unwrap().contains("exceeds 2^53"));
+            }
+
+            let code = CString::new("{ big = 1e21 }").unwrap();
+            let result = nickel_eval_json_compact(code.as_ptr());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), r#"{"big":1000000000000000000000}"#);
+            nickel_free_string(result);
+            let warnings = CStr::from_ptr(nickel_get_warnings()).to_str().unwrap();
+            assert!(warnings.contains("at `big`"), "unexpected warnings: {}", warnings);
+
+            // Every JSON entry point formats integers the same way
+            let code = CString::new("{ big = 1e21 }").unwrap();
+            let field = CString::new("big").unwrap();
+            for result in [
+                nickel_eval_json_tagged(code.as_ptr()),
+                nickel_eval_strict(code.as_ptr()),
+                nickel_eval_field(code.as_ptr(), field.as_ptr()),
+            ] {
+                assert!(!result.is_null());
+                assert!(CStr::from_ptr(result).to_str().unwrap().contains("1000000000000000000000"));
+                nickel_free_string(result);
+            }
+            let func = CString::new("fun x => x * 1e20").unwrap();
+            let args = CString::new("[10]").unwrap();
+            let mut json: *const c_char = ptr::null();
+            assert_eq!(nickel_apply(func.as_ptr(), args.as_ptr(), &mut json), 0);
+            assert_eq!(CStr::from_ptr(json).to_str().unwrap(), "1000000000000000000000");
+            nickel_free_string(json);
+
+            // Including those built around several values
+            let warned = |json: *const c_char| {
+                assert!(!json.is_null());
+                let text = CStr::from_ptr(json).to_str().unwrap().to_string();
+                nickel_free_string(json);
+                let warnings = nickel_get_warnings();
+                assert!(!warnings.is_null(), "No warning for {}", text);
+                assert!(CStr::from_ptr(warnings).to_str().unwrap().contains("exceeds 2^53"));
+                assert!(text.contains("1000000000000000000000"), "Unexpected JSON: {}", text);
+            };
+            let docs = CString::new("{ a = 1 }\n---\n{ big = 1e21 }").unwrap();
+            assert_eq!(nickel_eval_multi(docs.as_ptr(), &mut json), 0);
+            warned(json);
+            warned(nickel_eval_json_with_spans(code.as_ptr()));
+            let previous = CString::new(r#"{ "big": 1 }"#).unwrap();
+            warned(nickel_eval_diff(code.as_ptr(), previous.as_ptr()));
+            warned(nickel_eval_prefix(code.as_ptr(), field.as_ptr()));
+            warned(nickel_query(code.as_ptr(), field.as_ptr()));
+
+            let code = CString::new("9007199254740992").unwrap();
+            let result = nickel_eval_string(code.as_ptr());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "9007199254740992");
+            nickel_free_string(result);
+            assert!(nickel_get_warnings().is_null());
         }
     }
 
     #[test]
-    fn test_native_empty_record() {
+    fn test_eval_validate_schema() {
         unsafe {
+            let schema = CString::new(
+                r##"{
+                    "type": "object",
+                    "required": ["host", "port"],
+                    "properties": {
+                        "host": { "type": "string", "minLength": 1 },
+                        "port": { "$ref": "#/$defs/port" },
+                        "tags": { "type": "array", "items": { "type": "string" } }
+                    },
+                    "additionalProperties": false,
+                    "$defs": { "port": { "type": "integer", "minimum": 1, "maximum": 65535 } }
+                }"##,
+            )
+            .unwrap();
+
+            let code = CString::new(r#"{ host = "localhost", port = 8080, tags = ["a"] }"#).unwrap();
+            assert_eq!(nickel_eval_validate_schema(code.as_ptr(), schema.as_ptr()), 0);
+
+            let code = CString::new(r#"{ port = 70000.5, tags = ["a", 1], extra = true }"#).unwrap();
+            assert_eq!(nickel_eval_validate_schema(code.as_ptr(), schema.as_ptr()), 1);
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(err.starts_with("JSON Schema validation failed:\n"), "{}", err);
+            let lines: Vec<&str> = err.lines().skip(1).collect();
+            assert_eq!(lines.len(), 5, "{}", err);
+            for expected in [
+                "`<root>`: \"host\" is a required property",
+                "`<root>`: Additional properties are not allowed ('extra' was unexpected)",
+                "`port`: 70000.5 is not of type \"integer\"",
+                "`port`: 70000.5 is greater than the maximum of 65535",
+                "`tags[1]`: 1 is not of type \"string\"",
+            ] {
+                assert!(lines.contains(&expected), "missing {:?} in {}", expected, err);
+            }
+
+            // Keywords beyond the basics are honored
+            let schema = CString::new(
+                r#"{
+                    "type": "object",
+                    "properties": {
+                        "step": { "multipleOf": 0.1 },
+                        "pair": { "prefixItems": [{ "type": "string" }, { "type": "integer" }] }
+                    },
+                    "if": { "required": ["tls"] },
+                    "then": { "required": ["cert"] }
+                }"#,
+            )
+            .unwrap();
+            let code = CString::new(r#"{ step = 0.3, pair = ["a", 1] }"#).unwrap();
+            assert_eq!(nickel_eval_validate_schema(code.as_ptr(), schema.as_ptr()), 0);
+            let code = CString::new(r#"{ step = 0.3, pair = [1, 1], tls = true }"#).unwrap();
+            assert_eq!(nickel_eval_validate_schema(code.as_ptr(), schema.as_ptr()), 1);
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(err.contains("`pair[0]`: 1 is not of type \"string\""), "{}", err);
+            assert!(err.contains("\"cert\" is a required property"), "{}", err);
+
+            let code = CString::new("{ f = fun x => x }").unwrap();
+            assert_eq!(nickel_eval_validate_schema(code.as_ptr(), schema.as_ptr()), -1);
             let code = CString::new("{}").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_RECORD);
-            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(field_count, 0);
-            nickel_free_buffer(buffer);
+            let schema = CString::new(r#"{ "pattern": 1 }"#).unwrap();
+            assert_eq!(nickel_eval_validate_schema(code.as_ptr(), schema.as_ptr()), -1);
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(err.starts_with("Invalid JSON schema"), "{}", err);
+            let schema = CString::new("{ not json").unwrap();
+            assert_eq!(nickel_eval_validate_schema(code.as_ptr(), schema.as_ptr()), -1);
         }
     }
 
     #[test]
-    fn test_native_nested_record() {
+    fn test_leaf_paths() {
         unsafe {
-            let code = CString::new("{ outer = { inner = 42 } }").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_RECORD);
-            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(field_count, 1);
-            nickel_free_buffer(buffer);
+            let code = CString::new(
+                r#"{
+                    server = { port = 8080, hosts = ["a", "b"], tls = { enabled = false } },
+                    name = "app",
+                    empty = {},
+                    hidden | not_exported = 1,
+                }"#,
+            )
+            .unwrap();
+            let result = nickel_leaf_paths(code.as_ptr());
+            assert!(!result.is_null());
+            assert_eq!(
+                CStr::from_ptr(result).to_str().unwrap(),
+                "name\nserver.hosts.0\nserver.hosts.1\nserver.port\nserver.tls.enabled"
+            );
+            nickel_free_string(result);
+
+            let code = CString::new("[1, 2]").unwrap();
+            assert!(nickel_leaf_paths(code.as_ptr()).is_null());
         }
     }
 
     #[test]
-    fn test_native_record_with_mixed_types() {
+    fn test_eval_raw_string() {
         unsafe {
-            let code = CString::new(r#"{ name = "test", count = 42, active = true, data = null }"#).unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_RECORD);
-            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(field_count, 4);
-            nickel_free_buffer(buffer);
+            let code = CString::new(r#""hello" ++ " \"world\"\n""#).unwrap();
+            let result = nickel_eval_raw_string(code.as_ptr());
+            assert!(!result.is_null());
+            assert_eq!(CStr::from_ptr(result).to_str().unwrap(), "hello \"world\"\n");
+            nickel_free_string(result);
+
+            let code = CString::new("{ a = 1 }").unwrap();
+            assert!(nickel_eval_raw_string(code.as_ptr()).is_null());
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert_eq!(err, "Expected a string, got a record");
         }
     }
 
     #[test]
-    fn test_native_computed_value() {
+    fn test_eval_merge_files() {
+        let dir = std::env::temp_dir().join(format!("nickel_jl_merge_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("base.ncl"), r#"{ host = "localhost", port | Number | default = 80 }"#).unwrap();
+        std::fs::write(dir.join("prod.ncl"), "{ port = 8080 }").unwrap();
+        std::fs::write(dir.join("bad.ncl"), r#"{ port = "http" }"#).unwrap();
+        let path = |name: &str| CString::new(dir.join(name).to_str().unwrap()).unwrap();
+
         unsafe {
-            let code = CString::new("let x = 10 in let y = 20 in x + y").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_INT);
-            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
-            assert_eq!(value, 30);
-            nickel_free_buffer(buffer);
+            let files = [path("base.ncl"), path("prod.ncl")];
+            let ptrs: Vec<*const c_char> = files.iter().map(|p| p.as_ptr()).collect();
+            let result = nickel_eval_merge_files(ptrs.as_ptr(), ptrs.len());
+            assert!(!result.is_null(), "Unexpected error: {:?}", CStr::from_ptr(nickel_get_error()).to_str());
+            let json: serde_json::Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            assert_eq!(json, serde_json::json!({ "host": "localhost", "port": 8080 }));
+            nickel_free_string(result);
+
+            // The second file must satisfy the contracts of the first
+            let files = [path("base.ncl"), path("bad.ncl")];
+            let ptrs: Vec<*const c_char> = files.iter().map(|p| p.as_ptr()).collect();
+            assert!(nickel_eval_merge_files(ptrs.as_ptr(), ptrs.len()).is_null());
+
+            assert!(nickel_eval_merge_files(ptr::null(), 0).is_null());
         }
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_native_function_result() {
+    fn test_color_diagnostics() {
         unsafe {
-            let code = CString::new("let double = fun x => x * 2 in double 21").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_INT);
-            let value = i64::from_le_bytes(data[1..9].try_into().unwrap());
-            assert_eq!(value, 42);
-            nickel_free_buffer(buffer);
+            let code = CString::new("{ port | Number = \"http\" }").unwrap();
+            let error = || {
+                assert!(nickel_eval_string(code.as_ptr()).is_null());
+                CStr::from_ptr(nickel_get_error()).to_str().unwrap().to_string()
+            };
+
+            let plain = error();
+            assert!(!plain.contains('\x1b'), "Unexpected escapes: {:?}", plain);
+            assert!(plain.contains("contract broken by the value of `port`"));
+
+            nickel_set_color_diagnostics(true);
+            let colored = error();
+            assert!(colored.contains("\x1b["), "Expected escapes: {:?}", colored);
+
+            nickel_reset();
+            assert_eq!(error(), plain);
+            assert_eq!(uncolored(colored), plain);
         }
     }
 
     #[test]
-    fn test_native_array_operations() {
+    fn test_max_json_bytes() {
         unsafe {
-            // Test array map
-            let code = CString::new("[1, 2, 3] |> std.array.map (fun x => x * 2)").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_ARRAY);
-            let len = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(len, 3);
-            nickel_free_buffer(buffer);
+            let large = CString::new("std.array.generate (fun i => { id = i }) 1000").unwrap();
+            let small = CString::new("{ id = 1 }").unwrap();
+
+            nickel_set_max_json_bytes(100);
+            assert!(nickel_eval_string(large.as_ptr()).is_null());
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert_eq!(err, "JSON result exceeds the maximum of 100 bytes");
+            assert!(nickel_eval_json_compact(large.as_ptr()).is_null());
+            assert!(nickel_eval_json_tagged(large.as_ptr()).is_null());
+            let wrapped = CString::new("{ items = std.array.generate (fun i => { id = i }) 1000 }").unwrap();
+            let field = CString::new("items").unwrap();
+            assert!(nickel_eval_field(wrapped.as_ptr(), field.as_ptr()).is_null());
+            let mut out: *const c_char = ptr::null();
+            let mut stats: *const c_char = ptr::null();
+            assert_eq!(nickel_eval_with_stats(large.as_ptr(), &mut out, &mut stats), -1);
+            assert_eq!(nickel_eval_multi(large.as_ptr(), &mut out), -1);
+            assert!(out.is_null());
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert_eq!(err, "JSON result exceeds the maximum of 100 bytes");
+            assert!(nickel_query(wrapped.as_ptr(), field.as_ptr()).is_null());
+            let previous = CString::new("[]").unwrap();
+            assert!(nickel_eval_diff(large.as_ptr(), previous.as_ptr()).is_null());
+            let err = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert_eq!(err, "JSON result exceeds the maximum of 100 bytes");
+
+            let result = nickel_eval_string(small.as_ptr());
+            assert!(!result.is_null());
+            nickel_free_string(result);
+
+            nickel_reset();
+            let result = nickel_eval_string(large.as_ptr());
+            assert!(!result.is_null());
+            nickel_free_string(result);
         }
     }
 
     #[test]
-    fn test_native_record_merge() {
+    fn test_normalize() {
         unsafe {
-            let code = CString::new("{ a = 1 } & { b = 2 }").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_RECORD);
-            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(field_count, 2);
-            nickel_free_buffer(buffer);
+            let normalize = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let result = nickel_normalize(code.as_ptr());
+                assert!(!result.is_null(), "Unexpected error: {:?}", CStr::from_ptr(nickel_get_error()).to_str());
+                let text = CStr::from_ptr(result).to_str().unwrap().to_string();
+                nickel_free_string(result);
+                text
+            };
+
+            let compact = normalize("{b=[1,2],a|Number=1+1}");
+            let spread = normalize("# settings\n{\n  a\n    | Number\n    = 1 + 1, # two\n  b = [ 1,\n 2 ],\n}\n");
+            assert_eq!(compact, spread);
+            assert!(!compact.contains('#'));
+            assert!(compact.ends_with('\n'));
+            assert_ne!(compact, normalize("{ a = 1 + 2, b = [1, 2] }"));
+
+            let code = CString::new("{ a = ").unwrap();
+            assert!(nickel_normalize(code.as_ptr()).is_null());
         }
     }
 
     #[test]
-    fn test_json_all_types() {
-        // Test JSON serialization for all types
-        assert_eq!(eval_nickel_json("null").unwrap(), "null");
-        assert_eq!(eval_nickel_json("true").unwrap(), "true");
-        assert_eq!(eval_nickel_json("false").unwrap(), "false");
-        assert_eq!(eval_nickel_json("42").unwrap(), "42");
-        assert!(eval_nickel_json("3.14").unwrap().starts_with("3.14"));
-        assert_eq!(eval_nickel_json(r#""hello""#).unwrap(), "\"hello\"");
-        assert!(eval_nickel_json("[]").unwrap().contains("[]") || eval_nickel_json("[]").unwrap().contains("[\n]"));
+    fn test_eval_native_f32() {
+        unsafe {
+            let code = CString::new("[0.1, 2.5, 42]").unwrap();
+            let f64_buffer = nickel_eval_native(code.as_ptr());
+            assert!(!f64_buffer.data.is_null());
+            let f32_buffer = nickel_eval_native_f32(code.as_ptr());
+            assert!(!f32_buffer.data.is_null());
+            // Two decimals, 4 bytes smaller each; the integer is unchanged
+            assert_eq!(f32_buffer.len + 8, f64_buffer.len);
+
+            let bytes = std::slice::from_raw_parts(f32_buffer.data, f32_buffer.len);
+            assert_eq!(&bytes[..5], &[TYPE_ARRAY, 3, 0, 0, 0]);
+            assert_eq!(bytes[5], TYPE_FLOAT32);
+            let value = f32::from_le_bytes(bytes[6..10].try_into().unwrap());
+            assert!((f64::from(value) - 0.1).abs() < f64::from(f32::EPSILON));
+            assert_eq!(bytes[10], 1);
+            assert_eq!(bytes[11], TYPE_FLOAT32);
+            assert_eq!(f32::from_le_bytes(bytes[12..16].try_into().unwrap()), 2.5);
+            assert_eq!(bytes[16], 0);
+            assert_eq!(bytes[17], TYPE_INT);
+            assert_eq!(i64::from_le_bytes(bytes[18..26].try_into().unwrap()), 42);
+
+            nickel_free_buffer(f64_buffer);
+            nickel_free_buffer(f32_buffer);
+            assert_eq!(nickel_type_tag(CString::new("float32").unwrap().as_ptr()), i32::from(TYPE_FLOAT32));
+        }
     }
 
     #[test]
-    fn test_native_simple_enum() {
+    fn test_partial_apply() {
         unsafe {
-            let code = CString::new("let x = 'Foo in x").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            // TYPE_ENUM | tag_len | "Foo" | has_arg=0
-            assert_eq!(data[0], TYPE_ENUM);
-            let tag_len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-            assert_eq!(tag_len, 3); // "Foo"
-            assert_eq!(&data[5..8], b"Foo");
-            assert_eq!(data[8], 0); // no argument
-            nickel_free_buffer(buffer);
+            let func = CString::new("fun x y => x - y").unwrap();
+            let first = CString::new("[5]").unwrap();
+            let handle = nickel_partial_apply(func.as_ptr(), first.as_ptr());
+            assert!(!handle.is_null());
+
+            // Forcing before the last argument fails but keeps the handle usable
+            assert!(nickel_partial_force(handle).is_null());
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("Too few arguments"), "Unexpected error: {}", error);
+
+            let second = CString::new("[2]").unwrap();
+            assert_eq!(nickel_partial_apply_more(handle, second.as_ptr()), 0);
+            let json = nickel_partial_force(handle);
+            assert!(!json.is_null());
+            assert_eq!(CStr::from_ptr(json).to_str().unwrap(), "3");
+            nickel_free_string(json);
+
+            assert_eq!(nickel_partial_apply_more(handle, second.as_ptr()), -1);
+            let error = CStr::from_ptr(nickel_get_error()).to_str().unwrap();
+            assert!(error.contains("takes 2 but 3 were given"), "Unexpected error: {}", error);
+            let json = nickel_partial_force(handle);
+            assert!(!json.is_null());
+            nickel_free_string(json);
+            nickel_partial_free(handle);
+
+            // Earlier applications aren't evaluated again, so their traces appear once
+            let traced = || {
+                let warnings = nickel_get_warnings();
+                !warnings.is_null() && CStr::from_ptr(warnings).to_str().unwrap().contains("applied")
+            };
+            let func = CString::new("fun x => std.trace \"applied\" (fun y => x + y)").unwrap();
+            let handle = nickel_partial_apply(func.as_ptr(), first.as_ptr());
+            assert!(traced());
+            assert_eq!(nickel_partial_apply_more(handle, second.as_ptr()), 0);
+            assert!(!traced());
+            let json = nickel_partial_force(handle);
+            assert_eq!(CStr::from_ptr(json).to_str().unwrap(), "7");
+            nickel_free_string(json);
+            assert!(!traced());
+            nickel_partial_free(handle);
+
+            let not_function = CString::new("42").unwrap();
+            assert!(nickel_partial_apply(not_function.as_ptr(), first.as_ptr()).is_null());
+            nickel_partial_free(ptr::null_mut());
         }
     }
 
     #[test]
-    fn test_native_enum_variant() {
+    fn test_union_convention() {
         unsafe {
-            let code = CString::new("let x = 'Some 42 in x").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            // TYPE_ENUM | tag_len | "Some" | has_arg=1 | TYPE_INT | 42
-            assert_eq!(data[0], TYPE_ENUM);
-            let tag_len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-            assert_eq!(tag_len, 4); // "Some"
-            assert_eq!(&data[5..9], b"Some");
-            assert_eq!(data[9], 1); // has argument
-            assert_eq!(data[10], TYPE_INT);
-            nickel_free_buffer(buffer);
+            let encode = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                bytes
+            };
+
+            let union = "{ tag = 'Circle, value = { radius = 2 } }";
+            assert_eq!(encode(union)[0], TYPE_RECORD);
+
+            nickel_set_union_convention(true);
+            let bytes = encode(union);
+            assert_eq!(bytes, encode("'Circle { radius = 2 }"));
+            assert_eq!(&bytes[..12], &[&[TYPE_ENUM, 6, 0, 0, 0][..], b"Circle", &[1]].concat()[..]);
+            // Only records with exactly an enum `tag` and a `value` are affected
+            assert_eq!(encode("{ tag = 'Circle, value = 1, extra = 2 }")[0], TYPE_RECORD);
+            assert_eq!(encode("{ tag = \"Circle\", value = 1 }")[0], TYPE_RECORD);
+
+            nickel_reset();
+            assert_eq!(encode(union)[0], TYPE_RECORD);
         }
     }
 
     #[test]
-    fn test_native_enum_with_record() {
+    fn test_result_kind() {
         unsafe {
-            let code = CString::new("let x = 'Ok { value = 123 } in x").unwrap();
-            let buffer = nickel_eval_native(code.as_ptr());
-            assert!(!buffer.data.is_null());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            // TYPE_ENUM | tag_len | "Ok" | has_arg=1 | TYPE_RECORD | ...
-            assert_eq!(data[0], TYPE_ENUM);
-            let tag_len = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-            assert_eq!(tag_len, 2); // "Ok"
-            assert_eq!(&data[5..7], b"Ok");
-            assert_eq!(data[7], 1); // has argument
-            assert_eq!(data[8], TYPE_RECORD);
-            nickel_free_buffer(buffer);
+            let kind = |code: &str| nickel_result_kind(CString::new(code).unwrap().as_ptr());
+
+            assert_eq!(kind("1 + 2"), i32::from(TYPE_INT));
+            assert_eq!(kind("0.5"), i32::from(TYPE_FLOAT));
+            assert_eq!(kind("{ a = 1, b = std.fail_with \"not forced\" }"), i32::from(TYPE_RECORD));
+            assert_eq!(kind("[1, 2] @ [3]"), i32::from(TYPE_ARRAY));
+            assert_eq!(kind("{ a = 1 "), -1);
+            assert!(!nickel_get_error().is_null());
+            assert_eq!(nickel_result_kind(ptr::null()), -1);
         }
     }
 
     #[test]
-    fn test_file_eval_native() {
-        use std::fs;
-        use std::io::Write;
-
-        // Create a temp directory with test files
-        let temp_dir = std::env::temp_dir().join("nickel_test");
-        fs::create_dir_all(&temp_dir).unwrap();
+    fn test_register_secret() {
+        unsafe {
+            let code = CString::new("{ token | String = 12345678 }").unwrap();
+            let eval_error = || {
+                assert!(nickel_eval_string(code.as_ptr()).is_null());
+                CStr::from_ptr(nickel_get_error()).to_str().unwrap().to_string()
+            };
+            assert!(eval_error().contains("12345678"));
 
-        // Create a simple file
-        let simple_file = temp_dir.join("simple.ncl");
-        let mut f = fs::File::create(&simple_file).unwrap();
-        writeln!(f, "{{ x = 42 }}").unwrap();
+            let secret = CString::new("12345678").unwrap();
+            assert_eq!(nickel_register_secret(secret.as_ptr()), 0);
+            let error = eval_error();
+            assert!(!error.contains("12345678"), "Secret leaked: {}", error);
+            assert!(error.contains("***"), "Unexpected error: {}", error);
+            let mut message: *const c_char = ptr::null();
+            assert_eq!(nickel_get_contract_failure(ptr::null_mut(), &mut message), 0);
+            assert!(!CStr::from_ptr(message).to_str().unwrap().contains("12345678"));
 
-        unsafe {
-            let path = CString::new(simple_file.to_str().unwrap()).unwrap();
-            let buffer = nickel_eval_file_native(path.as_ptr());
-            assert!(!buffer.data.is_null(), "Expected result, got error: {:?}",
-                CStr::from_ptr(nickel_get_error()).to_str());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            assert_eq!(data[0], TYPE_RECORD);
-            nickel_free_buffer(buffer);
+            nickel_clear_secrets();
+            assert!(eval_error().contains("12345678"));
+            assert_eq!(nickel_register_secret(secret.as_ptr()), 0);
+            nickel_reset();
+            assert!(eval_error().contains("12345678"));
         }
+    }
 
-        // Clean up
-        fs::remove_file(simple_file).unwrap();
+    /// Convert a decoded protobuf `Value` message to JSON, for checking
+    /// `nickel_eval_protobuf` output.
+    fn protobuf_to_json(value: prost_types::Value) -> serde_json::Value {
+        use prost_types::value::Kind;
+
+        match value.kind.expect("Value without a kind") {
+            Kind::NullValue(_) => serde_json::Value::Null,
+            Kind::NumberValue(n) => serde_json::json!(n),
+            Kind::StringValue(s) => serde_json::json!(s),
+            Kind::BoolValue(b) => serde_json::json!(b),
+            Kind::StructValue(s) => {
+                s.fields.into_iter().map(|(k, v)| (k, protobuf_to_json(v))).collect::<serde_json::Map<_, _>>().into()
+            }
+            Kind::ListValue(l) => l.values.into_iter().map(protobuf_to_json).collect(),
+        }
     }
 
     #[test]
-    fn test_file_eval_with_imports() {
-        use std::fs;
-        use std::io::Write;
+    fn test_eval_protobuf() {
+        unsafe {
+            let eval = |code: &str| {
+                let code = CString::new(code).unwrap();
+                let mut buffer = NativeBuffer { data: ptr::null_mut(), len: 0 };
+                if nickel_eval_protobuf(code.as_ptr(), &mut buffer) != 0 {
+                    assert!(buffer.data.is_null());
+                    return Err(CStr::from_ptr(nickel_get_error()).to_str().unwrap().to_string());
+                }
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len);
+                let value = protobuf_to_json(prost::Message::decode(bytes).unwrap());
+                nickel_free_buffer(buffer);
+                Ok(value)
+            };
 
-        // Create a temp directory with test files
-        let temp_dir = std::env::temp_dir().join("nickel_import_test");
-        fs::create_dir_all(&temp_dir).unwrap();
+            let value = eval(r#"{
+                server = { host = "localhost", ports = [80, 443], tls = true },
+                ratio = 0.5,
+                backup = null,
+                mode = 'Fast,
+                level = 'Custom 3,
+            }"#).unwrap();
+            assert_eq!(value["server"]["host"], "localhost");
+            assert_eq!(value["server"]["ports"], serde_json::json!([80.0, 443.0]));
+            assert_eq!(value["server"]["tls"], true);
+            assert_eq!(value["ratio"], 0.5);
+            assert!(value["backup"].is_null());
+            assert_eq!(value["mode"], serde_json::json!({ "tag": "Fast" }));
+            assert_eq!(value["level"], serde_json::json!({ "tag": "Custom", "arg": 3.0 }));
 
-        // Create shared.ncl
-        let shared_file = temp_dir.join("shared.ncl");
-        let mut f = fs::File::create(&shared_file).unwrap();
-        writeln!(f, "{{ name = \"test\", value = 42 }}").unwrap();
+            // A string longer than 127 bytes needs a two-byte length
+            let long = "x".repeat(200);
+            assert_eq!(eval(&format!("\"{}\"", long)).unwrap(), long.as_str());
 
-        // Create main.ncl that imports shared.ncl
-        let main_file = temp_dir.join("main.ncl");
-        let mut f = fs::File::create(&main_file).unwrap();
-        writeln!(f, "let shared = import \"shared.ncl\" in").unwrap();
-        writeln!(f, "{{ imported_name = shared.name, extra = \"added\" }}").unwrap();
+            let error = eval("{ f = fun x => x }").unwrap_err();
+            assert!(error.contains("Cannot encode a function at `f` as protobuf"), "Unexpected error: {}", error);
+        }
+    }
 
+    #[test]
+    fn test_always_float() {
         unsafe {
-            let path = CString::new(main_file.to_str().unwrap()).unwrap();
-            let buffer = nickel_eval_file_native(path.as_ptr());
-            assert!(!buffer.data.is_null(), "Expected result, got error: {:?}",
-                CStr::from_ptr(nickel_get_error()).to_str());
-            let data = std::slice::from_raw_parts(buffer.data, buffer.len);
-            // Should be a record with two fields
-            assert_eq!(data[0], TYPE_RECORD);
-            let field_count = u32::from_le_bytes(data[1..5].try_into().unwrap());
-            assert_eq!(field_count, 2);
+            let code = CString::new("42").unwrap();
+            let encode = || {
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+                nickel_free_buffer(buffer);
+                bytes
+            };
+            assert_eq!(encode()[0], TYPE_INT);
+
+            nickel_set_always_float(true);
+            let bytes = encode();
+            assert_eq!(bytes[0], TYPE_FLOAT);
+            assert_eq!(f64::from_le_bytes(bytes[1..9].try_into().unwrap()), 42.0);
+            assert_eq!(bytes[9], 0);
+
+            // Integer arrays aren't packed either
+            let array = CString::new("[1, 2]").unwrap();
+            let buffer = nickel_eval_native_packed(array.as_ptr());
+            assert!(!buffer.data.is_null());
+            let bytes = std::slice::from_raw_parts(buffer.data, buffer.len);
+            assert_eq!(&bytes[..7], &[PROTOCOL_PACKED, TYPE_ARRAY, 2, 0, 0, 0, TYPE_FLOAT]);
             nickel_free_buffer(buffer);
-        }
 
-        // Clean up
-        fs::remove_file(main_file).unwrap();
-        fs::remove_file(shared_file).unwrap();
-        fs::remove_dir(temp_dir).unwrap();
+            nickel_reset();
+            assert_eq!(encode()[0], TYPE_INT);
+        }
     }
 
     #[test]
-    fn test_file_eval_not_found() {
+    fn test_native_date_field_fallback() {
         unsafe {
-            let path = CString::new("/nonexistent/path/file.ncl").unwrap();
-            let buffer = nickel_eval_file_native(path.as_ptr());
-            assert!(buffer.data.is_null());
-            let error = nickel_get_error();
-            assert!(!error.is_null());
+            let tag_of = |value: &str| {
+                let code = CString::new(format!(
+                    "let Date = std.contract.from_predicate std.is_string in {{ created | Date = \"{}\" }}",
+                    value
+                )).unwrap();
+                let buffer = nickel_eval_native(code.as_ptr());
+                assert!(!buffer.data.is_null());
+                let tag = std::slice::from_raw_parts(buffer.data, buffer.len)[16];
+                nickel_free_buffer(buffer);
+                tag
+            };
+
+            assert_eq!(tag_of("2024-01-15"), TYPE_DATE);
+            assert_eq!(tag_of("2024-01-15T10:30"), TYPE_DATE);
+            assert_eq!(tag_of("2024-02-29T10:30:00.123"), TYPE_DATE);
+            // Forms `DateTime` can't read stay strings instead of failing to decode
+            assert_eq!(tag_of("2024-01-15T10:30:00Z"), TYPE_STRING);
+            assert_eq!(tag_of("2024-01-15T10:30:00+02:00"), TYPE_STRING);
+            assert_eq!(tag_of("2024-01-15T10:30:00.123456"), TYPE_STRING);
+            assert_eq!(tag_of("2023-02-29"), TYPE_STRING);
+            assert_eq!(tag_of("yesterday"), TYPE_STRING);
         }
     }
 }
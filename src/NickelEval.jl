@@ -6,7 +6,7 @@ export nickel_eval, nickel_eval_file, nickel_export, nickel_read, @ncl_str, Nick
 export nickel_to_json, nickel_to_toml, nickel_to_yaml
 export check_ffi_available, nickel_eval_ffi, nickel_eval_native, nickel_eval_file_native
 export find_nickel_executable
-export NickelEnum
+export NickelEnum, NickelFunction
 
 """
     NickelError <: Exception
@@ -72,6 +72,40 @@ function Base.show(io::IO, e::NickelEnum)
     end
 end
 
+"""
+    NickelFunction
+
+Describes a Nickel function returned by the FFI. Functions can't be converted to
+Julia values, so only their shape is reported.
+
+# Fields
+- `arity::Int`: The number of arguments the function takes
+- `signature::Union{String, Nothing}`: The function type or contract the value is
+  annotated with, or `nothing` if it has none
+
+# Examples
+```julia
+result = nickel_eval_native("fun x y => x + y")
+result.arity      # => 2
+result.signature  # => nothing
+
+result = nickel_eval_native("{ inc | Number -> Number = fun x => x + 1 }")
+result["inc"].signature  # => "Number -> Number"
+```
+"""
+struct NickelFunction
+    arity::Int
+    signature::Union{String, Nothing}
+end
+
+function Base.show(io::IO, f::NickelFunction)
+    if f.signature === nothing
+        print(io, "<function/", f.arity, ">")
+    else
+        print(io, "<function/", f.arity, " : ", f.signature, ">")
+    end
+end
+
 include("subprocess.jl")
 include("ffi.jl")
 
@@ -13,6 +13,7 @@
 #   - Better performance for repeated evaluations
 
 using Artifacts
+using Dates
 using LazyArtifacts
 
 # Determine platform-specific library name
@@ -64,6 +65,12 @@ const TYPE_STRING = 0x04
 const TYPE_ARRAY  = 0x05
 const TYPE_RECORD = 0x06
 const TYPE_ENUM   = 0x07
+const TYPE_DATE   = 0x08
+const TYPE_MISSING = 0x09
+const TYPE_FUNCTION = 0x0a
+const TYPE_KEYED_RECORD = 0x0c
+const TYPE_QUANTITY = 0x0d
+const TYPE_UNSUPPORTED = 0x0e
 
 # C struct for native buffer (must match Rust NativeBuffer)
 struct NativeBuffer
@@ -140,12 +147,14 @@ Evaluate Nickel code using native FFI with binary protocol.
 Returns Julia native types directly from Nickel's type system:
 
 - Nickel `Number` (integer) → `Int64`
-- Nickel `Number` (decimal) → `Float64`
+- Nickel `Number` (decimal, or integer outside the `Int64` range) → `Float64`
 - Nickel `String` → `String`
 - Nickel `Bool` → `Bool`
 - Nickel `null` → `nothing`
 - Nickel `Array` → `Vector{Any}`
 - Nickel `Record` → `Dict{String, Any}`
+- Nickel `String` field annotated with a `Date` contract → `DateTime`
+- Nickel function → `NickelFunction` (arity and signature only)
 
 This preserves type information that would be lost through JSON serialization.
 
@@ -249,7 +258,9 @@ function _decode_value(io::IOBuffer)
     elseif tag == TYPE_INT
         return ltoh(read(io, Int64))  # little-endian to host
     elseif tag == TYPE_FLOAT
-        return ltoh(read(io, Float64))
+        value = ltoh(read(io, Float64))
+        read(io, UInt8)  # lossy flag: set when the Nickel number was rounded
+        return value
     elseif tag == TYPE_STRING
         len = ltoh(read(io, UInt32))
         bytes = read(io, len)
@@ -266,6 +277,23 @@ function _decode_value(io::IOBuffer)
             dict[key] = _decode_value(io)
         end
         return dict
+    elseif tag == TYPE_KEYED_RECORD
+        # Record with all-digit keys, see `nickel_set_numeric_keys`
+        # Format: count (u32) | (key_type (u8) | key | value)*, where the key is an
+        # Int64 after TYPE_INT or a length-prefixed string after TYPE_STRING
+        len = ltoh(read(io, UInt32))
+        dict = Dict{Union{Int64, String}, Any}()
+        for _ in 1:len
+            key_type = read(io, UInt8)
+            if key_type == TYPE_INT
+                key = ltoh(read(io, Int64))
+            else
+                key_len = ltoh(read(io, UInt32))
+                key = String(read(io, key_len))
+            end
+            dict[key] = _decode_value(io)
+        end
+        return dict
     elseif tag == TYPE_ENUM
         # Format: tag_len (u32) | tag_bytes | has_arg (u8) | [arg_value]
         tag_len = ltoh(read(io, UInt32))
@@ -273,6 +301,33 @@ function _decode_value(io::IOBuffer)
         has_arg = read(io, UInt8) != 0x00
         arg = has_arg ? _decode_value(io) : nothing
         return NickelEnum(tag_name, arg)
+    elseif tag == TYPE_DATE
+        # String field annotated with a `Date` contract, ISO-8601 encoded
+        len = ltoh(read(io, UInt32))
+        text = String(read(io, len))
+        # Keep the string if `DateTime` can't read it after all
+        date = tryparse(DateTime, text)
+        return date === nothing ? text : date
+    elseif tag == TYPE_QUANTITY
+        # String field annotated with a `Duration` or `ByteSize` contract
+        # Format: magnitude (f64) | unit_len (u32) | unit_bytes ("s" or "B")
+        value = ltoh(read(io, Float64))
+        unit_len = ltoh(read(io, UInt32))
+        return (value = value, unit = String(read(io, unit_len)))
+    elseif tag == TYPE_MISSING
+        # Record field declared without a definition, e.g. `{ x | Number }`
+        return missing
+    elseif tag == TYPE_FUNCTION
+        # Format: arity (u32) | signature_len (u32) | signature_bytes (empty if unknown)
+        arity = Int(ltoh(read(io, UInt32)))
+        sig_len = ltoh(read(io, UInt32))
+        signature = String(read(io, sig_len))
+        return NickelFunction(arity, isempty(signature) ? nothing : signature)
+    elseif tag == TYPE_UNSUPPORTED
+        # Placeholder for a value without a native form, see `nickel_set_encode_mode`
+        # Format: description_len (u32) | description_bytes, e.g. "a type"
+        len = ltoh(read(io, UInt32))
+        return (unsupported = String(read(io, len)),)
     else
         error("Unknown type tag in binary protocol: $tag")
     end
@@ -1,4 +1,5 @@
 using NickelEval
+using Dates
 using Test
 
 # Check if Nickel CLI is available
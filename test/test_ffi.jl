@@ -12,6 +12,10 @@
         @test nickel_eval_native("0.5") ≈ 0.5
         @test typeof(nickel_eval_native("3.14")) == Float64
 
+        # Integers beyond Int64 fall back to Float64
+        @test nickel_eval_native("std.number.pow 10 20") === 1.0e20
+        @test nickel_eval_native("[0.1, 2]") == Any[0.1, 2]
+
         # Booleans
         @test nickel_eval_native("true") === true
         @test nickel_eval_native("false") === false
@@ -336,6 +340,83 @@
         @test result["data"][1]["a"] === Int64(1)
         @test result["data"][2]["b"] == Any[true, false]
     end
+
+    @testset "Date-annotated fields" begin
+        code = """
+        let Date = std.contract.from_predicate std.is_string in
+        { created | Date = "2024-01-15T10:30:00", label = "2024-01-15" }
+        """
+        result = nickel_eval_native(code)
+        @test result["created"] == DateTime(2024, 1, 15, 10, 30, 0)
+        @test result["label"] == "2024-01-15"
+
+        # Time zones can't be represented by `DateTime`, so those stay strings
+        code = """
+        let Date = std.contract.from_predicate std.is_string in
+        { utc | Date = "2024-01-15T10:30:00Z", offset | Date = "2024-01-15T10:30:00+02:00" }
+        """
+        result = nickel_eval_native(code)
+        @test result["utc"] == "2024-01-15T10:30:00Z"
+        @test result["offset"] == "2024-01-15T10:30:00+02:00"
+    end
+
+    @testset "Duration and ByteSize fields" begin
+        code = """
+        let Duration = std.contract.from_predicate std.is_string in
+        let ByteSize = std.contract.from_predicate std.is_string in
+        { timeout | Duration = "1.5 min", memory | ByteSize = "4GiB", iso | Duration = "PT30S" }
+        """
+        result = nickel_eval_native(code)
+        @test result["timeout"] == (value = 90.0, unit = "s")
+        @test result["memory"] == (value = 4294967296.0, unit = "B")
+        # Strings that don't parse as a quantity stay strings
+        @test result["iso"] == "PT30S"
+    end
+
+    @testset "Functions" begin
+        result = nickel_eval_native("fun x y => x + y")
+        @test result isa NickelFunction
+        @test result.arity == 2
+        @test result.signature === nothing
+
+        result = nickel_eval_native("{ inc | Number -> Number = fun x => x + 1 }")
+        @test result["inc"] == NickelFunction(1, "Number -> Number")
+    end
+
+    @testset "Missing vs null fields" begin
+        @test nickel_eval_native("{ x = null }")["x"] === nothing
+        @test_throws NickelError nickel_eval_native("{ x | Number }")
+
+        # Only produced for records that aren't fully evaluated
+        data = UInt8[0x06, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, UInt8('x'), 0x09]
+        @test NickelEval._decode_native(data)["x"] === missing
+    end
+
+    @testset "Unsupported value placeholders" begin
+        code = "{ schema = Number, values = [1, String] }"
+        @test_throws NickelError nickel_eval_native(code)
+        @test ccall((:nickel_set_encode_mode, NickelEval.LIB_PATH), Cint, (UInt8,), 1) == 0
+        try
+            result = nickel_eval_native(code)
+            @test result["schema"] == (unsupported = "a type",)
+            @test result["values"] == Any[1, (unsupported = "a type",)]
+        finally
+            ccall((:nickel_set_encode_mode, NickelEval.LIB_PATH), Cint, (UInt8,), 0)
+        end
+    end
+
+    @testset "Numeric record keys" begin
+        ccall((:nickel_set_numeric_keys, NickelEval.LIB_PATH), Cvoid, (Bool,), true)
+        try
+            result = nickel_eval_native("{ \"1\" = \"one\", \"20\" = 20, name = \"x\" }")
+            @test result[1] == "one"
+            @test result[20] === Int64(20)
+            @test result["name"] == "x"
+        finally
+            ccall((:nickel_set_numeric_keys, NickelEval.LIB_PATH), Cvoid, (Bool,), false)
+        end
+        @test nickel_eval_native("{ \"1\" = 1 }") == Dict{String, Any}("1" => 1)
+    end
 end
 
 @testset "FFI JSON Evaluation" begin